@@ -4,6 +4,18 @@ use eyre::{eyre, Result, WrapErr};
 use serde::Deserialize;
 use structopt::StructOpt;
 
+use crate::{
+    epub::EpubConfig,
+    feed::FeedConfig,
+    journal_archive::JournalArchiveConfig,
+    mentions::UnlinkedMentionsMode,
+    output_plan::{DryRunOptions, StaleOutputs},
+    redirect::SlugRedirects,
+    search::SearchIndexConfig,
+    serve::ServeOptions,
+    tag_index::TagPagesConfig,
+};
+
 #[derive(Debug, Default, StructOpt)]
 struct CmdlineConfig {
     #[structopt(
@@ -42,6 +54,48 @@ struct CmdlineConfig {
         help = "Write files so that there is no time when the contents are partially written."
     )]
     pub safe_write: bool,
+
+    #[structopt(
+        long,
+        help = "After exporting, keep watching the graph, script, and templates, and re-export pages when they change."
+    )]
+    pub watch: bool,
+
+    #[structopt(
+        long,
+        help = "Fail the export if any links, embeds, block references, or images could not be resolved. They are always listed in link-report.json."
+    )]
+    pub strict: bool,
+
+    #[structopt(
+        long,
+        help = "Render everything and print the files that would be created, updated, or removed, without writing anything or uploading images."
+    )]
+    pub dry_run: bool,
+
+    #[structopt(
+        long,
+        requires = "dry-run",
+        help = "With --dry-run, also print a diff of each file that would be updated."
+    )]
+    pub diff: bool,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    #[structopt(
+        about = "Export, then serve the output directory on localhost. With a Logseq graph, pages are re-exported when they change and open browser tabs reload."
+    )]
+    Serve {
+        #[structopt(long, default_value = "127.0.0.1", help = "The address to listen on")]
+        host: String,
+
+        #[structopt(long, default_value = "8080", help = "The port to listen on")]
+        port: u16,
+    },
 }
 
 #[derive(Debug, Deserialize)]
@@ -133,6 +187,69 @@ struct FileConfig {
 
     /// Configuration for a Pic Store instance, to upload local images to the web.
     pub pic_store: Option<PicStoreConfig>,
+
+    /// Find plain-text mentions of page titles and aliases. "detect" passes them to the
+    /// script and templates, and "autolink" also renders them as links to exported pages.
+    pub unlinked_mentions: Option<UnlinkedMentionsMode>,
+
+    /// Write a `search-index.json` file next to the manifest, for searching the exported pages.
+    pub search_index: Option<SearchIndexConfig>,
+
+    /// The maximum length, in characters, of the `excerpt` passed to templates. Defaults to 200.
+    pub excerpt_length: Option<usize>,
+
+    /// Render page bodies as "html" or "markdown". Defaults to "html".
+    pub output_format: Option<OutputFormat>,
+
+    /// Also package the exported pages into an EPUB file.
+    pub epub: Option<EpubConfig>,
+
+    /// The scheme and host that the output is published at, like "https://example.com", for
+    /// making absolute URLs.
+    pub site_origin: Option<String>,
+
+    /// Write an Atom feed, and optionally an RSS feed, of the most recently edited pages.
+    pub feed: Option<FeedConfig>,
+
+    /// Write `sitemap.xml` next to the manifest. Defaults to false.
+    pub sitemap: Option<bool>,
+
+    /// Generate a page for each tag listing the pages with that tag, and an overview of the tags.
+    pub tag_pages: Option<TagPagesConfig>,
+
+    /// Generate archive pages of the journals by year and month, and link each journal page to
+    /// the ones before and after it.
+    pub journal_archive: Option<JournalArchiveConfig>,
+
+    /// Write namespaced pages into nested directories, with an index page for each namespace
+    /// that doesn't have a page of its own.
+    pub namespace_dirs: Option<bool>,
+
+    /// Write a page at each alias's URL that redirects to the page declaring the alias.
+    pub alias_redirects: Option<bool>,
+
+    /// Remember each page's slug, and when it changes, redirect from the old URL with "html"
+    /// redirect pages, a "netlify" `_redirects` file, or an "nginx" map file.
+    pub slug_redirects: Option<SlugRedirects>,
+
+    /// What to do with files written by the previous export for pages that are no longer
+    /// exported: "keep", "delete", or "move" them into `stale_output_dir`. Defaults to "keep".
+    pub stale_outputs: Option<StaleOutputs>,
+
+    /// Where `stale_outputs = "move"` puts the files. Defaults to the output directory's name with
+    /// `-stale` added, next to the output directory.
+    pub stale_output_dir: Option<PathBuf>,
+}
+
+/// The format of the rendered page bodies.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Html,
+    /// CommonMark, with the GitHub Flavored Markdown extensions for tables, strikethrough, and
+    /// task lists.
+    Markdown,
 }
 
 #[derive(Debug, Deserialize)]
@@ -140,6 +257,11 @@ struct FileConfig {
 pub enum PkmProduct {
     Roam,
     Logseq,
+    /// The JSON or EDN file from Logseq's "Export graph" menu
+    #[serde(rename = "logseq-export")]
+    LogseqExport,
+    /// A directory containing an Obsidian vault
+    Obsidian,
 }
 
 impl Default for PkmProduct {
@@ -155,7 +277,11 @@ impl FromStr for PkmProduct {
         match s {
             "roam" => Ok(Self::Roam),
             "logseq" => Ok(Self::Logseq),
-            _ => Err(eyre!("Supported products are roam, logseq")),
+            "logseq-export" => Ok(Self::LogseqExport),
+            "obsidian" => Ok(Self::Obsidian),
+            _ => Err(eyre!(
+                "Supported products are roam, logseq, logseq-export, obsidian"
+            )),
         }
     }
 }
@@ -197,6 +323,38 @@ pub struct Config {
     pub top_header_level: usize,
 
     pub pic_store: Option<PicStoreConfig>,
+
+    pub unlinked_mentions: Option<UnlinkedMentionsMode>,
+
+    pub search_index: Option<SearchIndexConfig>,
+
+    pub excerpt_length: usize,
+
+    pub output_format: OutputFormat,
+
+    pub epub: Option<EpubConfig>,
+
+    pub site_origin: Option<String>,
+    pub feed: Option<FeedConfig>,
+    pub sitemap: bool,
+    pub tag_pages: Option<TagPagesConfig>,
+    pub journal_archive: Option<JournalArchiveConfig>,
+
+    pub namespace_dirs: bool,
+    pub alias_redirects: bool,
+    pub slug_redirects: Option<SlugRedirects>,
+
+    pub stale_outputs: StaleOutputs,
+    pub stale_output_dir: PathBuf,
+
+    /// Keep running and re-export pages when the graph, script, or templates change.
+    pub watch: bool,
+    /// Fail the export when the link report has any entries.
+    pub strict: bool,
+    /// Render without writing anything, and print what would change.
+    pub dry_run: Option<DryRunOptions>,
+    /// Serve the output directory over HTTP after exporting.
+    pub serve: Option<ServeOptions>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -239,8 +397,17 @@ impl Config {
         )
         .context("Failed to open config file")?;
 
-        let mut file_cfg: FileConfig = toml::from_str(&config_file)?;
+        let file_cfg: FileConfig = toml::from_str(&config_file)?;
+        Self::from_parts(cmdline_cfg, file_cfg)
+    }
+
+    /// Build the configuration from a config file's contents, for tests.
+    #[cfg(test)]
+    pub fn from_toml(config_file: &str) -> Result<Config> {
+        Self::from_parts(CmdlineConfig::default(), toml::from_str(config_file)?)
+    }
 
+    fn from_parts(cmdline_cfg: CmdlineConfig, mut file_cfg: FileConfig) -> Result<Config> {
         if let Some(pc) = file_cfg.pic_store.as_mut() {
             if pc.api_key.is_none() {
                 let key = match std::env::var("PIC_STORE_KEY") {
@@ -256,10 +423,39 @@ impl Config {
             }
         }
 
+        let track_logseq_timestamps = file_cfg.track_logseq_timestamps.unwrap_or(true);
+        if file_cfg.slug_redirects.is_some() && !track_logseq_timestamps {
+            return Err(eyre!(
+                "slug_redirects saves the slugs in the timestamps database, so it needs track_logseq_timestamps"
+            ));
+        }
+
+        if file_cfg.feed.is_some() && file_cfg.site_origin.is_none() {
+            return Err(eyre!("The feed needs site_origin to make absolute URLs"));
+        }
+
+        let sitemap = file_cfg.sitemap.unwrap_or_default();
+        if sitemap && file_cfg.site_origin.is_none() {
+            return Err(eyre!("The sitemap needs site_origin to make absolute URLs"));
+        }
+
+        if cmdline_cfg.dry_run && (cmdline_cfg.watch || cmdline_cfg.command.is_some()) {
+            return Err(eyre!("--dry-run can't be combined with --watch or serve"));
+        }
+
+        let output = merge_required("output", cmdline_cfg.output, file_cfg.output)?;
+        let stale_output_dir = file_cfg.stale_output_dir.unwrap_or_else(|| {
+            let name = output
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| String::from("output"));
+            output.with_file_name(format!("{name}-stale"))
+        });
+
         let mut cfg = Config {
             path: merge_required("data", cmdline_cfg.data, file_cfg.data)?,
-            track_logseq_timestamps: file_cfg.track_logseq_timestamps.unwrap_or(true),
-            output: merge_required("output", cmdline_cfg.output, file_cfg.output)?,
+            track_logseq_timestamps,
+            output,
             script: file_cfg.script,
             product: merge_default(cmdline_cfg.product, file_cfg.product),
             safe_write: cmdline_cfg.safe_write || file_cfg.safe_write.unwrap_or(false),
@@ -290,6 +486,29 @@ impl Config {
             promote_headers: file_cfg.promote_headers.unwrap_or_default(),
             top_header_level: file_cfg.top_header_level.unwrap_or(1),
             pic_store: file_cfg.pic_store,
+            unlinked_mentions: file_cfg.unlinked_mentions,
+            search_index: file_cfg.search_index,
+            excerpt_length: file_cfg.excerpt_length.unwrap_or(200),
+            output_format: file_cfg.output_format.unwrap_or_default(),
+            epub: file_cfg.epub,
+            site_origin: file_cfg.site_origin,
+            feed: file_cfg.feed,
+            sitemap,
+            tag_pages: file_cfg.tag_pages,
+            journal_archive: file_cfg.journal_archive,
+            namespace_dirs: file_cfg.namespace_dirs.unwrap_or_default(),
+            alias_redirects: file_cfg.alias_redirects.unwrap_or_default(),
+            slug_redirects: file_cfg.slug_redirects,
+            stale_outputs: file_cfg.stale_outputs.unwrap_or_default(),
+            stale_output_dir,
+            watch: cmdline_cfg.watch,
+            strict: cmdline_cfg.strict,
+            dry_run: cmdline_cfg.dry_run.then_some(DryRunOptions {
+                diff: cmdline_cfg.diff,
+            }),
+            serve: cmdline_cfg.command.map(|command| match command {
+                Command::Serve { host, port } => ServeOptions { host, port },
+            }),
         };
 
         // Make sure base url starts and ends with a slash
@@ -0,0 +1,196 @@
+//! A line-based unified diff, for showing how a dry run would change the existing output.
+
+use std::fmt::Write;
+
+/// Lines of unchanged text to show around each change.
+const CONTEXT: usize = 3;
+
+/// Above this many cells in the comparison table, the differing middle of the files is shown as
+/// entirely removed and re-added instead of finding the smallest diff.
+const MAX_TABLE_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Find the edits that turn `old` into `new`, using the longest common subsequence of lines.
+fn diff_ops(old: &[&str], new: &[&str]) -> Vec<Op> {
+    // Most changes to a page are small, so skip past the common start and end before building
+    // the table.
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut ops = vec![Op::Equal; prefix];
+
+    if old_mid.len() * new_mid.len() > MAX_TABLE_SIZE {
+        ops.resize(ops.len() + old_mid.len(), Op::Delete);
+        ops.resize(ops.len() + new_mid.len(), Op::Insert);
+    } else {
+        // lengths[i * width + j] is the length of the longest common subsequence of
+        // old_mid[i..] and new_mid[j..].
+        let width = new_mid.len() + 1;
+        let mut lengths = vec![0u32; (old_mid.len() + 1) * width];
+        for i in (0..old_mid.len()).rev() {
+            for j in (0..new_mid.len()).rev() {
+                lengths[i * width + j] = if old_mid[i] == new_mid[j] {
+                    lengths[(i + 1) * width + j + 1] + 1
+                } else {
+                    lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < old_mid.len() && j < new_mid.len() {
+            if old_mid[i] == new_mid[j] {
+                ops.push(Op::Equal);
+                i += 1;
+                j += 1;
+            } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+                ops.push(Op::Delete);
+                i += 1;
+            } else {
+                ops.push(Op::Insert);
+                j += 1;
+            }
+        }
+
+        ops.resize(ops.len() + old_mid.len() - i, Op::Delete);
+        ops.resize(ops.len() + new_mid.len() - j, Op::Insert);
+    }
+
+    ops.resize(ops.len() + suffix, Op::Equal);
+    ops
+}
+
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+/// Compare two versions of a file in the unified diff format. Returns an empty string if they
+/// have the same lines.
+pub fn unified_diff(name: &str, old: &str, new: &str) -> String {
+    let old_lines = old.lines().collect::<Vec<_>>();
+    let new_lines = new.lines().collect::<Vec<_>>();
+    let ops = diff_ops(&old_lines, &new_lines);
+
+    // The line in each file where each op starts.
+    let mut positions = Vec::with_capacity(ops.len());
+    let (mut old_pos, mut new_pos) = (0, 0);
+    for op in &ops {
+        positions.push((old_pos, new_pos));
+        match op {
+            Op::Equal => {
+                old_pos += 1;
+                new_pos += 1;
+            }
+            Op::Delete => old_pos += 1,
+            Op::Insert => new_pos += 1,
+        }
+    }
+
+    // Group the changes into hunks, merging them when their context would overlap.
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (i, _) in ops.iter().enumerate().filter(|(_, op)| **op != Op::Equal) {
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + CONTEXT + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    if hunks.is_empty() {
+        return String::new();
+    }
+
+    let mut output = format!("--- {name}\n+++ {name}\n");
+    for (start, end) in hunks {
+        let ops = &ops[start..end];
+        let (old_start, new_start) = positions[start];
+        let old_len = ops.iter().filter(|op| **op != Op::Insert).count();
+        let new_len = ops.iter().filter(|op| **op != Op::Delete).count();
+        writeln!(
+            output,
+            "@@ -{} +{} @@",
+            hunk_range(old_start, old_len),
+            hunk_range(new_start, new_len)
+        )
+        .unwrap();
+
+        for (op, (old_pos, new_pos)) in ops.iter().zip(&positions[start..end]) {
+            let (prefix, line) = match op {
+                Op::Equal => (' ', old_lines[*old_pos]),
+                Op::Delete => ('-', old_lines[*old_pos]),
+                Op::Insert => ('+', new_lines[*new_pos]),
+            };
+            writeln!(output, "{prefix}{line}").unwrap();
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+
+    #[test]
+    fn same_contents() {
+        assert_eq!(unified_diff("a.html", "one\ntwo\n", "one\ntwo\n"), "");
+    }
+
+    #[test]
+    fn changes_with_context() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n";
+        assert_eq!(
+            unified_diff("a.html", old, new),
+            indoc! {"
+                --- a.html
+                +++ a.html
+                @@ -1,6 +1,6 @@
+                 1
+                 2
+                -3
+                +three
+                 4
+                 5
+                 6
+                @@ -10,3 +10,4 @@
+                 10
+                 11
+                 12
+                +13
+            "}
+        );
+    }
+
+    #[test]
+    fn new_and_empty_files() {
+        assert_eq!(
+            unified_diff("a.html", "", "one\n"),
+            "--- a.html\n+++ a.html\n@@ -0,0 +1 @@\n+one\n"
+        );
+        assert_eq!(
+            unified_diff("a.html", "one\ntwo\n", ""),
+            "--- a.html\n+++ a.html\n@@ -1,2 +0,0 @@\n-one\n-two\n"
+        );
+    }
+}
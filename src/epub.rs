@@ -0,0 +1,422 @@
+use std::{
+    borrow::Cow,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use ahash::HashMap;
+use eyre::{Result, WrapErr};
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::{html, image::image_full_path};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct EpubConfig {
+    /// The EPUB file to write.
+    pub path: PathBuf,
+    /// The title of the book. Defaults to the name of the EPUB file.
+    pub title: Option<String>,
+    pub author: Option<String>,
+    /// The language of the book. Defaults to "en".
+    pub language: Option<String>,
+}
+
+/// A rendered page to include in the book.
+pub struct EpubChapter {
+    pub title: String,
+    pub slug: String,
+    /// The page's source file, for resolving relative image paths
+    pub path: PathBuf,
+    /// The rendered HTML of the page
+    pub body: String,
+}
+
+struct BookImage {
+    href: String,
+    media_type: &'static str,
+    data: Vec<u8>,
+}
+
+fn image_media_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    let media_type = match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        _ => return None,
+    };
+
+    Some(media_type)
+}
+
+fn chapter_filename(index: usize) -> String {
+    format!("page-{:04}.xhtml", index + 1)
+}
+
+/// Point links to other pages in the book at their chapter files.
+fn rewrite_links<'a>(body: &'a str, chapters_by_slug: &HashMap<String, String>) -> Cow<'a, str> {
+    static HREF: Lazy<Regex> = Lazy::new(|| Regex::new(r##"href="([^"#]*)(#[^"]*)?""##).unwrap());
+
+    HREF.replace_all(body, |caps: &Captures| {
+        match chapters_by_slug.get(&caps[1]) {
+            Some(filename) => format!(
+                r##"href="{filename}{anchor}""##,
+                anchor = caps.get(2).map(|m| m.as_str()).unwrap_or_default()
+            ),
+            None => caps[0].to_string(),
+        }
+    })
+}
+
+/// Copy local images into the book and point the image tags at the copies.
+fn embed_images<'a>(
+    body: &'a str,
+    base_dir: &Path,
+    chapter_path: &Path,
+    images: &mut HashMap<PathBuf, BookImage>,
+) -> Cow<'a, str> {
+    static IMG_SRC: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"(<img\s[^>]*?src=")([^"]*)""#).unwrap());
+
+    IMG_SRC.replace_all(body, |caps: &Captures| {
+        let src = caps[2].replace("&amp;", "&");
+        let Some(path) = image_full_path(base_dir, chapter_path, &src) else {
+            return caps[0].to_string();
+        };
+
+        if !images.contains_key(&path) {
+            let Some(media_type) = image_media_type(&path) else {
+                return caps[0].to_string();
+            };
+
+            let data = match std::fs::read(base_dir.join(&path)) {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("Leaving image {} out of the EPUB: {e}", path.display());
+                    return caps[0].to_string();
+                }
+            };
+
+            let extension = path.extension().unwrap_or_default().to_string_lossy();
+            let href = format!("images/image-{:04}.{extension}", images.len() + 1);
+            images.insert(
+                path.clone(),
+                BookImage {
+                    href,
+                    media_type,
+                    data,
+                },
+            );
+        }
+
+        format!(r##"{}{}""##, &caps[1], images[&path].href)
+    })
+}
+
+/// Adjust the HTML output so that it is also valid XHTML.
+fn to_xhtml(body: &str) -> String {
+    static VOID_ELEMENT: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"<(br|hr|img|input|source|wbr)(\s[^>]*)?>"#).unwrap());
+
+    let body = body
+        .replace("&mdash;", "&#8212;")
+        .replace("&nbsp;", "&#160;");
+    VOID_ELEMENT
+        .replace_all(&body, |caps: &Captures| {
+            let attrs = caps.get(2).map(|m| m.as_str()).unwrap_or_default();
+            let attrs = attrs.trim_end().trim_end_matches('/');
+            // XHTML doesn't allow attributes without values.
+            let attrs = attrs
+                .split(' ')
+                .filter(|attr| !attr.is_empty())
+                .map(|attr| match attr {
+                    "disabled" | "checked" => format!(r#"{attr}="{attr}""#),
+                    _ => attr.to_string(),
+                })
+                .join(" ");
+
+            if attrs.is_empty() {
+                format!("<{} />", &caps[1])
+            } else {
+                format!("<{} {attrs} />", &caps[1])
+            }
+        })
+        .into_owned()
+}
+
+fn xhtml_document(title: &str, language: &str, body: &str) -> String {
+    format!(
+        r##"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops" lang="{language}" xml:lang="{language}">
+<head>
+<meta charset="UTF-8" />
+<title>{title}</title>
+</head>
+<body>
+{body}
+</body>
+</html>
+"##,
+        title = html::escape(title),
+        language = html::escape(language),
+    )
+}
+
+const CONTAINER_XML: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"##;
+
+/// Package the chapters into an EPUB 3 file, in order of their titles.
+pub fn write_epub(
+    config: &EpubConfig,
+    base_dir: &Path,
+    mut chapters: Vec<EpubChapter>,
+) -> Result<()> {
+    chapters.sort_by(|a, b| {
+        a.title
+            .to_lowercase()
+            .cmp(&b.title.to_lowercase())
+            .then_with(|| a.slug.cmp(&b.slug))
+    });
+
+    let book_title = config.title.clone().unwrap_or_else(|| {
+        config
+            .path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default()
+    });
+    let language = config.language.as_deref().unwrap_or("en");
+
+    let chapters_by_slug = chapters
+        .iter()
+        .enumerate()
+        .map(|(i, chapter)| (html::escape(&chapter.slug).to_string(), chapter_filename(i)))
+        .collect::<HashMap<_, _>>();
+
+    let mut images = HashMap::default();
+    let documents = chapters
+        .iter()
+        .map(|chapter| {
+            let body = rewrite_links(&chapter.body, &chapters_by_slug);
+            let body = embed_images(&body, base_dir, &chapter.path, &mut images);
+            let body = format!(
+                "<h1>{}</h1>\n{}",
+                html::escape(&chapter.title),
+                to_xhtml(&body)
+            );
+            xhtml_document(&chapter.title, language, &body)
+        })
+        .collect::<Vec<_>>();
+
+    let nav_items = chapters
+        .iter()
+        .enumerate()
+        .map(|(i, chapter)| {
+            format!(
+                r##"<li><a href="{}">{}</a></li>"##,
+                chapter_filename(i),
+                html::escape(&chapter.title)
+            )
+        })
+        .join("\n");
+    let nav = xhtml_document(
+        &book_title,
+        language,
+        &format!("<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n<ol>\n{nav_items}\n</ol>\n</nav>"),
+    );
+
+    // A stable identifier, so that readers recognize new exports as the same book.
+    let hash = blake3::hash(book_title.as_bytes());
+    let mut id_bytes = [0u8; 16];
+    id_bytes.copy_from_slice(&hash.as_bytes()[..16]);
+    let identifier = uuid::Uuid::from_bytes(id_bytes);
+
+    let mut manifest_items = vec![String::from(
+        r##"<item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>"##,
+    )];
+    for (i, document) in documents.iter().enumerate() {
+        let mut properties = Vec::new();
+        if document.contains("<math") {
+            properties.push("mathml");
+        }
+        if document.contains(r#"src="http"#) {
+            properties.push("remote-resources");
+        }
+        let properties = if properties.is_empty() {
+            String::new()
+        } else {
+            format!(r#" properties="{}""#, properties.join(" "))
+        };
+
+        manifest_items.push(format!(
+            r##"<item id="page-{n}" href="{filename}" media-type="application/xhtml+xml"{properties}/>"##,
+            n = i + 1,
+            filename = chapter_filename(i),
+        ));
+    }
+    for (i, image) in images
+        .values()
+        .sorted_by(|a, b| a.href.cmp(&b.href))
+        .enumerate()
+    {
+        manifest_items.push(format!(
+            r##"<item id="image-{n}" href="{href}" media-type="{media_type}"/>"##,
+            n = i + 1,
+            href = image.href,
+            media_type = image.media_type,
+        ));
+    }
+
+    let spine = (0..documents.len())
+        .map(|i| format!(r##"<itemref idref="page-{}"/>"##, i + 1))
+        .join("\n    ");
+
+    let creator = config
+        .author
+        .as_ref()
+        .map(|author| format!("\n    <dc:creator>{}</dc:creator>", html::escape(author)))
+        .unwrap_or_default();
+
+    let package = format!(
+        r##"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id" xml:lang="{language}">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="book-id">urn:uuid:{identifier}</dc:identifier>
+    <dc:title>{title}</dc:title>
+    <dc:language>{language}</dc:language>{creator}
+    <meta property="dcterms:modified">{modified}</meta>
+  </metadata>
+  <manifest>
+    {manifest}
+  </manifest>
+  <spine>
+    {spine}
+  </spine>
+</package>
+"##,
+        language = html::escape(language),
+        title = html::escape(&book_title),
+        modified = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+        manifest = manifest_items.join("\n    "),
+    );
+
+    let file = std::fs::File::create(&config.path)
+        .with_context(|| format!("Writing {}", config.path.display()))?;
+    let mut zip = ZipWriter::new(file);
+
+    // The mimetype file must come first, without compression.
+    zip.start_file(
+        "mimetype",
+        FileOptions::default().compression_method(CompressionMethod::Stored),
+    )?;
+    zip.write_all(b"application/epub+zip")?;
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file("META-INF/container.xml", options)?;
+    zip.write_all(CONTAINER_XML.as_bytes())?;
+    zip.start_file("OEBPS/content.opf", options)?;
+    zip.write_all(package.as_bytes())?;
+    zip.start_file("OEBPS/nav.xhtml", options)?;
+    zip.write_all(nav.as_bytes())?;
+
+    for (i, document) in documents.iter().enumerate() {
+        zip.start_file(format!("OEBPS/{}", chapter_filename(i)), options)?;
+        zip.write_all(document.as_bytes())?;
+    }
+
+    for image in images.values() {
+        zip.start_file(format!("OEBPS/{}", image.href), options)?;
+        zip.write_all(&image.data)?;
+    }
+
+    zip.finish()
+        .with_context(|| format!("Writing {}", config.path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn rewrites_links_to_chapters() {
+        let chapters_by_slug =
+            HashMap::from_iter([(String::from("/a"), String::from("page-0001.xhtml"))]);
+        assert_eq!(
+            rewrite_links(
+                r##"<a href="/a">A</a> <a class="block-ref" href="/a#abc">ref</a> <a href="/b">B</a>"##,
+                &chapters_by_slug
+            ),
+            r##"<a href="page-0001.xhtml">A</a> <a class="block-ref" href="page-0001.xhtml#abc">ref</a> <a href="/b">B</a>"##
+        );
+    }
+
+    #[test]
+    fn xhtml() {
+        assert_eq!(
+            to_xhtml(r##"a &mdash; b<br><input type="checkbox" disabled checked /><hr />"##),
+            r##"a &#8212; b<br /><input type="checkbox" disabled="disabled" checked="checked" /><hr />"##
+        );
+    }
+
+    #[test]
+    fn package() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = EpubConfig {
+            path: dir.path().join("book.epub"),
+            title: Some(String::from("Notes")),
+            author: None,
+            language: None,
+        };
+
+        let chapters = vec![
+            EpubChapter {
+                title: String::from("Beta"),
+                slug: String::from("/beta"),
+                path: dir.path().join("beta.md"),
+                body: String::from(r##"<ul><li>See <a href="/alpha">Alpha</a></li></ul>"##),
+            },
+            EpubChapter {
+                title: String::from("Alpha"),
+                slug: String::from("/alpha"),
+                path: dir.path().join("alpha.md"),
+                body: String::from("<p>First</p>"),
+            },
+        ];
+        write_epub(&config, dir.path(), chapters).unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&config.path).unwrap()).unwrap();
+        assert_eq!(archive.by_index(0).unwrap().name(), "mimetype");
+
+        let mut beta = String::new();
+        archive
+            .by_name("OEBPS/page-0002.xhtml")
+            .unwrap()
+            .read_to_string(&mut beta)
+            .unwrap();
+        assert!(beta.contains(r##"<h1>Beta</h1>"##));
+        assert!(beta.contains(r##"<a href="page-0001.xhtml">Alpha</a>"##));
+
+        let mut nav = String::new();
+        archive
+            .by_name("OEBPS/nav.xhtml")
+            .unwrap()
+            .read_to_string(&mut nav)
+            .unwrap();
+        assert!(nav.contains(r##"<li><a href="page-0001.xhtml">Alpha</a></li>"##));
+    }
+}
@@ -0,0 +1,415 @@
+//! Atom and RSS feeds of the most recently edited pages.
+
+use std::fmt::Write;
+
+use ahash::HashMap;
+use chrono::{SecondsFormat, TimeZone, Utc};
+use itertools::Itertools;
+use serde::Deserialize;
+
+use crate::{html, make_pages::title_to_slug};
+
+/// What each feed entry contains.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedContent {
+    /// The page's `excerpt`
+    #[default]
+    Excerpt,
+    /// The page's rendered HTML
+    Full,
+}
+
+fn default_entries() -> usize {
+    20
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct FeedConfig {
+    /// The title of the feed.
+    pub title: String,
+    /// A description of the feed. Defaults to the title.
+    pub description: Option<String>,
+    /// The author of the pages. Defaults to the title.
+    pub author: Option<String>,
+    /// The number of pages in each feed. Defaults to 20.
+    #[serde(default = "default_entries")]
+    pub entries: usize,
+    /// Whether entries have the page's excerpt or its full contents. Defaults to "excerpt".
+    #[serde(default)]
+    pub content: FeedContent,
+    /// Write `rss.xml` as well as `feed.xml`. Defaults to false.
+    #[serde(default)]
+    pub rss: bool,
+    /// Also write a feed for each tag into the `feeds` directory. Defaults to false.
+    #[serde(default)]
+    pub tag_feeds: bool,
+}
+
+/// An exported page, as it appears in a feed.
+#[derive(Clone, Debug)]
+pub struct FeedEntry {
+    pub title: String,
+    pub slug: String,
+    pub tags: Vec<String>,
+    pub created_time: u64,
+    pub edited_time: u64,
+    /// The excerpt or the rendered HTML, depending on `FeedConfig::content`
+    pub content: String,
+}
+
+/// A feed file to write, with its path relative to the output directory.
+#[derive(Debug)]
+pub struct FeedFile {
+    pub path: String,
+    pub contents: String,
+}
+
+fn rfc3339(time: u64) -> String {
+    Utc.timestamp_millis_opt(time as i64)
+        .single()
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn rfc2822(time: u64) -> String {
+    Utc.timestamp_millis_opt(time as i64)
+        .single()
+        .unwrap_or_default()
+        .to_rfc2822()
+}
+
+struct Feed<'a> {
+    title: String,
+    /// The paths of the Atom and RSS files, relative to the output directory
+    atom_path: String,
+    rss_path: String,
+    entries: Vec<&'a FeedEntry>,
+}
+
+struct FeedWriter<'a> {
+    config: &'a FeedConfig,
+    /// The scheme and host of the site, without a trailing slash
+    origin: &'a str,
+    /// The URL of the output directory, ending with a slash
+    site_url: String,
+}
+
+impl<'a> FeedWriter<'a> {
+    /// The absolute URL of a page's slug.
+    fn url(&self, slug: &str) -> String {
+        format!("{}/{}", self.origin, slug.trim_start_matches('/'))
+    }
+
+    /// The feed's updated time is the latest edit, so the file only changes when a page does.
+    fn updated(feed: &Feed) -> u64 {
+        feed.entries
+            .iter()
+            .map(|entry| entry.edited_time)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn atom(&self, feed: &Feed) -> String {
+        let feed_url = format!("{}{}", self.site_url, feed.atom_path);
+        let author = self.config.author.as_deref().unwrap_or(&self.config.title);
+
+        let mut output = String::new();
+        writeln!(output, r#"<?xml version="1.0" encoding="utf-8"?>"#).unwrap();
+        writeln!(output, r#"<feed xmlns="http://www.w3.org/2005/Atom">"#).unwrap();
+        writeln!(output, "  <title>{}</title>", html::escape(&feed.title)).unwrap();
+        if let Some(description) = self.config.description.as_deref() {
+            writeln!(
+                output,
+                "  <subtitle>{}</subtitle>",
+                html::escape(description)
+            )
+            .unwrap();
+        }
+        writeln!(output, "  <id>{}</id>", html::escape(&feed_url)).unwrap();
+        writeln!(
+            output,
+            r#"  <link rel="self" href="{}"/>"#,
+            html::escape(&feed_url)
+        )
+        .unwrap();
+        writeln!(
+            output,
+            r#"  <link href="{}"/>"#,
+            html::escape(&self.site_url)
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "  <updated>{}</updated>",
+            rfc3339(Self::updated(feed))
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "  <author><name>{}</name></author>",
+            html::escape(author)
+        )
+        .unwrap();
+
+        for entry in &feed.entries {
+            let url = self.url(&entry.slug);
+            writeln!(output, "  <entry>").unwrap();
+            writeln!(output, "    <title>{}</title>", html::escape(&entry.title)).unwrap();
+            writeln!(output, r#"    <link href="{}"/>"#, html::escape(&url)).unwrap();
+            writeln!(output, "    <id>{}</id>", html::escape(&url)).unwrap();
+            writeln!(
+                output,
+                "    <published>{}</published>",
+                rfc3339(entry.created_time)
+            )
+            .unwrap();
+            writeln!(
+                output,
+                "    <updated>{}</updated>",
+                rfc3339(entry.edited_time)
+            )
+            .unwrap();
+            for tag in &entry.tags {
+                writeln!(output, r#"    <category term="{}"/>"#, html::escape(tag)).unwrap();
+            }
+            match self.config.content {
+                FeedContent::Excerpt => writeln!(
+                    output,
+                    r#"    <summary type="text">{}</summary>"#,
+                    html::escape(&entry.content)
+                ),
+                // Links in the body are relative to the site, so give them a base.
+                FeedContent::Full => writeln!(
+                    output,
+                    r#"    <content type="html" xml:base="{}">{}</content>"#,
+                    html::escape(&url),
+                    html::escape(&entry.content)
+                ),
+            }
+            .unwrap();
+            writeln!(output, "  </entry>").unwrap();
+        }
+
+        writeln!(output, "</feed>").unwrap();
+        output
+    }
+
+    fn rss(&self, feed: &Feed) -> String {
+        let feed_url = format!("{}{}", self.site_url, feed.rss_path);
+        let description = self
+            .config
+            .description
+            .as_deref()
+            .unwrap_or(&self.config.title);
+
+        let mut output = String::new();
+        writeln!(output, r#"<?xml version="1.0" encoding="utf-8"?>"#).unwrap();
+        writeln!(
+            output,
+            r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">"#
+        )
+        .unwrap();
+        writeln!(output, "<channel>").unwrap();
+        writeln!(output, "  <title>{}</title>", html::escape(&feed.title)).unwrap();
+        writeln!(output, "  <link>{}</link>", html::escape(&self.site_url)).unwrap();
+        writeln!(
+            output,
+            "  <description>{}</description>",
+            html::escape(description)
+        )
+        .unwrap();
+        writeln!(
+            output,
+            r#"  <atom:link href="{}" rel="self" type="application/rss+xml"/>"#,
+            html::escape(&feed_url)
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "  <lastBuildDate>{}</lastBuildDate>",
+            rfc2822(Self::updated(feed))
+        )
+        .unwrap();
+
+        for entry in &feed.entries {
+            let url = self.url(&entry.slug);
+            writeln!(output, "  <item>").unwrap();
+            writeln!(output, "    <title>{}</title>", html::escape(&entry.title)).unwrap();
+            writeln!(output, "    <link>{}</link>", html::escape(&url)).unwrap();
+            writeln!(
+                output,
+                r#"    <guid isPermaLink="true">{}</guid>"#,
+                html::escape(&url)
+            )
+            .unwrap();
+            writeln!(
+                output,
+                "    <pubDate>{}</pubDate>",
+                rfc2822(entry.created_time)
+            )
+            .unwrap();
+            for tag in &entry.tags {
+                writeln!(output, "    <category>{}</category>", html::escape(tag)).unwrap();
+            }
+            writeln!(
+                output,
+                "    <description>{}</description>",
+                html::escape(&entry.content)
+            )
+            .unwrap();
+            writeln!(output, "  </item>").unwrap();
+        }
+
+        writeln!(output, "</channel>").unwrap();
+        writeln!(output, "</rss>").unwrap();
+        output
+    }
+}
+
+/// The most recently edited entries, newest first.
+fn newest<'a>(entries: impl Iterator<Item = &'a FeedEntry>, count: usize) -> Vec<&'a FeedEntry> {
+    entries
+        .sorted_by(|a, b| {
+            b.edited_time
+                .cmp(&a.edited_time)
+                .then_with(|| a.title.cmp(&b.title))
+        })
+        .take(count)
+        .collect()
+}
+
+/// Create the site's feeds. `origin` is the scheme and host that the site is served from, and
+/// `base_url` is the path of the output directory within it.
+pub fn make_feeds(
+    config: &FeedConfig,
+    origin: &str,
+    base_url: &str,
+    entries: &[FeedEntry],
+) -> Vec<FeedFile> {
+    let origin = origin.trim_end_matches('/');
+    let writer = FeedWriter {
+        config,
+        origin,
+        site_url: format!("{origin}/{}", base_url.trim_start_matches('/')),
+    };
+
+    let mut feeds = vec![Feed {
+        title: config.title.clone(),
+        atom_path: String::from("feed.xml"),
+        rss_path: String::from("rss.xml"),
+        entries: newest(entries.iter(), config.entries),
+    }];
+
+    if config.tag_feeds {
+        // Tags that differ only in case share a feed.
+        let mut tags: HashMap<String, (&str, Vec<&FeedEntry>)> = HashMap::default();
+        for entry in entries {
+            for tag in entry.tags.iter().unique_by(|tag| title_to_slug(tag)) {
+                tags.entry(title_to_slug(tag))
+                    .or_insert_with(|| (tag, Vec::new()))
+                    .1
+                    .push(entry);
+            }
+        }
+
+        feeds.extend(
+            tags.into_iter()
+                .filter(|(slug, _)| !slug.is_empty())
+                .sorted_by(|a, b| a.0.cmp(&b.0))
+                .map(|(slug, (tag, tag_entries))| Feed {
+                    title: format!("{}: {tag}", config.title),
+                    atom_path: format!("feeds/{slug}.xml"),
+                    rss_path: format!("feeds/{slug}.rss.xml"),
+                    entries: newest(tag_entries.into_iter(), config.entries),
+                }),
+        );
+    }
+
+    feeds
+        .iter()
+        .flat_map(|feed| {
+            let atom = FeedFile {
+                path: feed.atom_path.clone(),
+                contents: writer.atom(feed),
+            };
+            let rss = config.rss.then(|| FeedFile {
+                path: feed.rss_path.clone(),
+                contents: writer.rss(feed),
+            });
+            std::iter::once(atom).chain(rss)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(title: &str, tags: &[&str], edited_time: u64) -> FeedEntry {
+        FeedEntry {
+            title: title.to_string(),
+            slug: format!("/notes/{}", title_to_slug(title)),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            created_time: 1_700_000_000_000,
+            edited_time,
+            content: format!("About {title} & more"),
+        }
+    }
+
+    #[test]
+    fn feeds() {
+        let config = FeedConfig {
+            title: String::from("Notes"),
+            description: None,
+            author: None,
+            entries: 2,
+            content: FeedContent::Excerpt,
+            rss: true,
+            tag_feeds: true,
+        };
+        let entries = [
+            entry("Old", &["Rust"], 1_700_000_000_000),
+            entry("Newest", &["rust", "Books"], 1_700_000_300_000),
+            entry("Middle", &[], 1_700_000_200_000),
+        ];
+
+        let feeds = make_feeds(&config, "https://example.com/", "/notes/", &entries);
+        let paths = feeds.iter().map(|f| f.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                "feed.xml",
+                "rss.xml",
+                "feeds/books.xml",
+                "feeds/books.rss.xml",
+                "feeds/rust.xml",
+                "feeds/rust.rss.xml"
+            ]
+        );
+
+        let atom = &feeds[0].contents;
+        assert!(atom.contains(r#"<link rel="self" href="https://example.com/notes/feed.xml"/>"#));
+        assert!(atom.contains("<updated>2023-11-14T22:18:20Z</updated>"));
+        // Only the two newest pages fit.
+        let titles = atom
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("<title>"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            titles,
+            vec!["Notes</title>", "Newest</title>", "Middle</title>"]
+        );
+        assert!(atom.contains(r#"<link href="https://example.com/notes/newest"/>"#));
+        assert!(atom.contains(r#"<summary type="text">About Newest &amp; more</summary>"#));
+
+        let rss = &feeds[1].contents;
+        assert!(rss.contains("<pubDate>Tue, 14 Nov 2023 22:13:20 +0000</pubDate>"));
+        assert!(rss.contains("<guid isPermaLink=\"true\">https://example.com/notes/middle</guid>"));
+
+        // Both spellings of the tag go in the same feed, named after the first one seen.
+        let rust = &feeds[4].contents;
+        assert!(rust.contains("<title>Notes: Rust</title>"));
+        assert_eq!(rust.matches("<entry>").count(), 2);
+    }
+}
@@ -3,7 +3,7 @@ use std::path::PathBuf;
 use ahash::HashMap;
 use smallvec::SmallVec;
 
-use crate::{content::BlockContent, parse_string::ContentStyle};
+use crate::{content::BlockContent, whiteboard::Whiteboard};
 
 #[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
 pub enum ViewType {
@@ -98,11 +98,13 @@ pub struct Block {
     pub wrapper_element: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ParsedPage {
     pub path: PathBuf,
     pub root_block: usize,
     pub blocks: HashMap<usize, Block>,
+    /// The drawing, if this page is a Logseq whiteboard
+    pub whiteboard: Option<Whiteboard>,
 }
 
 #[derive(Debug)]
@@ -114,17 +116,91 @@ pub struct Graph {
     /// true if the blocks are ordered by the order field, instead of just the order in which they
     /// appear in `children`
     pub block_explicit_ordering: bool,
+}
+
+#[cfg(test)]
+impl Block {
+    /// A block on the page whose page block is `page`, with everything else empty. When `id` is
+    /// `page`, this is the page block itself. Tests fill in any other fields they need.
+    pub fn test(id: usize, page: usize, contents: &str) -> Block {
+        Block {
+            id,
+            containing_page: page,
+            page_title: None,
+            original_title: None,
+            uid: String::new(),
+            parent: (id != page).then_some(page),
+            children: Default::default(),
+            order: id,
+            include_type: Default::default(),
+            tags: Default::default(),
+            attrs: Default::default(),
+            is_journal: false,
+            contents: BlockContent::new_parsed(
+                crate::parse_string::ContentStyle::Logseq,
+                contents.to_string(),
+            )
+            .unwrap(),
+            heading: 0,
+            view_type: Default::default(),
+            this_block_list_type: Default::default(),
+            edit_time: 0,
+            create_time: 0,
+            extra_classes: Vec::new(),
+            content_element: None,
+            wrapper_element: None,
+        }
+    }
+}
+
+impl Block {
+    /// The alternate titles declared for this page with the `alias` attribute, or Obsidian's
+    /// `aliases`.
+    pub fn aliases(&self) -> Vec<String> {
+        ["alias", "aliases"]
+            .iter()
+            .filter_map(|attr| self.attrs.get(*attr))
+            .flatten()
+            .flat_map(|v| v.split(','))
+            .map(|v| {
+                v.trim()
+                    .trim_start_matches('#')
+                    .trim_start_matches("[[")
+                    .trim_end_matches("]]")
+                    .trim()
+                    .to_string()
+            })
+            .filter(|v| !v.is_empty())
+            .collect()
+    }
+}
 
-    pub content_style: ContentStyle,
+impl ParsedPage {
+    /// Shift every block id in the page by `offset`, so that pages read from separate files
+    /// don't reuse each other's ids.
+    pub fn offset_ids(&mut self, offset: usize) {
+        self.root_block += offset;
+        self.blocks = std::mem::take(&mut self.blocks)
+            .into_iter()
+            .map(|(id, mut block)| {
+                block.id += offset;
+                block.containing_page += offset;
+                block.parent = block.parent.map(|p| p + offset);
+                for child in block.children.iter_mut() {
+                    *child += offset;
+                }
+                (id + offset, block)
+            })
+            .collect();
+    }
 }
 
 impl Graph {
-    pub fn new(content_style: ContentStyle, block_explicit_ordering: bool) -> Graph {
+    pub fn new(block_explicit_ordering: bool) -> Graph {
         Graph {
             blocks: HashMap::default(),
             blocks_by_uid: HashMap::default(),
             page_blocks: Vec::new(),
-            content_style,
             block_explicit_ordering,
         }
     }
@@ -162,9 +238,56 @@ impl Graph {
         })
     }
 
+    /// The children of a block, in the order that they appear on the page.
+    pub fn children(&self, block: &Block) -> Vec<&Block> {
+        let mut children = block
+            .children
+            .iter()
+            .filter_map(|id| self.blocks.get(id))
+            .collect::<Vec<_>>();
+        if self.block_explicit_ordering {
+            children.sort_by_key(|b| b.order);
+        }
+
+        children
+    }
+
     pub fn block_from_uid(&self, uid: &str) -> Option<&Block> {
         self.blocks_by_uid
             .get(uid)
             .and_then(|id| self.blocks.get(id))
     }
+
+    /// Return true if the block's own content will appear when its page is rendered, based on
+    /// the include settings of the block and its ancestors.
+    pub fn is_block_rendered(&self, block_id: usize) -> bool {
+        let Some(block) = self.blocks.get(&block_id) else {
+            return false;
+        };
+
+        if matches!(
+            block.include_type,
+            BlockInclude::Exclude | BlockInclude::OnlyChildren
+        ) {
+            return false;
+        }
+
+        let mut parent = block.parent;
+        while let Some(parent_id) = parent {
+            let Some(parent_block) = self.blocks.get(&parent_id) else {
+                break;
+            };
+
+            if matches!(
+                parent_block.include_type,
+                BlockInclude::Exclude | BlockInclude::JustBlock
+            ) {
+                return false;
+            }
+
+            parent = parent_block.parent;
+        }
+
+        true
+    }
 }
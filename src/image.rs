@@ -25,16 +25,27 @@ pub struct ImageInfo {
 pub struct Images {
     images: std::sync::Mutex<HashMap<String, ImageInfo>>,
     pending_images: std::sync::Mutex<Vec<(Image, String)>>,
+    /// Images that a dry run found no record of, and so would have looked up or uploaded.
+    skipped_uploads: std::sync::Mutex<Vec<PathBuf>>,
+    /// Don't contact Pic Store, and leave out the images that aren't in the database already.
+    dry_run: bool,
     base_path: PathBuf,
     pic_store: PicStoreClient,
     db: MetadataDb,
 }
 
 impl Images {
-    pub fn new(base_path: PathBuf, pic_store: PicStoreClient, db: MetadataDb) -> Self {
+    pub fn new(
+        base_path: PathBuf,
+        pic_store: PicStoreClient,
+        db: MetadataDb,
+        dry_run: bool,
+    ) -> Self {
         Self {
             images: Mutex::new(HashMap::default()),
             pending_images: Mutex::new(Vec::new()),
+            skipped_uploads: Mutex::new(Vec::new()),
+            dry_run,
             base_path,
             pic_store,
             db,
@@ -69,6 +80,8 @@ impl Images {
                 image.path.to_string_lossy().to_string(),
                 ImageInfo { image, data },
             );
+        } else if self.dry_run {
+            self.skipped_uploads.lock().unwrap().push(image.path);
         } else {
             // This is a new image, so add it to the CDN if necessary.
             let result = self.pic_store.get_or_upload_image(&image, upload_profile)?;
@@ -94,6 +107,13 @@ impl Images {
         Ok(())
     }
 
+    /// The images that would have been uploaded if this wasn't a dry run.
+    pub fn skipped_uploads(&self) -> Vec<PathBuf> {
+        let mut paths = self.skipped_uploads.lock().unwrap().clone();
+        paths.sort();
+        paths
+    }
+
     /// Extract the image list once everything has been gathered.
     pub fn finish(self) -> Result<HashMap<String, ImageInfo>> {
         let pending = self.pending_images.into_inner().unwrap();
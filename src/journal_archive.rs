@@ -0,0 +1,355 @@
+//! Generated archive pages for the journals, by year and by month, and the links between
+//! consecutive journal pages.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use ahash::HashMap;
+use chrono::{Datelike, NaiveDate};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::OutputFormat, html, make_pages::ListingPage, markdown};
+
+fn default_dir() -> String {
+    String::from("journals")
+}
+
+fn default_title() -> String {
+    String::from("Journals")
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct JournalArchiveConfig {
+    /// The template for the archive pages. Defaults to the default template.
+    pub template: Option<PathBuf>,
+    /// The directory, relative to the output directory and the base URL, that the year and month
+    /// pages are written to. The page listing the years is written next to it, as
+    /// `journals.html` for the default "journals".
+    #[serde(default = "default_dir")]
+    pub dir: String,
+    /// The title of the page listing the years. Defaults to "Journals".
+    #[serde(default = "default_title")]
+    pub title: String,
+}
+
+/// An exported journal page, as far as the archive cares.
+pub struct JournalMember<'a> {
+    pub title: &'a str,
+    pub slug: &'a str,
+    pub date: NaiveDate,
+    pub create_time: u64,
+    pub edit_time: u64,
+}
+
+/// A link to another page, for the journal navigation.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct JournalLink {
+    pub title: String,
+    pub slug: String,
+}
+
+/// The journal pages before and after a journal page.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct JournalNav {
+    pub previous: Option<JournalLink>,
+    pub next: Option<JournalLink>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    pub title: String,
+    pub slug: String,
+    /// The number of journal pages, for entries that link to another archive page
+    pub count: Option<usize>,
+}
+
+/// A generated page listing the years, the months of a year, or the journal pages of a month.
+#[derive(Debug)]
+pub struct ArchivePage {
+    pub title: String,
+    /// The path of the page relative to the output directory, without the extension
+    pub url_name: String,
+    pub slug: String,
+    pub create_time: u64,
+    pub edit_time: u64,
+    pub entries: Vec<ArchiveEntry>,
+}
+
+impl ArchivePage {
+    /// Render the list of entries on the page.
+    pub fn render(&self, format: OutputFormat) -> String {
+        match format {
+            OutputFormat::Html => {
+                let items = self
+                    .entries
+                    .iter()
+                    .map(|entry| {
+                        let count = entry
+                            .count
+                            .map(|c| format!(" <span class=\"archive-count\">({c})</span>"))
+                            .unwrap_or_default();
+                        format!(
+                            "  <li><a href=\"{slug}\">{title}</a>{count}</li>\n",
+                            slug = html::escape(&entry.slug),
+                            title = html::escape(&entry.title)
+                        )
+                    })
+                    .join("");
+                format!("<ul class=\"journal-archive\">\n{items}</ul>\n")
+            }
+            OutputFormat::Markdown => self
+                .entries
+                .iter()
+                .map(|entry| {
+                    let count = entry.count.map(|c| format!(" ({c})")).unwrap_or_default();
+                    format!(
+                        "- [{title}]({slug}){count}\n",
+                        title = markdown::escape(&entry.title),
+                        slug = markdown::escape_url(&entry.slug)
+                    )
+                })
+                .join(""),
+        }
+    }
+}
+
+pub struct JournalArchive {
+    /// The page listing the years, followed by each year's page and its month pages, newest first
+    pub pages: Vec<ArchivePage>,
+    /// Keyed by the slug of each journal page
+    nav: HashMap<String, JournalNav>,
+}
+
+fn archive_page<'a>(
+    title: String,
+    url_name: String,
+    make_slug: &impl Fn(&str) -> String,
+    members: impl Iterator<Item = &'a JournalMember<'a>> + Clone,
+    entries: Vec<ArchiveEntry>,
+) -> ArchivePage {
+    ArchivePage {
+        title,
+        slug: make_slug(&url_name),
+        url_name,
+        create_time: members.clone().map(|m| m.create_time).min().unwrap_or(0),
+        edit_time: members.map(|m| m.edit_time).max().unwrap_or(0),
+        entries,
+    }
+}
+
+impl JournalArchive {
+    /// Build the archive pages for the journal pages. `make_slug` turns a path relative to the
+    /// output directory into a URL. Each level lists the newest entries first.
+    pub fn new(
+        config: &JournalArchiveConfig,
+        members: &[JournalMember],
+        make_slug: impl Fn(&str) -> String,
+    ) -> JournalArchive {
+        let dir = config.dir.trim_matches('/');
+        let members = members
+            .iter()
+            .sorted_by(|a, b| (a.date, a.slug).cmp(&(b.date, b.slug)))
+            .collect::<Vec<_>>();
+
+        let link = |member: &JournalMember| JournalLink {
+            title: member.title.to_string(),
+            slug: member.slug.to_string(),
+        };
+        let nav = members
+            .iter()
+            .enumerate()
+            .map(|(i, member)| {
+                let nav = JournalNav {
+                    previous: i.checked_sub(1).map(|i| link(members[i])),
+                    next: members.get(i + 1).map(|m| link(m)),
+                };
+                (member.slug.to_string(), nav)
+            })
+            .collect();
+
+        let mut by_month: BTreeMap<i32, BTreeMap<u32, Vec<&JournalMember>>> = BTreeMap::new();
+        for member in &members {
+            by_month
+                .entry(member.date.year())
+                .or_default()
+                .entry(member.date.month())
+                .or_default()
+                .push(member);
+        }
+
+        let mut year_pages = Vec::new();
+        let mut year_entries = Vec::new();
+        for (year, months) in by_month.iter().rev() {
+            let year_url = format!("{dir}/{year}");
+
+            let mut month_pages = Vec::new();
+            let mut month_entries = Vec::new();
+            for (month, month_members) in months.iter().rev() {
+                let month_name = month_members[0].date.format("%B %Y").to_string();
+                let month_page = archive_page(
+                    month_name.clone(),
+                    format!("{year_url}/{month:02}"),
+                    &make_slug,
+                    month_members.iter().copied(),
+                    month_members
+                        .iter()
+                        .rev()
+                        .map(|member| ArchiveEntry {
+                            title: member.title.to_string(),
+                            slug: member.slug.to_string(),
+                            count: None,
+                        })
+                        .collect(),
+                );
+
+                month_entries.push(ArchiveEntry {
+                    title: month_name,
+                    slug: month_page.slug.clone(),
+                    count: Some(month_members.len()),
+                });
+                month_pages.push(month_page);
+            }
+
+            let year_members = months.values().flatten().copied();
+            let year_page = archive_page(
+                year.to_string(),
+                year_url,
+                &make_slug,
+                year_members.clone(),
+                month_entries,
+            );
+
+            year_entries.push(ArchiveEntry {
+                title: year.to_string(),
+                slug: year_page.slug.clone(),
+                count: Some(year_members.count()),
+            });
+            year_pages.push(year_page);
+            year_pages.extend(month_pages);
+        }
+
+        let root = archive_page(
+            config.title.clone(),
+            dir.to_string(),
+            &make_slug,
+            members.iter().copied(),
+            year_entries,
+        );
+
+        let mut pages = vec![root];
+        pages.extend(year_pages);
+        JournalArchive { pages, nav }
+    }
+
+    /// The journal pages before and after the journal page with this slug.
+    pub fn nav(&self, slug: &str) -> Option<&JournalNav> {
+        self.nav.get(slug)
+    }
+
+    /// Render the archive pages.
+    pub fn render(&self, format: OutputFormat) -> Vec<ListingPage> {
+        self.pages
+            .iter()
+            .map(|page| ListingPage {
+                title: page.title.clone(),
+                url_name: page.url_name.clone(),
+                slug: page.slug.clone(),
+                body: page.render(format),
+                create_time: page.create_time,
+                edit_time: page.edit_time,
+                tag_counts: Vec::new(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(title: &str, edit_time: u64) -> JournalMember<'_> {
+        JournalMember {
+            title,
+            slug: title,
+            date: crate::logseq::journal_date(title).unwrap(),
+            create_time: edit_time,
+            edit_time,
+        }
+    }
+
+    #[test]
+    fn archive_pages_and_nav() {
+        let members = [
+            member("2023-02-01", 4),
+            member("2022-12-31", 1),
+            member("2023-02-14", 3),
+            member("2023-01-09", 2),
+        ];
+        let config = JournalArchiveConfig {
+            template: None,
+            dir: String::from("journals"),
+            title: String::from("Journals"),
+        };
+        let archive = JournalArchive::new(&config, &members, |name| format!("/{name}"));
+
+        let pages = archive
+            .pages
+            .iter()
+            .map(|page| (page.title.as_str(), page.slug.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pages,
+            vec![
+                ("Journals", "/journals"),
+                ("2023", "/journals/2023"),
+                ("February 2023", "/journals/2023/02"),
+                ("January 2023", "/journals/2023/01"),
+                ("2022", "/journals/2022"),
+                ("December 2022", "/journals/2022/12"),
+            ]
+        );
+
+        let root = &archive.pages[0];
+        assert_eq!(root.create_time, 1);
+        assert_eq!(root.edit_time, 4);
+        assert_eq!(
+            root.render(OutputFormat::Markdown),
+            "- [2023](/journals/2023) (3)\n- [2022](/journals/2022) (1)\n"
+        );
+
+        let february = &archive.pages[2];
+        assert_eq!(february.edit_time, 4);
+        assert_eq!(
+            february.entries,
+            vec![
+                ArchiveEntry {
+                    title: String::from("2023-02-14"),
+                    slug: String::from("2023-02-14"),
+                    count: None,
+                },
+                ArchiveEntry {
+                    title: String::from("2023-02-01"),
+                    slug: String::from("2023-02-01"),
+                    count: None,
+                },
+            ]
+        );
+
+        let link = |title: &str| {
+            Some(JournalLink {
+                title: title.to_string(),
+                slug: title.to_string(),
+            })
+        };
+        assert_eq!(
+            archive.nav("2023-01-09"),
+            Some(&JournalNav {
+                previous: link("2022-12-31"),
+                next: link("2023-02-01"),
+            })
+        );
+        assert_eq!(archive.nav("2022-12-31").unwrap().previous, None);
+        assert_eq!(archive.nav("2023-02-14").unwrap().next, None);
+        assert_eq!(archive.nav("not-a-journal"), None);
+    }
+}
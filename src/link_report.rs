@@ -0,0 +1,223 @@
+//! Links, embeds, and images that didn't resolve to anything in the export, gathered while
+//! rendering so that they can be fixed without reading through the output.
+
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+};
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{graph::Block, image::image_full_path, page::IdSlugUid};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkKind {
+    Link,
+    Tag,
+    PageEmbed,
+    BlockEmbed,
+    BlockRef,
+    Image,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkProblemReason {
+    /// No page has this title or alias.
+    MissingPage,
+    /// The page exists, but the script didn't include it in the export, or didn't allow
+    /// embedding it.
+    UnexportedPage,
+    /// No block has this uid.
+    MissingBlock,
+    /// The local image file doesn't exist.
+    MissingImage,
+}
+
+/// A reference from a block that couldn't be resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkProblem {
+    pub block_uid: String,
+    pub kind: LinkKind,
+    pub target: String,
+    pub reason: LinkProblemReason,
+}
+
+/// The problems found while rendering a page. The renderers only have shared access to the
+/// page, so this uses interior mutability like `Page::latest_found_edit_time`.
+#[derive(Debug, Default)]
+pub struct LinkProblems(RefCell<Vec<LinkProblem>>);
+
+impl LinkProblems {
+    pub fn add(&self, block: &Block, kind: LinkKind, target: &str, reason: LinkProblemReason) {
+        self.0.borrow_mut().push(LinkProblem {
+            block_uid: block.uid.clone(),
+            kind,
+            target: target.to_string(),
+            reason,
+        });
+    }
+
+    /// Record an image if it refers to a local file that doesn't exist.
+    pub fn check_image(&self, block: &Block, base_dir: &Path, page_path: &Path, url: &str) {
+        let is_remote = url.contains("://") || url.starts_with("data:") || url.starts_with("http");
+        if !is_remote && image_full_path(base_dir, page_path, url).is_none() {
+            self.add(block, LinkKind::Image, url, LinkProblemReason::MissingImage);
+        }
+    }
+
+    /// Record a link, tag, or page embed if the page it refers to can't be used for it.
+    pub fn check_page(&self, block: &Block, kind: LinkKind, title: &str, page: Option<&IdSlugUid>) {
+        let reason = match page {
+            None => LinkProblemReason::MissingPage,
+            Some(p) if kind == LinkKind::PageEmbed && !p.allow_embed => {
+                LinkProblemReason::UnexportedPage
+            }
+            Some(p) if kind != LinkKind::PageEmbed && !p.include => {
+                LinkProblemReason::UnexportedPage
+            }
+            Some(_) => return,
+        };
+
+        self.add(block, kind, title, reason);
+    }
+
+    /// Record a block reference that didn't match any block. Since the `((...))` syntax can also
+    /// hold plain text, only text that looks like a uid counts.
+    pub fn check_missing_block_ref(&self, block: &Block, uid: &str) {
+        if !uid.is_empty() && !uid.contains(char::is_whitespace) {
+            self.add(
+                block,
+                LinkKind::BlockRef,
+                uid,
+                LinkProblemReason::MissingBlock,
+            );
+        }
+    }
+
+    pub fn take(&self) -> Vec<LinkProblem> {
+        self.0.take()
+    }
+}
+
+/// An entry in `link-report.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LinkReportEntry {
+    /// The title of the page containing the reference
+    pub page: String,
+    /// The slug of the page containing the reference
+    pub slug: String,
+    /// The source file of the page containing the reference
+    pub source: PathBuf,
+    /// The uid of the block containing the reference, if it has one
+    pub block_uid: String,
+    pub kind: LinkKind,
+    /// The page title, block uid, or image path that was referenced
+    pub target: String,
+    pub reason: LinkProblemReason,
+}
+
+impl LinkReportEntry {
+    pub fn new(page: &str, slug: &str, source: &Path, problem: LinkProblem) -> LinkReportEntry {
+        LinkReportEntry {
+            page: page.to_string(),
+            slug: slug.to_string(),
+            source: source.to_path_buf(),
+            block_uid: problem.block_uid,
+            kind: problem.kind,
+            target: problem.target,
+            reason: problem.reason,
+        }
+    }
+}
+
+/// Read the report written by a previous export, if there is one.
+pub fn read_link_report(path: &Path) -> Vec<LinkReportEntry> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+/// The contents of the link report file, sorted so that unchanged problems don't move around.
+pub fn link_report_json(entries: &mut [LinkReportEntry]) -> Result<String> {
+    entries.sort_by(|a, b| {
+        (&a.slug, &a.block_uid, a.kind, &a.target).cmp(&(&b.slug, &b.block_uid, b.kind, &b.target))
+    });
+
+    Ok(serde_json::to_string_pretty(entries)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(uid: &str) -> Block {
+        Block {
+            uid: uid.to_string(),
+            ..Block::test(1, 1, "")
+        }
+    }
+
+    fn page(include: bool, allow_embed: bool) -> IdSlugUid {
+        IdSlugUid {
+            id: 2,
+            output_title: String::from("Target"),
+            slug: String::from("target"),
+            uid: String::new(),
+            include,
+            allow_embed,
+        }
+    }
+
+    #[test]
+    fn records_problems() {
+        let problems = LinkProblems::default();
+        let b = block("abc");
+
+        problems.check_page(&b, LinkKind::Link, "Target", Some(&page(true, false)));
+        problems.check_page(&b, LinkKind::Tag, "Gone", None);
+        problems.check_page(&b, LinkKind::Link, "Private", Some(&page(false, true)));
+        problems.check_page(&b, LinkKind::PageEmbed, "Target", Some(&page(true, false)));
+        problems.check_missing_block_ref(&b, "missing-uid");
+        problems.check_missing_block_ref(&b, "just some text");
+
+        let found = problems
+            .take()
+            .into_iter()
+            .map(|p| (p.block_uid, p.kind, p.target, p.reason))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    String::from("abc"),
+                    LinkKind::Tag,
+                    String::from("Gone"),
+                    LinkProblemReason::MissingPage
+                ),
+                (
+                    String::from("abc"),
+                    LinkKind::Link,
+                    String::from("Private"),
+                    LinkProblemReason::UnexportedPage
+                ),
+                (
+                    String::from("abc"),
+                    LinkKind::PageEmbed,
+                    String::from("Target"),
+                    LinkProblemReason::UnexportedPage
+                ),
+                (
+                    String::from("abc"),
+                    LinkKind::BlockRef,
+                    String::from("missing-uid"),
+                    LinkProblemReason::MissingBlock
+                ),
+            ]
+        );
+        assert!(problems.take().is_empty());
+    }
+}
@@ -2,6 +2,7 @@ use std::io::BufRead;
 
 use ahash::HashMap;
 use eyre::{eyre, Result};
+use itertools::{put_back, Itertools};
 use nom::{
     branch::alt,
     bytes::complete::{tag, take_while, take_while1},
@@ -43,41 +44,84 @@ pub struct LogseqRawBlock {
     pub attrs: HashMap<String, AttrList>,
 }
 
+/// Assigns parents to blocks based on their indent levels as they are added to the list.
+#[derive(Default)]
+pub struct BlockNesting {
+    current_indent: u32,
+    current_parent: Option<usize>,
+}
+
+impl BlockNesting {
+    pub fn push(&mut self, blocks: &mut Vec<LogseqRawBlock>, mut block: LogseqRawBlock) {
+        if block.indent > self.current_indent {
+            // A new child of the previous block.
+            // A block with nothing before it to nest under is a root block.
+            self.current_parent = blocks.len().checked_sub(1);
+        } else if block.indent < self.current_indent {
+            // Going up a level. Find the most recent block in the list
+            // with the same indent level, and use its parent.
+            self.current_parent = blocks
+                .iter()
+                .rfind(|b| b.indent == block.indent)
+                .and_then(|block| block.parent_idx);
+        }
+        // otherwise it's a sibling so it has the same parent
+
+        self.current_indent = block.indent;
+        block.parent_idx = self.current_parent;
+
+        blocks.push(block);
+    }
+}
+
 pub fn parse_raw_blocks(
     blocks: &mut Vec<LogseqRawBlock>,
     lines: &mut LinesIterator<impl BufRead>,
 ) -> Result<()> {
-    let mut current_indent = 0;
-    let mut current_parent: Option<usize> = None;
+    let mut nesting = BlockNesting::default();
     loop {
         match read_raw_block(lines)? {
             RawBlockOutput::Done => break,
             RawBlockOutput::Empty => {}
-            RawBlockOutput::Block(mut block) => {
-                if block.indent > current_indent {
-                    // A new child of the previous block.
-                    current_parent = Some(blocks.len() - 1);
-                } else if block.indent < current_indent {
-                    // Going up a level. Find the most recent block in the list
-                    // with the same indent level, and use its parent.
-                    current_parent = blocks
-                        .iter()
-                        .rfind(|b| b.indent == block.indent)
-                        .and_then(|block| block.parent_idx);
-                }
-                // otherwise it's a sibling so it has the same parent
-
-                current_indent = block.indent;
-                block.parent_idx = current_parent;
-
-                blocks.push(block);
-            }
+            RawBlockOutput::Block(block) => nesting.push(blocks, block),
         }
     }
 
     Ok(())
 }
 
+/// Parse the text of a single block, such as the block contents in Logseq's graph exports, which
+/// come without the outline structure of a page file.
+pub fn parse_block_content(content: &str) -> Result<Option<LogseqRawBlock>> {
+    let text = content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| match (i, line.is_empty()) {
+            (0, _) => format!("- {line}"),
+            (_, true) => String::new(),
+            (_, false) => format!("  {line}"),
+        })
+        .join("\n");
+
+    let mut lines = put_back(std::io::Cursor::new(text).lines());
+    match read_raw_block(&mut lines)? {
+        RawBlockOutput::Block(block) => Ok(Some(block)),
+        RawBlockOutput::Done | RawBlockOutput::Empty => Ok(None),
+    }
+}
+
+/// Gather the hashtags in the block's contents.
+pub fn content_hashtags(contents: &BlockContent) -> AttrList {
+    contents
+        .borrow_parsed()
+        .iter()
+        .filter_map(|ex| match ex {
+            Expression::Hashtag(tag, _) => Some(tag.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
 enum RawBlockOutput {
     Done,
     Empty,
@@ -190,13 +234,7 @@ fn read_raw_block(lines: &mut LinesIterator<impl BufRead>) -> Result<RawBlockOut
 
     let contents = line_contents.join("\n");
     let parsed = BlockContent::new_parsed(parse_string::ContentStyle::Logseq, contents)?;
-
-    let mut tags = AttrList::new();
-    for ex in parsed.borrow_parsed() {
-        if let Expression::Hashtag(tag, _) = ex {
-            tags.push(tag.to_string());
-        }
-    }
+    let tags = content_hashtags(&parsed);
 
     let block_contents = LogseqRawBlock {
         id,
@@ -24,6 +24,22 @@ pub enum PageMatchType {
     ByHash,
 }
 
+/// The slug and output file of an exported page.
+pub struct PageSlug<'a> {
+    pub filename: &'a Path,
+    pub slug: &'a str,
+    pub output_path: &'a str,
+}
+
+/// A slug that a page was exported at before its slug changed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PreviousSlug {
+    pub old_slug: String,
+    pub old_output_path: String,
+    /// The page's source file, relative to the graph directory
+    pub filename: String,
+}
+
 pub struct MetadataDbPageUpdate {
     pub match_type: Option<PageMatchType>,
     pub entry: MetadataDbPage,
@@ -69,6 +85,7 @@ impl MetadataDb {
         let migrations = Migrations::new(vec![
             M::up(include_str!("./migrations/0001-initial.sql")),
             M::up(include_str!("./migrations/0002-images.sql")),
+            M::up(include_str!("./migrations/0003-slugs.sql")),
         ]);
 
         migrations.to_latest(&mut conn)?;
@@ -83,6 +100,15 @@ impl MetadataDb {
         })))
     }
 
+    /// The name that the database uses for a page's file.
+    pub fn relative_filename(&self, filename: &Path) -> String {
+        filename
+            .strip_prefix(&self.0.root_path)
+            .unwrap_or(filename)
+            .to_string_lossy()
+            .to_string()
+    }
+
     /// Look up a page by filename, or if the filename is not present, then look it up by hash to
     /// see if it was renamed.
     pub fn lookup_page(
@@ -90,17 +116,14 @@ impl MetadataDb {
         filename: &Path,
         hash: &[u8],
     ) -> Result<Option<(PageMatchType, MetadataDbPage)>> {
-        let check_path = filename
-            .strip_prefix(&self.0.root_path)
-            .unwrap_or(filename)
-            .to_string_lossy();
+        let check_path = self.relative_filename(filename);
 
         let conn = self.0.read_pool.get()?;
         let mut stmt = conn.prepare_cached(
             "SELECT filename, hash, created_at, edited_at FROM pages WHERE filename = ?",
         )?;
         let filename_row = stmt
-            .query_row(params![check_path.as_ref()], |row| {
+            .query_row(params![check_path.as_str()], |row| {
                 MetadataDbPage::try_from(row)
             })
             .optional()?;
@@ -120,6 +143,84 @@ impl MetadataDb {
         Ok(hash_row.map(|row| (PageMatchType::ByHash, row)))
     }
 
+    /// Save the slug of each exported page, and remember the previous slug of any page whose slug
+    /// changed. Since renamed files are matched up by their contents when the graph is read, this
+    /// also catches slugs that changed because a page was renamed. Pages only have a slug saved if
+    /// the graph reader tracks them in the database, which Logseq graphs do.
+    ///
+    /// Returns every previous slug remembered so far. The changes are discarded unless `save` is
+    /// true, for dry runs.
+    pub fn update_page_slugs(&self, pages: &[PageSlug], save: bool) -> Result<Vec<PreviousSlug>> {
+        let mut conn = self.0.write_conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        {
+            let mut lookup_stmt =
+                tx.prepare_cached("SELECT slug, output_path FROM pages WHERE filename = ?")?;
+            let mut update_stmt =
+                tx.prepare_cached("UPDATE pages SET slug = ?, output_path = ? WHERE filename = ?")?;
+            let mut add_redirect_stmt = tx.prepare_cached(
+                r##"INSERT INTO slug_redirects (old_slug, old_output_path, filename)
+                VALUES (?, ?, ?)
+                ON CONFLICT DO UPDATE SET
+                    old_output_path=EXCLUDED.old_output_path,
+                    filename=EXCLUDED.filename"##,
+            )?;
+            let mut remove_redirect_stmt =
+                tx.prepare_cached("DELETE FROM slug_redirects WHERE old_slug = ?")?;
+
+            for page in pages {
+                let filename = self.relative_filename(page.filename);
+                let previous = lookup_stmt
+                    .query_row(params![filename.as_str()], |row| {
+                        Ok((
+                            row.get::<_, Option<String>>(0)?,
+                            row.get::<_, Option<String>>(1)?,
+                        ))
+                    })
+                    .optional()?;
+
+                let Some((old_slug, old_output_path)) = previous else {
+                    continue;
+                };
+
+                if let (Some(old_slug), Some(old_output_path)) = (old_slug, old_output_path) {
+                    if old_slug != page.slug {
+                        add_redirect_stmt.execute(params![
+                            old_slug,
+                            old_output_path,
+                            filename.as_str()
+                        ])?;
+                    }
+                }
+
+                // A page has this slug now, so it shouldn't redirect anywhere else.
+                remove_redirect_stmt.execute(params![page.slug])?;
+                update_stmt.execute(params![page.slug, page.output_path, filename.as_str()])?;
+            }
+        }
+
+        let previous_slugs = {
+            let mut stmt = tx.prepare_cached(
+                "SELECT old_slug, old_output_path, filename FROM slug_redirects ORDER BY old_slug",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok(PreviousSlug {
+                    old_slug: row.get(0)?,
+                    old_output_path: row.get(1)?,
+                    filename: row.get(2)?,
+                })
+            })?;
+            rows.collect::<Result<Vec<_>, _>>()?
+        };
+
+        if save {
+            tx.commit()?;
+        }
+
+        Ok(previous_slugs)
+    }
+
     pub fn get_image(&self, image: &Image) -> Result<Option<PicStoreImageData>> {
         let conn = self.0.read_pool.get()?;
         let mut stmt = conn.prepare_cached(
@@ -178,3 +279,77 @@ impl Deref for MetadataDb {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn previous_slugs() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = MetadataDb::new(dir.path().to_path_buf()).unwrap();
+        db.write_conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO pages (filename, hash, created_at, edited_at) VALUES ('pages/a.md', x'00', 0, 0)",
+                [],
+            )
+            .unwrap();
+
+        let filename = dir.path().join("pages/a.md");
+        let update = |slug: &str, save: bool| {
+            db.update_page_slugs(
+                &[
+                    PageSlug {
+                        filename: &filename,
+                        slug,
+                        output_path: &format!("out{slug}.html"),
+                    },
+                    // Not tracked in the database, so it's ignored.
+                    PageSlug {
+                        filename: Path::new("pages/untracked.md"),
+                        slug: "/untracked",
+                        output_path: "out/untracked.html",
+                    },
+                ],
+                save,
+            )
+            .unwrap()
+        };
+
+        assert!(update("/a", true).is_empty());
+        assert!(update("/a", true).is_empty());
+
+        // A dry run sees the change but doesn't keep it.
+        assert_eq!(update("/b", false).len(), 1);
+        assert!(update("/a", true).is_empty());
+
+        update("/b", true);
+        assert_eq!(
+            update("/c", true),
+            vec![
+                PreviousSlug {
+                    old_slug: String::from("/a"),
+                    old_output_path: String::from("out/a.html"),
+                    filename: String::from("pages/a.md"),
+                },
+                PreviousSlug {
+                    old_slug: String::from("/b"),
+                    old_output_path: String::from("out/b.html"),
+                    filename: String::from("pages/a.md"),
+                },
+            ]
+        );
+
+        // Going back to an earlier slug stops redirecting it.
+        let previous = update("/a", true);
+        assert_eq!(
+            previous
+                .iter()
+                .map(|p| p.old_slug.as_str())
+                .collect::<Vec<_>>(),
+            vec!["/b", "/c"]
+        );
+    }
+}
@@ -0,0 +1,477 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+
+use ahash::{HashMap, HashMapExt};
+use edn_rs::Edn;
+use eyre::{eyre, Result, WrapErr};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+use smallvec::SmallVec;
+
+use super::{
+    blocks::{content_hashtags, parse_block_content},
+    BlockFormat, JsonBlock,
+};
+use crate::{
+    content::BlockContent,
+    graph::{AttrList, Block, BlockInclude, ListType, ParsedPage, ViewType},
+    parse_string::ContentStyle,
+};
+
+/// Properties that the block parser handles itself instead of keeping as attributes.
+const SPECIAL_PROPERTIES: &[&str] = &["id", "view-mode", "logseq.order-list-type", "collapsed"];
+
+/// Journal pages aren't marked in the export, so recognize titles in Logseq's default journal
+/// format, like "Oct 16th, 2023".
+static JOURNAL_TITLE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[A-Z][a-z]{2} \d{1,2}(st|nd|rd|th), \d{4}$").unwrap());
+
+#[derive(Deserialize)]
+struct JsonExport {
+    blocks: Vec<JsonBlock>,
+}
+
+/// Read the JSON or EDN file from Logseq's "Export graph" menu.
+pub fn graph_from_logseq_export(path: &Path) -> Result<(bool, Vec<ParsedPage>)> {
+    let raw =
+        std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+
+    let blocks = if path.extension().map(|e| e == "edn").unwrap_or(false) {
+        blocks_from_edn(&raw)?
+    } else {
+        serde_json::from_str::<JsonExport>(&raw)
+            .with_context(|| format!("Parsing {}", path.display()))?
+            .blocks
+    };
+
+    // Pages without timestamps of their own get the time of the export.
+    let file_time = std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+
+    let mut reader = ExportReader {
+        next_id: 0,
+        path: path.to_path_buf(),
+        file_time,
+    };
+
+    let pages = blocks
+        .into_iter()
+        .filter(|block| block.page_name.is_some())
+        .map(|page| reader.read_page(page))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok((false, pages))
+}
+
+struct ExportReader {
+    next_id: usize,
+    path: PathBuf,
+    file_time: u64,
+}
+
+impl ExportReader {
+    fn read_page(&mut self, page: JsonBlock) -> Result<ParsedPage> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut attrs = page
+            .properties
+            .iter()
+            .map(|(name, value)| (name.clone(), property_values(value)))
+            .collect::<HashMap<_, _>>();
+
+        let page_name = page.page_name.unwrap_or_default();
+        let title = attrs
+            .remove("title")
+            .and_then(|values| values.into_iter().next())
+            .unwrap_or(page_name);
+        let tags = attrs.get("tags").cloned().unwrap_or_default();
+        let view_type = attrs
+            .get("view-mode")
+            .and_then(|values| values.first())
+            .map(ViewType::from)
+            .unwrap_or_default();
+
+        let edit_time = page.updated_at.unwrap_or(self.file_time);
+        let create_time = page.created_at.unwrap_or(edit_time);
+        let is_journal = JOURNAL_TITLE.is_match(&title);
+
+        let mut blocks = HashMap::new();
+        let mut children = SmallVec::new();
+        for (order, child) in page.children.into_iter().enumerate() {
+            children.push(self.read_block(&mut blocks, child, id, id, order, is_journal)?);
+        }
+
+        blocks.insert(
+            id,
+            Block {
+                id,
+                uid: page.id,
+                include_type: BlockInclude::IfChildrenPresent,
+                containing_page: id,
+                page_title: Some(title),
+                original_title: None,
+                is_journal,
+                contents: BlockContent::new_empty(ContentStyle::Logseq),
+                heading: 0,
+                view_type,
+                this_block_list_type: ListType::Default,
+                create_time,
+                edit_time,
+                children,
+
+                extra_classes: Vec::new(),
+                content_element: None,
+                wrapper_element: None,
+
+                tags,
+                attrs,
+                parent: None,
+                order: 0,
+            },
+        );
+
+        Ok(ParsedPage {
+            path: self.path.clone(),
+            root_block: id,
+            blocks,
+            whiteboard: None,
+        })
+    }
+
+    /// Add a block and its children to `blocks`, returning the block's id.
+    fn read_block(
+        &mut self,
+        blocks: &mut HashMap<usize, Block>,
+        input: JsonBlock,
+        page_id: usize,
+        parent_id: usize,
+        order: usize,
+        is_journal: bool,
+    ) -> Result<usize> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let content = input.content.unwrap_or_default();
+        let (contents, tags, mut attrs, heading, view_type, this_block_list_type) =
+            if input.format == Some(BlockFormat::Org) {
+                let contents = BlockContent::new_parsed(ContentStyle::Org, content)?;
+                let tags = content_hashtags(&contents);
+                (
+                    contents,
+                    tags,
+                    HashMap::default(),
+                    0,
+                    ViewType::Inherit,
+                    ListType::Default,
+                )
+            } else {
+                match parse_block_content(&content)
+                    .with_context(|| format!("Parsing block {}", input.id))?
+                {
+                    Some(raw) => (
+                        raw.contents,
+                        raw.tags,
+                        raw.attrs,
+                        raw.header_level as usize,
+                        raw.view_type,
+                        raw.this_block_list_type,
+                    ),
+                    None => (
+                        BlockContent::new_empty(ContentStyle::Logseq),
+                        AttrList::new(),
+                        HashMap::default(),
+                        0,
+                        ViewType::Inherit,
+                        ListType::Default,
+                    ),
+                }
+            };
+
+        // The exported properties usually duplicate the property lines in the content, but fill
+        // in any that were only in the properties.
+        for (name, value) in &input.properties {
+            if !SPECIAL_PROPERTIES.contains(&name.as_str()) && !attrs.contains_key(name) {
+                attrs.insert(name.clone(), property_values(value));
+            }
+        }
+
+        let mut children = SmallVec::new();
+        for (child_order, child) in input.children.into_iter().enumerate() {
+            children.push(self.read_block(blocks, child, page_id, id, child_order, is_journal)?);
+        }
+
+        blocks.insert(
+            id,
+            Block {
+                id,
+                uid: input.id,
+                include_type: BlockInclude::default(),
+                order,
+                parent: Some(parent_id),
+                children,
+                attrs,
+                tags,
+                create_time: input.created_at.unwrap_or(0),
+                edit_time: input.updated_at.unwrap_or(0),
+                view_type,
+                this_block_list_type,
+                contents,
+                heading: input.heading_level.unwrap_or(heading),
+                is_journal,
+                page_title: None,
+                original_title: None,
+                containing_page: page_id,
+                extra_classes: Vec::new(),
+                content_element: None,
+                wrapper_element: None,
+            },
+        );
+
+        Ok(id)
+    }
+}
+
+/// Convert a property value from the export into attribute values.
+fn property_values(value: &serde_json::Value) -> AttrList {
+    match value {
+        serde_json::Value::Null => AttrList::new(),
+        serde_json::Value::String(s) => smallvec::smallvec![s.clone()],
+        serde_json::Value::Array(values) => values.iter().flat_map(property_values).collect(),
+        other => smallvec::smallvec![other.to_string()],
+    }
+}
+
+/// Get a value from an EDN map, where the key may or may not have the `:block/` namespace.
+fn edn_field<'e>(edn: &'e Edn, name: &str) -> Option<&'e Edn> {
+    edn.get(format!(":block/{name}"))
+        .or_else(|| edn.get(format!(":{name}")))
+}
+
+fn edn_string(edn: Option<&Edn>) -> Option<String> {
+    match edn? {
+        Edn::Str(s) | Edn::Uuid(s) => Some(s.clone()),
+        Edn::Key(k) => Some(k.trim_start_matches(':').to_string()),
+        _ => None,
+    }
+}
+
+fn edn_to_json(edn: &Edn) -> serde_json::Value {
+    match edn {
+        Edn::Nil | Edn::Empty => serde_json::Value::Null,
+        Edn::Bool(b) => serde_json::Value::Bool(*b),
+        Edn::Int(i) => serde_json::Value::from(*i),
+        Edn::UInt(u) => serde_json::Value::from(*u),
+        Edn::Double(_) | Edn::Rational(_) => edn
+            .to_float()
+            .map(serde_json::Value::from)
+            .unwrap_or(serde_json::Value::Null),
+        Edn::Vector(_) | Edn::List(_) => {
+            serde_json::Value::Array(edn.iter_some().unwrap().map(edn_to_json).collect())
+        }
+        Edn::Set(_) => serde_json::Value::Array(edn.set_iter().unwrap().map(edn_to_json).collect()),
+        Edn::Str(_) | Edn::Uuid(_) | Edn::Key(_) => {
+            serde_json::Value::String(edn_string(Some(edn)).unwrap_or_default())
+        }
+        other => serde_json::Value::String(other.to_string()),
+    }
+}
+
+fn block_from_edn(edn: &Edn) -> Result<JsonBlock> {
+    let id = edn_string(edn_field(edn, "id")).ok_or_else(|| eyre!("Block has no id: {edn}"))?;
+
+    let properties = edn_field(edn, "properties")
+        .and_then(|p| p.map_iter())
+        .map(|props| {
+            props
+                .map(|(name, value)| (name.trim_start_matches(':').to_string(), edn_to_json(value)))
+                .collect::<HashMap<_, _>>()
+        })
+        .unwrap_or_default();
+
+    let children = edn_field(edn, "children")
+        .and_then(|c| c.iter_some())
+        .map(|children| children.map(block_from_edn).collect::<Result<Vec<_>>>())
+        .transpose()?
+        .unwrap_or_default();
+
+    let format = edn_string(edn_field(edn, "format")).map(|f| match f.as_str() {
+        "markdown" => BlockFormat::Markdown,
+        "org" => BlockFormat::Org,
+        _ => BlockFormat::Unknown,
+    });
+
+    let number = |name: &str| edn_field(edn, name).and_then(|n| n.to_uint());
+
+    Ok(JsonBlock {
+        id,
+        page_name: edn_string(edn_field(edn, "page-name")),
+        properties,
+        children,
+        format,
+        content: edn_string(edn_field(edn, "content")),
+        heading_level: number("heading-level"),
+        created_at: number("created-at").map(|t| t as u64),
+        updated_at: number("updated-at").map(|t| t as u64),
+    })
+}
+
+fn blocks_from_edn(raw: &str) -> Result<Vec<JsonBlock>> {
+    let data = Edn::from_str(raw)?;
+    data.get(":blocks")
+        .and_then(|b| b.iter_some())
+        .ok_or_else(|| eyre!("Export has no :blocks list"))?
+        .map(block_from_edn)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(blocks: Vec<JsonBlock>) -> Vec<ParsedPage> {
+        let mut reader = ExportReader {
+            next_id: 0,
+            path: PathBuf::from("export.json"),
+            file_time: 1000,
+        };
+
+        blocks
+            .into_iter()
+            .map(|page| reader.read_page(page).unwrap())
+            .collect()
+    }
+
+    fn check_page(page: &ParsedPage) {
+        let root = &page.blocks[&page.root_block];
+        assert_eq!(root.page_title.as_deref(), Some("Test Page"));
+        assert_eq!(root.uid, "6440ad5b-0000-0000-0000-000000000001");
+        assert_eq!(root.tags.as_slice(), &["a", "b"]);
+        assert_eq!(root.children.len(), 2);
+
+        let heading = &page.blocks[&root.children[0]];
+        assert_eq!(heading.uid, "6440ad5b-0000-0000-0000-000000000002");
+        assert_eq!(heading.heading, 2);
+        assert_eq!(heading.contents.borrow_string(), "A heading");
+        assert_eq!(heading.children.len(), 1);
+
+        let child = &page.blocks[&heading.children[0]];
+        assert_eq!(child.parent, Some(root.children[0]));
+        assert_eq!(child.uid, "6440ad5b-0000-0000-0000-000000000003");
+        assert_eq!(child.tags.as_slice(), &["tag"]);
+        assert_eq!(child.attrs["rating"].as_slice(), &["5"]);
+
+        let second = &page.blocks[&root.children[1]];
+        assert_eq!(second.order, 1);
+        assert_eq!(second.contents.borrow_string(), "Second");
+    }
+
+    #[test]
+    fn json() {
+        let source = r###"{
+          "version": 1,
+          "blocks": [{
+            "id": "6440ad5b-0000-0000-0000-000000000001",
+            "page-name": "Test Page",
+            "properties": {"tags": ["a", "b"]},
+            "format": "markdown",
+            "children": [
+              {
+                "id": "6440ad5b-0000-0000-0000-000000000002",
+                "format": "markdown",
+                "content": "## A heading",
+                "children": [{
+                  "id": "6440ad5b-0000-0000-0000-000000000003",
+                  "format": "markdown",
+                  "properties": {"rating": 5},
+                  "content": "Some #tag text\nid:: 6440ad5b-0000-0000-0000-000000000003",
+                  "children": []
+                }]
+              },
+              {
+                "id": "6440ad5b-0000-0000-0000-000000000004",
+                "format": "markdown",
+                "content": "Second",
+                "children": []
+              }
+            ]
+          }]
+        }"###;
+
+        let export = serde_json::from_str::<JsonExport>(source).unwrap();
+        let pages = read(export.blocks);
+        assert_eq!(pages.len(), 1);
+        check_page(&pages[0]);
+    }
+
+    #[test]
+    fn edn() {
+        let source = r##"{:version 1,
+          :blocks
+          [{:block/id #uuid "6440ad5b-0000-0000-0000-000000000001",
+            :block/page-name "Test Page",
+            :block/properties {:tags #{"a" "b"}},
+            :block/format :markdown,
+            :block/children
+            [{:block/id #uuid "6440ad5b-0000-0000-0000-000000000002",
+              :block/format :markdown,
+              :block/content "A heading",
+              :block/heading-level 2,
+              :block/children
+              [{:block/id #uuid "6440ad5b-0000-0000-0000-000000000003",
+                :block/format :markdown,
+                :block/properties {:rating 5},
+                :block/content "Some #tag text",
+                :block/children []}]}
+             {:block/id #uuid "6440ad5b-0000-0000-0000-000000000004",
+              :block/format :markdown,
+              :block/content "Second",
+              :block/children []}]}]}"##;
+
+        let pages = read(blocks_from_edn(source).unwrap());
+        assert_eq!(pages.len(), 1);
+        check_page(&pages[0]);
+    }
+
+    #[test]
+    fn journal_titles() {
+        assert!(JOURNAL_TITLE.is_match("Oct 16th, 2023"));
+        assert!(JOURNAL_TITLE.is_match("Jan 1st, 2024"));
+        assert!(!JOURNAL_TITLE.is_match("October notes"));
+    }
+
+    #[test]
+    fn journal_page() {
+        let source = r###"{
+          "version": 1,
+          "blocks": [{
+            "id": "6440ad5b-0000-0000-0000-000000000001",
+            "page-name": "Oct 16th, 2023",
+            "format": "markdown",
+            "children": [{
+              "id": "6440ad5b-0000-0000-0000-000000000002",
+              "format": "markdown",
+              "content": "An entry",
+              "children": []
+            }]
+          }]
+        }"###;
+
+        let export = serde_json::from_str::<JsonExport>(source).unwrap();
+        let pages = read(export.blocks);
+        let root = &pages[0].blocks[&pages[0].root_block];
+        assert!(root.is_journal);
+        assert_eq!(
+            crate::logseq::journal_date(root.page_title.as_deref().unwrap()),
+            chrono::NaiveDate::from_ymd_opt(2023, 10, 16)
+        );
+    }
+}
@@ -0,0 +1,8 @@
+ALTER TABLE pages ADD COLUMN slug TEXT;
+ALTER TABLE pages ADD COLUMN output_path TEXT;
+
+CREATE TABLE slug_redirects (
+  old_slug TEXT PRIMARY KEY,
+  old_output_path TEXT NOT NULL,
+  filename TEXT NOT NULL
+);
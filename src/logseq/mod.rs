@@ -1,9 +1,12 @@
 mod attrs;
-mod blocks;
+pub mod blocks;
 pub mod db;
-mod page_header;
+pub mod export;
+mod org_blocks;
+pub mod page_header;
 #[cfg(test)]
 mod tests;
+mod whiteboard;
 
 use std::{
     fs::File,
@@ -16,8 +19,10 @@ use std::{
 use ahash::{HashMap, HashMapExt};
 use edn_rs::Edn;
 use eyre::{eyre, Result, WrapErr};
-use itertools::{put_back, PutBack};
+use itertools::{put_back, Itertools, PutBack};
+use once_cell::sync::Lazy;
 use rayon::prelude::*;
+use regex::Regex;
 use rusqlite::params;
 use serde::Deserialize;
 use smallvec::{smallvec, SmallVec};
@@ -32,13 +37,17 @@ use crate::{
     parse_string::ContentStyle,
 };
 
-#[derive(Clone, Copy, Deserialize, Debug)]
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum BlockFormat {
     Markdown,
+    Org,
+    #[serde(other)]
     Unknown,
 }
 
+/// A page or block from Logseq's "Export graph" JSON file. Pages are the top-level blocks, and
+/// have a `page_name`.
 #[derive(Deserialize, Debug)]
 pub struct JsonBlock {
     pub id: String,
@@ -46,11 +55,16 @@ pub struct JsonBlock {
     pub page_name: Option<String>,
     #[serde(default)]
     pub properties: HashMap<String, serde_json::Value>,
+    #[serde(default)]
     pub children: Vec<JsonBlock>,
     pub format: Option<BlockFormat>,
     pub content: Option<String>,
     #[serde(rename = "heading-level")]
     pub heading_level: Option<usize>,
+    #[serde(rename = "created-at")]
+    pub created_at: Option<u64>,
+    #[serde(rename = "updated-at")]
+    pub updated_at: Option<u64>,
 }
 
 pub struct PageMetadata {
@@ -61,11 +75,13 @@ pub struct PageMetadata {
 pub struct LogseqGraph {
     next_id: usize,
     root: PathBuf,
+    /// Roll back the page updates to the metadata database instead of saving them.
+    dry_run: bool,
 
     legacy_page_metadata: HashMap<String, PageMetadata>,
 }
 
-type LinesIterator<T> = PutBack<std::io::Lines<T>>;
+pub type LinesIterator<T> = PutBack<std::io::Lines<T>>;
 
 impl LogseqGraph {
     // This is a weird way to do it since the "constructor" returns a Graph instead of a
@@ -74,19 +90,187 @@ impl LogseqGraph {
     pub fn build(
         path: PathBuf,
         metadata_db: Option<MetadataDb>,
-    ) -> Result<(ContentStyle, bool, Vec<ParsedPage>)> {
+        dry_run: bool,
+    ) -> Result<(bool, Vec<ParsedPage>)> {
+        let mut lsgraph = LogseqGraph::open(path)?;
+        lsgraph.dry_run = dry_run;
+        let pages = lsgraph.read_all(&metadata_db)?;
+        Ok((false, pages))
+    }
+
+    /// Prepare to read the graph at `path`. Watch mode keeps the returned LogseqGraph around so
+    /// that it can reread individual pages as they change.
+    pub fn open(path: PathBuf) -> Result<LogseqGraph> {
         let mut lsgraph = LogseqGraph {
             next_id: 0,
             root: path,
+            dry_run: false,
             legacy_page_metadata: HashMap::default(),
         };
 
         lsgraph.read_legacy_page_metadata()?;
-        let mut pages = lsgraph.read_page_directory("pages", &metadata_db, false)?;
-        let journals = lsgraph.read_page_directory("journals", &metadata_db, true)?;
+        Ok(lsgraph)
+    }
+
+    /// Read every page in the graph.
+    pub fn read_all(&mut self, metadata_db: &Option<MetadataDb>) -> Result<Vec<ParsedPage>> {
+        let mut pages = self.read_page_directory("pages", metadata_db, false)?;
+        let journals = self.read_page_directory("journals", metadata_db, true)?;
+        let whiteboards = self.read_whiteboards()?;
 
         pages.extend(journals.into_iter());
-        Ok((ContentStyle::Logseq, false, pages))
+        pages.extend(whiteboards);
+        Ok(pages)
+    }
+
+    /// Read the whiteboards, if the graph has any.
+    fn read_whiteboards(&mut self) -> Result<Vec<ParsedPage>> {
+        let dir = self.root.join("whiteboards");
+        let files = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries
+                .map(|f| f.map(|f| f.path()))
+                .filter(|f| {
+                    f.as_ref()
+                        .map(|f| f.extension().map(|ext| ext == "edn").unwrap_or(false))
+                        .unwrap_or(true)
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        files
+            .into_iter()
+            .map(|file| {
+                let source = std::fs::read_to_string(&file)
+                    .with_context(|| format!("Reading {}", file.display()))?;
+                let raw =
+                    whiteboard::parse_whiteboard(&source).with_context(|| format!("{file:?}"))?;
+                Ok(self.process_whiteboard(file, raw))
+            })
+            .collect()
+    }
+
+    fn process_whiteboard(&mut self, path: PathBuf, raw: whiteboard::RawWhiteboard) -> ParsedPage {
+        let base_id = self.next_id;
+        self.next_id += raw.blocks.len() + 1;
+
+        let title = raw.title.unwrap_or_else(|| {
+            let stem = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            urlencoding::decode(&stem)
+                .map(|s| s.into_owned())
+                .unwrap_or(stem)
+        });
+
+        let file_time = std::fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let edit_time = raw.updated_time.unwrap_or(file_time);
+        let create_time = raw.created_time.unwrap_or(edit_time);
+
+        let page_block = Block {
+            id: base_id,
+            uid: String::new(),
+            include_type: BlockInclude::IfChildrenPresent,
+            containing_page: base_id,
+            page_title: Some(title),
+            original_title: None,
+            is_journal: false,
+            contents: BlockContent::new_empty(ContentStyle::Logseq),
+            heading: 0,
+            view_type: ViewType::default(),
+            this_block_list_type: crate::graph::ListType::Default,
+            create_time,
+            edit_time,
+            children: SmallVec::new(),
+
+            extra_classes: Vec::new(),
+            content_element: None,
+            wrapper_element: None,
+
+            tags: AttrList::new(),
+            attrs: HashMap::default(),
+            parent: None,
+            order: 0,
+        };
+
+        let ids_by_uid = raw
+            .blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| (block.uid.clone(), base_id + i + 1))
+            .collect::<HashMap<_, _>>();
+
+        let mut blocks = HashMap::with_capacity(raw.blocks.len() + 1);
+        blocks.insert(base_id, page_block);
+
+        for (i, input) in raw.blocks.into_iter().enumerate() {
+            let this_id = base_id + i + 1;
+            let parent_id = input
+                .parent_uid
+                .and_then(|uid| ids_by_uid.get(&uid).copied())
+                .unwrap_or(base_id);
+
+            let contents = BlockContent::new_parsed(ContentStyle::Logseq, input.content)
+                .unwrap_or_else(|_| BlockContent::new_empty(ContentStyle::Logseq));
+
+            let block = Block {
+                id: this_id,
+                uid: input.uid,
+                include_type: BlockInclude::default(),
+                order: i,
+                parent: Some(parent_id),
+                children: SmallVec::new(),
+                attrs: HashMap::default(),
+                tags: AttrList::new(),
+                create_time: 0,
+                edit_time: 0,
+                view_type: ViewType::default(),
+                this_block_list_type: crate::graph::ListType::Default,
+                contents,
+                heading: 0,
+                is_journal: false,
+                page_title: None,
+                original_title: None,
+                containing_page: base_id,
+                extra_classes: Vec::new(),
+                content_element: None,
+                wrapper_element: None,
+            };
+
+            blocks.insert(this_id, block);
+        }
+
+        // Link the children after all the blocks exist, since a parent can come after its
+        // children in the file.
+        let children = blocks
+            .values()
+            .filter_map(|block| Some((block.parent?, block.id)))
+            .sorted()
+            .collect::<Vec<_>>();
+        for (parent, child) in children {
+            blocks.get_mut(&parent).unwrap().children.push(child);
+        }
+
+        ParsedPage {
+            root_block: base_id,
+            blocks,
+            path,
+            whiteboard: Some(raw.whiteboard),
+        }
+    }
+
+    /// The directories that contain pages, and whether or not they are journal directories.
+    pub fn page_directories(&self) -> [(PathBuf, bool); 2] {
+        [
+            (self.root.join("pages"), false),
+            (self.root.join("journals"), true),
+        ]
     }
 
     /// Read the pages-metadata.edn file. Logseq does not use this anymore, but if it exists, we read
@@ -152,13 +336,23 @@ impl LogseqGraph {
         let files = std::fs::read_dir(&dir)
             .with_context(|| format!("{dir:?}"))?
             .map(|f| f.map(|f| f.path()))
+            .filter(|f| f.as_ref().map(|f| is_page_file(f)).unwrap_or(true))
             .collect::<Result<Vec<_>, _>>()?;
 
+        self.read_files(&files, metadata_db, is_journal)
+    }
+
+    /// Read a set of page files, all from the same directory.
+    pub fn read_files(
+        &mut self,
+        files: &[PathBuf],
+        metadata_db: &Option<MetadataDb>,
+        is_journal: bool,
+    ) -> Result<Vec<ParsedPage>> {
         let mut raw_pages = files
             .par_iter()
-            .filter(|file| file.extension().map(|ext| ext == "md").unwrap_or(false))
             .map(|file| {
-                read_logseq_md_file(file, metadata_db, is_journal)
+                read_logseq_page_file(file, metadata_db, is_journal)
                     .with_context(|| format!("{file:?}"))
             })
             .collect::<Result<Vec<_>, _>>()?;
@@ -235,7 +429,9 @@ impl LogseqGraph {
                 }
             }
 
-            tx.commit()?;
+            if !self.dry_run {
+                tx.commit()?;
+            }
             output
         } else {
             pages.into_iter().map(|(_, page)| page).collect()
@@ -256,17 +452,9 @@ impl LogseqGraph {
             .and_then(|t| self.legacy_page_metadata.get(&t));
 
         let (default_time, fs_create_time) = if is_journal {
-            let mut i = title.as_deref().unwrap_or_default().splitn(3, '-');
-            let y = i.next().map(|x| x.parse::<i32>());
-            let m = i.next().map(|x| x.parse::<u32>());
-            let d = i.next().map(|x| x.parse::<u32>());
-
-            let default_time = match (y, m, d) {
-                (Some(Ok(y)), Some(Ok(m)), Some(Ok(d))) => chrono::NaiveDate::from_ymd_opt(y, m, d)
-                    .map(|d| d.and_hms(0, 0, 0).timestamp_millis() as u64)
-                    .unwrap_or_default(),
-                _ => 0,
-            };
+            let default_time = journal_date(title.as_deref().unwrap_or_default())
+                .map(|d| d.and_hms(0, 0, 0).timestamp_millis() as u64)
+                .unwrap_or_default();
 
             // For journals we always return the journal's date as the create date
             (default_time, default_time)
@@ -294,6 +482,13 @@ impl LogseqGraph {
                 .unwrap_or(default_time),
         };
 
+        let filename = page
+            .path
+            .strip_prefix(&self.root)
+            .unwrap_or(&page.path)
+            .to_string_lossy()
+            .into_owned();
+
         let (db_update, created_time, updated_time) = match page.metadata_entry.take() {
             Some((match_type, meta)) => {
                 if meta.hash == page.hash {
@@ -304,7 +499,7 @@ impl LogseqGraph {
                         // filename.
                         PageMatchType::ByHash => Some(MetadataDbPageUpdate {
                             match_type: Some(match_type),
-                            entry: meta,
+                            entry: MetadataDbPage { filename, ..meta },
                         }),
                         // The filename didn't change, so there's nothing to do.
                         PageMatchType::ByFilename => None,
@@ -333,13 +528,6 @@ impl LogseqGraph {
             }
             None => {
                 // This is a new entry, so use the filesystem timestamps.
-                let filename = page
-                    .path
-                    .strip_prefix(&self.root)
-                    .unwrap_or(&page.path)
-                    .to_string_lossy()
-                    .into_owned();
-
                 let db_update = MetadataDbPageUpdate {
                     match_type: None,
                     entry: MetadataDbPage {
@@ -458,11 +646,40 @@ impl LogseqGraph {
                 root_block,
                 blocks,
                 path: page.path,
+                whiteboard: None,
             },
         )
     }
 }
 
+/// The date of a journal page, from its title like `2022-09-20` or `Sep 20th, 2022`.
+pub fn journal_date(title: &str) -> Option<chrono::NaiveDate> {
+    static ORDINAL: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\d+)(st|nd|rd|th)\b").unwrap());
+
+    let without_ordinals = ORDINAL.replace_all(title.trim(), "$1");
+    [
+        "%Y-%m-%d",
+        "%Y_%m_%d",
+        "%Y/%m/%d",
+        "%Y%m%d",
+        "%b %d, %Y",
+        "%B %d, %Y",
+    ]
+    .iter()
+    .find_map(|format| chrono::NaiveDate::parse_from_str(&without_ordinals, format).ok())
+}
+
+/// Return true if the file is a page that the graph reader understands.
+pub fn is_page_file(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext == "md" || ext == "org")
+        .unwrap_or(false)
+}
+
+fn is_org_file(path: &Path) -> bool {
+    path.extension().map(|ext| ext == "org").unwrap_or(false)
+}
+
 #[derive(Debug, Eq)]
 struct LogseqRawPage {
     path: PathBuf,
@@ -488,7 +705,7 @@ impl PartialEq for LogseqRawPage {
     }
 }
 
-fn read_logseq_md_file(
+fn read_logseq_page_file(
     filename: &Path,
     metadata_db: &Option<MetadataDb>,
     is_journal: bool,
@@ -542,7 +759,18 @@ fn parse_logseq_file(
     lines: &mut LinesIterator<impl BufRead>,
     is_journal: bool,
 ) -> Result<(HashMap<String, AttrList>, Vec<LogseqRawBlock>)> {
-    let page_attrs_list = page_header::parse_page_header(lines)?;
+    let is_org = is_org_file(filename);
+    let content_style = if is_org {
+        ContentStyle::Org
+    } else {
+        ContentStyle::Logseq
+    };
+
+    let page_attrs_list = if is_org {
+        org_blocks::parse_org_page_header(lines)?
+    } else {
+        page_header::parse_page_header(lines)?
+    };
 
     // Create a block containing the page header attributes so that it will show up in the output
     let attrs_block_contents = page_attrs_list
@@ -558,13 +786,17 @@ fn parse_logseq_file(
 
     for string in attrs_block_contents {
         let attrs_block = LogseqRawBlock {
-            contents: BlockContent::new_parsed(ContentStyle::Logseq, string)?,
+            contents: BlockContent::new_parsed(content_style, string)?,
             ..Default::default()
         };
         blocks.push(attrs_block);
     }
 
-    blocks::parse_raw_blocks(&mut blocks, lines)?;
+    if is_org {
+        org_blocks::parse_org_raw_blocks(&mut blocks, lines)?;
+    } else {
+        blocks::parse_raw_blocks(&mut blocks, lines)?;
+    }
 
     let mut page_attrs = page_attrs_list
         .into_iter()
@@ -589,6 +821,9 @@ fn parse_logseq_file(
     if is_journal {
         // Convert title from 2022_09_20 to 2022-09-20
         title = title.replace('_', "-");
+    } else {
+        // Namespaced pages are saved as Books___Notes.md
+        title = title.replace("___", "/");
     }
 
     page_attrs.insert(String::from(orig_title_key), smallvec![title]);
@@ -0,0 +1,414 @@
+use std::io::BufRead;
+
+use ahash::HashMap;
+use eyre::Result;
+use smallvec::SmallVec;
+
+use super::{
+    attrs::parse_attr_line,
+    blocks::{content_hashtags, BlockNesting, LogseqRawBlock},
+    LinesIterator,
+};
+use crate::{
+    content::BlockContent,
+    graph::{AttrList, ListType, ViewType},
+    parse_string::ContentStyle,
+};
+
+/// Parse the `#+name: value` lines at the top of an org page.
+pub fn parse_org_page_header(
+    lines: &mut LinesIterator<impl BufRead>,
+) -> Result<Vec<(String, AttrList)>> {
+    let mut page_attrs = Vec::new();
+    while let Some(line) = lines.next().transpose()? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let parsed = line
+            .strip_prefix("#+")
+            .filter(|rest| !rest.to_lowercase().starts_with("begin_"))
+            .map(|rest| parse_attr_line(":", rest))
+            .transpose()?
+            .flatten();
+
+        match parsed {
+            Some((name, values)) => page_attrs.push((name.to_lowercase(), values)),
+            None => {
+                lines.put_back(Ok(line));
+                break;
+            }
+        }
+    }
+
+    Ok(page_attrs)
+}
+
+/// If the line is a headline, return its level and text.
+fn headline(line: &str) -> Option<(u32, &str)> {
+    let level = line.chars().take_while(|c| *c == '*').count();
+    if level == 0 {
+        return None;
+    }
+
+    let rest = &line[level..];
+    if rest.is_empty() {
+        Some((level as u32, rest))
+    } else {
+        rest.strip_prefix(' ').map(|text| (level as u32, text))
+    }
+}
+
+/// Split trailing org tags like `:tag1:tag2:` off of a headline.
+fn split_headline_tags(text: &str) -> (&str, AttrList) {
+    let trimmed = text.trim_end();
+    let Some((title, tags)) = trimmed.rsplit_once(char::is_whitespace) else {
+        return (trimmed, AttrList::new());
+    };
+
+    let is_tag_list = tags.len() > 2
+        && tags.starts_with(':')
+        && tags.ends_with(':')
+        && tags[1..tags.len() - 1]
+            .split(':')
+            .all(|t| !t.is_empty() && t.chars().all(|c| c.is_alphanumeric() || "_@#%".contains(c)));
+
+    if is_tag_list {
+        let tags = tags
+            .split(':')
+            .filter(|t| !t.is_empty())
+            .map(|t| t.to_string())
+            .collect();
+        (title.trim_end(), tags)
+    } else {
+        (trimmed, AttrList::new())
+    }
+}
+
+/// Parse a `:name: value` line from a properties drawer.
+fn drawer_property(line: &str) -> Option<(String, String)> {
+    let (name, value) = line.trim().strip_prefix(':')?.split_once(':')?;
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return None;
+    }
+
+    Some((name.to_lowercase(), value.trim().to_string()))
+}
+
+#[derive(Default)]
+struct OrgBlockBuilder {
+    indent: u32,
+    id: String,
+    header_level: u32,
+    view_type: ViewType,
+    this_block_list_type: ListType,
+    collapsed: bool,
+    tags: AttrList,
+    attrs: HashMap<String, AttrList>,
+    lines: SmallVec<[String; 2]>,
+}
+
+impl OrgBlockBuilder {
+    fn add_property(&mut self, name: String, value: String) {
+        match name.as_str() {
+            "id" => self.id = value,
+            "collapsed" => self.collapsed = value == "true",
+            "view-mode" => self.view_type = ViewType::from(value),
+            "logseq.order-list-type" => {
+                if value == "number" {
+                    self.this_block_list_type = ListType::Number;
+                }
+            }
+            "heading" => {
+                self.header_level = match value.parse::<u32>() {
+                    Ok(level) => level,
+                    // `heading:: true` makes a heading at the headline's level.
+                    Err(_) if value == "true" => self.indent + 1,
+                    Err(_) => 0,
+                };
+            }
+            _ => {
+                // Properties stay in the contents as Logseq-style attributes so that they are
+                // rendered the same way as in Markdown pages.
+                let values = parse_attr_line("::", &format!("{name}:: {value}"))
+                    .ok()
+                    .flatten()
+                    .map(|(_, values)| values)
+                    .unwrap_or_default();
+                self.attrs.insert(name.clone(), values);
+                self.lines.push(format!("{name}:: {value}"));
+            }
+        }
+    }
+
+    fn build(self) -> Result<Option<LogseqRawBlock>> {
+        let contents = self.lines.join("\n");
+        if contents.trim().is_empty() && self.id.is_empty() {
+            return Ok(None);
+        }
+
+        let contents = BlockContent::new_parsed(ContentStyle::Org, contents)?;
+        let mut tags = self.tags;
+        tags.extend(content_hashtags(&contents));
+
+        Ok(Some(LogseqRawBlock {
+            id: self.id,
+            parent_idx: None,
+            header_level: self.header_level,
+            contents,
+            view_type: self.view_type,
+            this_block_list_type: self.this_block_list_type,
+            collapsed: self.collapsed,
+            indent: self.indent,
+            tags,
+            attrs: self.attrs,
+        }))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Drawer {
+    None,
+    Properties,
+    /// A drawer that is left out of the output, like `:LOGBOOK:`
+    Skipped,
+}
+
+/// The kind of `#+BEGIN_` block that the parser is inside of.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BeginBlock {
+    None,
+    /// `#+BEGIN_SRC` or `#+BEGIN_EXAMPLE`, which become fenced code
+    Source,
+    /// `#+BEGIN_QUOTE`, which becomes a block quote
+    Quote,
+    /// `#+BEGIN_QUERY`, which the content parser reads as an advanced query
+    Query,
+    /// Any other block, like `#+BEGIN_VERSE`, which keeps its lines but not the markers
+    Other,
+}
+
+/// Parse the blocks of an org page. Each headline starts a block, nested according to the number of
+/// stars relative to the first headline, and the lines after the headline are the rest of the
+/// block's contents.
+pub fn parse_org_raw_blocks(
+    blocks: &mut Vec<LogseqRawBlock>,
+    lines: &mut LinesIterator<impl BufRead>,
+) -> Result<()> {
+    let mut nesting = BlockNesting::default();
+    let mut current = OrgBlockBuilder::default();
+    let mut drawer = Drawer::None;
+    let mut begin_block = BeginBlock::None;
+    let mut first_level = None;
+
+    for line in lines {
+        let line = line?;
+        let trimmed = line.trim();
+        let lowercase = trimmed.to_lowercase();
+
+        if begin_block != BeginBlock::None {
+            if lowercase.starts_with("#+end_") {
+                match begin_block {
+                    BeginBlock::Source => current.lines.push(String::from("```")),
+                    BeginBlock::Query => current.lines.push(trimmed.to_string()),
+                    _ => {}
+                }
+                begin_block = BeginBlock::None;
+            } else if begin_block == BeginBlock::Quote {
+                current.lines.push(format!("> {trimmed}"));
+            } else {
+                current.lines.push(line);
+            }
+            continue;
+        }
+
+        if drawer != Drawer::None {
+            if lowercase == ":end:" {
+                drawer = Drawer::None;
+            } else if drawer == Drawer::Properties {
+                if let Some((name, value)) = drawer_property(trimmed) {
+                    current.add_property(name, value);
+                }
+            }
+            continue;
+        }
+
+        if let Some((level, text)) = headline(&line) {
+            let previous = std::mem::take(&mut current);
+            if let Some(block) = previous.build()? {
+                nesting.push(blocks, block);
+            }
+
+            let (text, tags) = split_headline_tags(text);
+            let first_level = *first_level.get_or_insert(level);
+            current.indent = level.saturating_sub(first_level);
+            current.tags = tags;
+            current.lines.push(text.to_string());
+        } else if lowercase == ":properties:" {
+            drawer = Drawer::Properties;
+        } else if lowercase == ":logbook:" {
+            drawer = Drawer::Skipped;
+        } else if let Some(kind) = lowercase.strip_prefix("#+begin_") {
+            let (name, args) = kind.split_once(char::is_whitespace).unwrap_or((kind, ""));
+            begin_block = match name {
+                "src" | "example" => {
+                    current.lines.push(format!("```{}", args.trim()));
+                    BeginBlock::Source
+                }
+                "quote" => BeginBlock::Quote,
+                "query" => {
+                    current.lines.push(trimmed.to_string());
+                    BeginBlock::Query
+                }
+                _ => BeginBlock::Other,
+            };
+        } else if !trimmed.is_empty() || !current.lines.is_empty() {
+            current.lines.push(trimmed.to_string());
+        }
+    }
+
+    if let Some(block) = current.build()? {
+        nesting.push(blocks, block);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufRead;
+
+    use indoc::indoc;
+    use itertools::put_back;
+    use smallvec::smallvec;
+
+    use super::*;
+    use crate::parse_string::Expression;
+
+    fn parse(input: &str) -> (Vec<(String, AttrList)>, Vec<LogseqRawBlock>) {
+        let mut lines = put_back(std::io::BufReader::new(input.as_bytes()).lines());
+        let attrs = parse_org_page_header(&mut lines).unwrap();
+        let mut blocks = Vec::new();
+        parse_org_raw_blocks(&mut blocks, &mut lines).unwrap();
+        (attrs, blocks)
+    }
+
+    #[test]
+    fn headlines_and_properties() {
+        let (attrs, blocks) = parse(indoc! {r##"
+            #+title: Org Page
+            #+tags: a, b
+
+            * First headline :idea:
+            :PROPERTIES:
+            :id: 6420b7a1-1111-2222-3333-444455556666
+            :source: The Book
+            :END:
+            Some *bold* text
+            ** A child
+            :LOGBOOK:
+            CLOCK: [2023-01-01 Sun 10:00]
+            :END:
+            #+BEGIN_SRC rust
+            * not a headline
+            #+END_SRC
+            * Second
+            "##});
+
+        assert_eq!(
+            attrs,
+            vec![
+                (String::from("title"), smallvec![String::from("Org Page")]),
+                (
+                    String::from("tags"),
+                    smallvec![String::from("a"), String::from("b")]
+                ),
+            ]
+        );
+
+        assert_eq!(blocks.len(), 3);
+
+        assert_eq!(blocks[0].id, "6420b7a1-1111-2222-3333-444455556666");
+        assert_eq!(blocks[0].parent_idx, None);
+        assert_eq!(blocks[0].tags, AttrList::from_iter([String::from("idea")]));
+        assert_eq!(
+            blocks[0].contents.borrow_string(),
+            "First headline\nsource:: The Book\nSome *bold* text"
+        );
+        assert_eq!(
+            blocks[0].attrs.get("source"),
+            Some(&smallvec![String::from("The Book")])
+        );
+
+        assert_eq!(blocks[1].parent_idx, Some(0));
+        assert_eq!(
+            blocks[1].contents.borrow_string(),
+            "A child\n```rust\n* not a headline\n```"
+        );
+
+        assert_eq!(blocks[2].parent_idx, None);
+        assert_eq!(blocks[2].contents.borrow_string(), "Second");
+    }
+
+    #[test]
+    fn text_before_first_headline() {
+        let (attrs, blocks) = parse("Intro text\n* Headline\n");
+        assert!(attrs.is_empty());
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].contents.borrow_string(), "Intro text");
+        assert_eq!(blocks[1].parent_idx, None);
+    }
+
+    #[test]
+    fn starts_below_top_level() {
+        let (_, blocks) = parse(indoc! {r##"
+            ** First
+            *** Child
+            ** Second
+            "##});
+
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[0].indent, 0);
+        assert_eq!(blocks[0].parent_idx, None);
+        assert_eq!(blocks[1].parent_idx, Some(0));
+        assert_eq!(blocks[2].parent_idx, None);
+    }
+
+    #[test]
+    fn begin_blocks() {
+        let (_, blocks) = parse(indoc! {r##"
+            * Quoted
+            #+BEGIN_QUOTE
+              Some [[Page]] text
+              on two lines
+            #+END_QUOTE
+            After the quote
+            #+BEGIN_VERSE
+            A verse
+              indented
+            #+END_VERSE
+            #+BEGIN_SRC python
+              print("hi")
+            #+END_SRC
+            "##});
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(
+            blocks[0].contents.borrow_string(),
+            "Quoted\n> Some [[Page]] text\n> on two lines\nAfter the quote\nA verse\n  indented\n```python\n  print(\"hi\")\n```"
+        );
+        assert_eq!(
+            &blocks[0].contents.borrow_parsed()[..2],
+            &[
+                Expression::Text("Quoted"),
+                Expression::BlockQuote(vec![
+                    Expression::Text("Some "),
+                    Expression::Link("Page"),
+                    Expression::Text(" text"),
+                    Expression::Text("\n"),
+                    Expression::Text("on two lines"),
+                ]),
+            ]
+        );
+    }
+}
@@ -71,14 +71,22 @@ pub fn parse_page_header(
                 }
             };
 
-            let separator = if header_state == HeaderParseState::YamlFrontMatter {
-                ":"
+            let parsed = if header_state == HeaderParseState::YamlFrontMatter {
+                // A YAML block list item adds to the values of the previous attribute.
+                let list_item = line.trim_start().strip_prefix("- ");
+                if let Some((item, (_, values))) = list_item.zip(page_attrs.last_mut()) {
+                    if values.len() == 1 && values[0].is_empty() {
+                        values.clear();
+                    }
+                    values.push(yaml_scalar(item.trim()).to_string());
+                    continue;
+                }
+
+                parse_yaml_attr_line(line.as_str())
             } else {
-                "::"
+                super::attrs::parse_attr_line("::", line.as_str())
             };
 
-            let parsed = super::attrs::parse_attr_line(separator, line.as_str());
-
             match parsed {
                 Ok(Some((attr_name, attr_values))) => page_attrs.push((attr_name, attr_values)),
                 _ => break line,
@@ -93,6 +101,42 @@ pub fn parse_page_header(
     Ok(page_attrs)
 }
 
+/// Remove the quotes from a quoted YAML string.
+fn yaml_scalar(value: &str) -> &str {
+    ['"', '\'']
+        .iter()
+        .find_map(|&quote| value.strip_prefix(quote)?.strip_suffix(quote))
+        .unwrap_or(value)
+}
+
+/// Parse a line of YAML front matter. Besides plain `name: value` lines, this handles the flow
+/// lists like `tags: [a, b]` and the quoted strings that other tools, like Obsidian, write.
+fn parse_yaml_attr_line(line: &str) -> Result<Option<(String, AttrList)>> {
+    let flow_list = line.split_once(':').and_then(|(name, value)| {
+        let items = value.trim().strip_prefix('[')?.strip_suffix(']')?;
+        // `[[link]]` is a page link, not a list.
+        (!items.starts_with('[') && !name.contains(char::is_whitespace)).then_some((name, items))
+    });
+
+    if let Some((name, items)) = flow_list {
+        let values = items
+            .split(',')
+            .map(|item| yaml_scalar(item.trim()).to_string())
+            .filter(|item| !item.is_empty())
+            .collect();
+        return Ok(Some((name.to_string(), values)));
+    }
+
+    let parsed = super::attrs::parse_attr_line(":", line)?;
+    Ok(parsed.map(|(name, values)| {
+        let values = values
+            .iter()
+            .map(|value| yaml_scalar(value).to_string())
+            .collect();
+        (name, values)
+    }))
+}
+
 #[cfg(test)]
 mod test {
 
@@ -204,4 +248,37 @@ mod test {
             )
         );
     }
+
+    #[test]
+    fn yaml_lists() {
+        let input = indoc! { r##"
+                ---
+                aliases: [First, "Second"]
+                tags:
+                  - a
+                  - nested/b
+                cssclass: 'wide'
+                ---
+                Some text
+                "##
+        };
+
+        assert_eq!(
+            run_test(input).unwrap(),
+            (
+                String::from("Some text"),
+                vec![
+                    (
+                        String::from("aliases"),
+                        smallvec![String::from("First"), String::from("Second")]
+                    ),
+                    (
+                        String::from("tags"),
+                        smallvec![String::from("a"), String::from("nested/b")]
+                    ),
+                    (String::from("cssclass"), smallvec![String::from("wide")])
+                ]
+            )
+        );
+    }
 }
@@ -0,0 +1,292 @@
+use std::str::FromStr;
+
+use ahash::HashMap;
+use edn_rs::Edn;
+use eyre::{eyre, Result};
+
+use crate::whiteboard::{Portal, PortalKind, Shape, ShapeKind, Whiteboard};
+
+/// A block of text stored in a whiteboard file. These are shown in the whiteboard through
+/// block portals.
+#[derive(Debug, PartialEq, Eq)]
+pub struct WhiteboardBlock {
+    pub uid: String,
+    pub parent_uid: Option<String>,
+    pub content: String,
+}
+
+#[derive(Debug)]
+pub struct RawWhiteboard {
+    pub title: Option<String>,
+    pub created_time: Option<u64>,
+    pub updated_time: Option<u64>,
+    pub whiteboard: Whiteboard,
+    pub blocks: Vec<WhiteboardBlock>,
+}
+
+fn string(edn: Option<&Edn>) -> Option<String> {
+    match edn? {
+        Edn::Str(s) | Edn::Uuid(s) => Some(s.clone()),
+        Edn::Key(k) => Some(k.trim_start_matches(':').to_string()),
+        _ => None,
+    }
+}
+
+fn number(edn: Option<&Edn>) -> Option<f64> {
+    edn.and_then(|e| e.to_float())
+}
+
+fn point(edn: Option<&Edn>) -> Option<(f64, f64)> {
+    let mut values = edn?.iter_some()?;
+    let x = values.next()?.to_float()?;
+    let y = values.next()?.to_float()?;
+    Some((x, y))
+}
+
+fn timestamp(edn: Option<&Edn>) -> Option<u64> {
+    edn.and_then(|e| e.to_uint()).map(|t| t as u64)
+}
+
+fn is_arrow(edn: Option<&Edn>) -> bool {
+    string(edn).map(|s| s == "arrow").unwrap_or(false)
+}
+
+/// Get the UUID from a reference like `[:block/uuid #uuid "..."]` or `{:block/uuid #uuid "..."}`.
+fn referenced_uuid(edn: Option<&Edn>) -> Option<String> {
+    match edn? {
+        Edn::Map(_) => string(edn?.get(":block/uuid")),
+        Edn::Vector(_) => {
+            let mut values = edn?.iter_some()?;
+            match values.next()? {
+                Edn::Key(k) if k == ":block/uuid" => string(values.next()),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn parse_shape(shape: &Edn, assets: &HashMap<String, String>) -> Option<(Option<String>, Shape)> {
+    let shape_type = string(shape.get(":type"))?;
+    let asset_src = || string(shape.get(":assetId")).and_then(|id| assets.get(&id).cloned());
+
+    let kind = match shape_type.as_str() {
+        "box" => ShapeKind::Box {
+            radius: number(shape.get(":borderRadius")).unwrap_or(0.0),
+        },
+        "ellipse" => ShapeKind::Ellipse,
+        "polygon" => ShapeKind::Polygon {
+            sides: number(shape.get(":sides")).unwrap_or(3.0) as usize,
+        },
+        "text" => ShapeKind::Text {
+            text: string(shape.get(":text")).unwrap_or_default(),
+            font_size: number(shape.get(":fontSize")).unwrap_or(20.0),
+        },
+        "line" => {
+            let handles = shape.get(":handles");
+            let handle = |name: &str| point(handles?.get(name)?.get(":point"));
+            let decorations = shape.get(":decorations");
+            ShapeKind::Line {
+                start: handle(":start").unwrap_or((0.0, 0.0)),
+                end: handle(":end").unwrap_or((0.0, 0.0)),
+                start_arrow: is_arrow(decorations.and_then(|d| d.get(":start"))),
+                end_arrow: is_arrow(decorations.and_then(|d| d.get(":end"))),
+            }
+        }
+        "pencil" | "highlighter" => ShapeKind::Path {
+            points: shape
+                .get(":points")
+                .and_then(|p| p.iter_some())
+                .map(|points| points.filter_map(|p| point(Some(p))).collect())
+                .unwrap_or_default(),
+            highlighter: shape_type == "highlighter",
+        },
+        "logseq-portal" => {
+            let kind = match string(shape.get(":blockType")).as_deref() {
+                Some("B") => PortalKind::Block,
+                _ => PortalKind::Page,
+            };
+            ShapeKind::Portal(Portal {
+                kind,
+                target: string(shape.get(":pageId"))?,
+            })
+        }
+        "image" => ShapeKind::Image { src: asset_src()? },
+        "video" => ShapeKind::Link { url: asset_src()? },
+        "youtube" | "tweet" | "iframe" => ShapeKind::Link {
+            url: string(shape.get(":url"))?,
+        },
+        "html" => ShapeKind::Html {
+            html: string(shape.get(":html"))?,
+        },
+        // Groups and anything else that we don't know how to draw.
+        _ => return None,
+    };
+
+    let fill = if shape.get(":noFill") == Some(&Edn::Bool(true)) {
+        Some(String::from("none"))
+    } else {
+        string(shape.get(":fill"))
+    };
+
+    let shape_data = Shape {
+        kind,
+        point: point(shape.get(":point")).unwrap_or((0.0, 0.0)),
+        size: point(shape.get(":size")).unwrap_or((0.0, 0.0)),
+        rotation: number(shape.get(":rotation")).unwrap_or(0.0),
+        opacity: number(shape.get(":opacity")).unwrap_or(1.0),
+        fill,
+        stroke: string(shape.get(":stroke")),
+        stroke_width: number(shape.get(":strokeWidth")),
+        label: string(shape.get(":label")).unwrap_or_default(),
+    };
+
+    Some((string(shape.get(":index")), shape_data))
+}
+
+/// Parse a whiteboard `.edn` file from a Logseq graph's `whiteboards` directory.
+pub fn parse_whiteboard(source: &str) -> Result<RawWhiteboard> {
+    let data = Edn::from_str(source)?;
+
+    let page = data.get(":pages").and_then(|p| p.iter_some()?.next());
+    let title = page.and_then(|p| {
+        string(p.get(":block/original-name")).or_else(|| string(p.get(":block/name")))
+    });
+    let assets = page
+        .and_then(|p| {
+            p.get(":block/properties")?
+                .get(":logseq.tldraw.page")?
+                .get(":assets")
+        })
+        .and_then(|a| a.iter_some())
+        .map(|assets| {
+            assets
+                .filter_map(|asset| Some((string(asset.get(":id"))?, string(asset.get(":src"))?)))
+                .collect::<HashMap<_, _>>()
+        })
+        .unwrap_or_default();
+
+    let blocks = data
+        .get(":blocks")
+        .and_then(|b| b.iter_some())
+        .ok_or_else(|| eyre!("Whiteboard has no :blocks list"))?;
+
+    let mut shapes = Vec::new();
+    let mut text_blocks = Vec::new();
+    for block in blocks {
+        let properties = block.get(":block/properties");
+        let shape = properties
+            .filter(|p| string(p.get(":ls-type")).as_deref() == Some("whiteboard-shape"))
+            .and_then(|p| p.get(":logseq.tldraw.shape"));
+
+        match shape {
+            Some(shape) => shapes.extend(parse_shape(shape, &assets)),
+            None => {
+                let (Some(uid), Some(content)) = (
+                    string(block.get(":block/uuid")),
+                    string(block.get(":block/content")),
+                ) else {
+                    continue;
+                };
+
+                text_blocks.push(WhiteboardBlock {
+                    uid,
+                    parent_uid: referenced_uuid(block.get(":block/parent")),
+                    content,
+                });
+            }
+        }
+    }
+
+    // Shapes are drawn in the order of their fractional indexes, when they have them.
+    shapes.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    Ok(RawWhiteboard {
+        title,
+        created_time: page.and_then(|p| timestamp(p.get(":block/created-at"))),
+        updated_time: page.and_then(|p| timestamp(p.get(":block/updated-at"))),
+        whiteboard: Whiteboard {
+            shapes: shapes.into_iter().map(|(_, shape)| shape).collect(),
+        },
+        blocks: text_blocks,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r##"{:blocks
+ ({:block/content "",
+   :block/properties
+   {:ls-type :whiteboard-shape,
+    :logseq.tldraw.shape
+    {:type "box", :id "s1", :index "a2", :point [10 20], :size [100 50], :label "Box", :fill "yellow"}},
+   :block/uuid #uuid "6440ad5b-0000-0000-0000-000000000001"}
+  {:block/content "",
+   :block/properties
+   {:ls-type :whiteboard-shape,
+    :logseq.tldraw.shape
+    {:type "line", :id "s2", :index "a1", :point [0 0],
+     :handles {:start {:id "start", :point [0 0]}, :end {:id "end", :point [50.5 10]}},
+     :decorations {:end "arrow"}}},
+   :block/uuid #uuid "6440ad5b-0000-0000-0000-000000000002"}
+  {:block/content "",
+   :block/properties
+   {:ls-type :whiteboard-shape,
+    :logseq.tldraw.shape
+    {:type "logseq-portal", :id "s3", :index "a3", :point [200 0], :size [300 100],
+     :blockType "B", :pageId "6440ad5b-0000-0000-0000-000000000004"}},
+   :block/uuid #uuid "6440ad5b-0000-0000-0000-000000000003"}
+  {:block/content "A note on the board",
+   :block/parent [:block/name "planning"],
+   :block/uuid #uuid "6440ad5b-0000-0000-0000-000000000004"}),
+ :pages
+ ({:block/name "planning",
+   :block/original-name "Planning",
+   :block/type "whiteboard",
+   :block/created-at 1681000000000,
+   :block/updated-at 1682000000000,
+   :block/properties {:ls-type :whiteboard-page, :logseq.tldraw.page {:id "planning", :assets []}}})}
+"##;
+
+    #[test]
+    fn parse() {
+        let parsed = parse_whiteboard(SOURCE).unwrap();
+        assert_eq!(parsed.title.as_deref(), Some("Planning"));
+        assert_eq!(parsed.created_time, Some(1681000000000));
+        assert_eq!(parsed.updated_time, Some(1682000000000));
+
+        let shapes = &parsed.whiteboard.shapes;
+        assert_eq!(shapes.len(), 3);
+        assert_eq!(
+            shapes[0].kind,
+            ShapeKind::Line {
+                start: (0.0, 0.0),
+                end: (50.5, 10.0),
+                start_arrow: false,
+                end_arrow: true,
+            }
+        );
+        assert_eq!(shapes[1].kind, ShapeKind::Box { radius: 0.0 });
+        assert_eq!(shapes[1].point, (10.0, 20.0));
+        assert_eq!(shapes[1].label, "Box");
+        assert_eq!(shapes[1].fill.as_deref(), Some("yellow"));
+        assert_eq!(
+            shapes[2].kind,
+            ShapeKind::Portal(Portal {
+                kind: PortalKind::Block,
+                target: String::from("6440ad5b-0000-0000-0000-000000000004"),
+            })
+        );
+
+        assert_eq!(
+            parsed.blocks,
+            vec![WhiteboardBlock {
+                uid: String::from("6440ad5b-0000-0000-0000-000000000004"),
+                parent_uid: None,
+                content: String::from("A note on the board"),
+            }]
+        );
+    }
+}
@@ -1,52 +1,66 @@
 mod config;
 mod content;
+mod diff;
+mod epub;
+mod feed;
 mod graph;
 mod html;
 mod image;
+mod journal_archive;
+mod link_report;
 mod logseq;
 mod make_pages;
+mod markdown;
+mod mentions;
+mod namespace;
+mod obsidian;
+mod output_plan;
 mod page;
 mod parse_string;
 #[cfg(test)]
 mod parse_string_tests;
 mod pic_store;
+mod plain_text;
+mod query;
+mod redirect;
+mod references;
 mod roam_edn;
+mod roam_json;
 mod script;
+mod search;
+mod serve;
+mod sitemap;
 mod string_builder;
 mod syntax_highlight;
+mod tag_index;
 mod template;
-use std::{fs::File, io::Read};
+mod watch;
+mod whiteboard;
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
 
+use ahash::HashSet;
 use config::Config;
-use eyre::{Result, WrapErr};
+use eyre::{eyre, Result, WrapErr};
 use zip::read::ZipArchive;
 
-use crate::{config::PkmProduct, make_pages::make_pages_from_script};
+use crate::{
+    config::PkmProduct,
+    graph::ParsedPage,
+    make_pages::{make_pages_from_script, ExportInput},
+    script::PageScript,
+};
 
 fn main() -> Result<()> {
     color_eyre::install()?;
 
     let config = Config::load()?;
 
-    let hbars = template::create(config.template.as_deref())?;
-    let mut templates = template::DedupingTemplateRegistry::new(hbars);
-    if let Some(path) = config.template.as_deref() {
-        templates.add_file_with_key("default".to_string(), path)?;
-    }
-
-    if let Some(path) = config
-        .pic_store
-        .as_ref()
-        .and_then(|ps| ps.template.as_deref())
-    {
-        templates.add_file_with_key("default_picture".to_string(), path)?;
-    } else {
-        // Use the default picture template if none was provided.
-        templates.add_template(
-            "default_picture".to_string(),
-            image::DEFAULT_PICTURE_TEMPLATE.to_string(),
-        )?;
-    }
+    let mut templates = template::create_registry(&config)?;
+    let script = PageScript::compile(&config.script)?;
 
     let highlight_class_prefix = config.highlight_class_prefix.clone().map(|p| {
         // syntect requires a &`static str, so intentionally leak the string into the
@@ -59,29 +73,40 @@ fn main() -> Result<()> {
     let metadata_db = (config.track_logseq_timestamps || config.pic_store.is_some())
         .then(|| {
             let base_dir = match config.product {
-                PkmProduct::Roam => dirs::config_dir().unwrap().join("export-logseq-notes"),
-                PkmProduct::Logseq => config.path.clone(),
+                PkmProduct::Roam | PkmProduct::LogseqExport => {
+                    dirs::config_dir().unwrap().join("export-logseq-notes")
+                }
+                PkmProduct::Logseq | PkmProduct::Obsidian => config.path.clone(),
             };
 
             logseq::db::MetadataDb::new(base_dir)
         })
         .transpose()?;
 
-    let (content_style, explicit_ordering, parsed_pages) = match config.product {
-        PkmProduct::Roam => {
-            let mut f = File::open(&config.path)
-                .with_context(|| format!("Opening {}", config.path.display()))?;
-            let mut raw_data = String::new();
-            if config.path.extension().map(|e| e == "zip").unwrap_or(false) {
-                let mut zip_reader = ZipArchive::new(f)?;
-                let mut file = zip_reader.by_index(0)?;
-                file.read_to_string(&mut raw_data)?;
-            } else {
-                f.read_to_string(&mut raw_data)?;
-                drop(f);
-            }
-            roam_edn::graph_from_roam_edn(&raw_data)?
-        }
+    let reload = serve::ReloadSignal::default();
+    let server = config
+        .serve
+        .as_ref()
+        .map(|options| serve::start(options, &config.output, reload.clone()))
+        .transpose()?;
+
+    // Serving a Logseq graph also watches it, so that the preview stays up to date.
+    let watch = config.watch || (server.is_some() && matches!(config.product, PkmProduct::Logseq));
+    if watch {
+        return watch::watch(
+            &config,
+            templates,
+            script,
+            &highlighter,
+            metadata_db,
+            || reload.notify(),
+        );
+    }
+
+    let (explicit_ordering, parsed_pages) = match config.product {
+        PkmProduct::Roam => read_roam_export(&config.path)?,
+        PkmProduct::LogseqExport => logseq::export::graph_from_logseq_export(&config.path)?,
+        PkmProduct::Obsidian => obsidian::read_vault(&config.path)?,
         PkmProduct::Logseq => logseq::LogseqGraph::build(
             config.path.clone(),
             if config.track_logseq_timestamps {
@@ -89,20 +114,165 @@ fn main() -> Result<()> {
             } else {
                 None
             },
+            config.dry_run.is_some(),
         )?,
     };
 
     let (wrote, skipped) = make_pages_from_script(
-        parsed_pages,
-        content_style,
-        explicit_ordering,
-        templates,
+        ExportInput {
+            pages: parsed_pages,
+            explicit_ordering,
+            render_only: None,
+        },
+        &script,
+        &mut templates,
         &highlighter,
         &config,
         metadata_db,
     )?;
 
-    println!("Wrote {wrote} pages, skipped {skipped} up-to-date");
+    if config.dry_run.is_none() {
+        println!("Wrote {wrote} pages, skipped {skipped} up-to-date");
+    }
+
+    if let Some(server) = server {
+        server
+            .join()
+            .map_err(|_| eyre!("The preview server stopped unexpectedly"))?;
+    }
 
     Ok(())
 }
+
+/// Read a Roam EDN or JSON export. A ZIP file may hold several exports, and the pages from all of
+/// them are combined.
+fn read_roam_export(path: &Path) -> Result<(bool, Vec<ParsedPage>)> {
+    let mut f = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+
+    let mut files = Vec::new();
+    if path.extension().map(|e| e == "zip").unwrap_or(false) {
+        let mut zip_reader = ZipArchive::new(f)?;
+        for i in 0..zip_reader.len() {
+            let mut file = zip_reader.by_index(i)?;
+            let name = PathBuf::from(file.name());
+            let is_export = file.is_file()
+                && name
+                    .extension()
+                    .map(|e| e == "edn" || e == "json")
+                    .unwrap_or(false);
+            if !is_export {
+                continue;
+            }
+
+            let mut raw_data = String::new();
+            file.read_to_string(&mut raw_data)
+                .with_context(|| format!("Reading {}", name.display()))?;
+            files.push((path.join(name), raw_data));
+        }
+    } else {
+        let mut raw_data = String::new();
+        f.read_to_string(&mut raw_data)?;
+        files.push((path.to_path_buf(), raw_data));
+    }
+
+    // A backup can have both the EDN and JSON exports of a graph. Read the EDN first, and leave
+    // out the pages that an earlier file already had so they aren't exported twice.
+    files.sort_by_key(|(file_path, _)| file_path.extension().map(|e| e != "edn").unwrap_or(true));
+
+    let mut explicit_ordering = true;
+    let mut pages = Vec::new();
+    let mut loaded_titles = HashSet::default();
+    for (file_path, raw_data) in files {
+        let (file_ordering, mut file_pages) =
+            if file_path.extension().map(|e| e == "json").unwrap_or(false) {
+                roam_json::graph_from_roam_json(&file_path, &raw_data)
+            } else {
+                roam_edn::graph_from_roam_edn(&file_path, &raw_data)
+            }
+            .with_context(|| format!("Parsing {}", file_path.display()))?;
+
+        let read_pages = file_pages.len();
+        file_pages.retain(|page| {
+            let title = page.blocks[&page.root_block].page_title.clone();
+            !loaded_titles.contains(&title)
+        });
+        if file_pages.len() < read_pages {
+            eprintln!(
+                "{}: Skipping {} pages that an earlier export file already had",
+                file_path.display(),
+                read_pages - file_pages.len()
+            );
+        }
+        if file_pages.is_empty() {
+            continue;
+        }
+        loaded_titles.extend(
+            file_pages
+                .iter()
+                .map(|page| page.blocks[&page.root_block].page_title.clone()),
+        );
+
+        // Each file numbers its blocks on its own, so move them past the ids used so far.
+        let offset = pages
+            .iter()
+            .flat_map(|p: &ParsedPage| p.blocks.keys())
+            .max()
+            .map(|id| id + 1)
+            .unwrap_or(0);
+        if offset > 0 {
+            for page in file_pages.iter_mut() {
+                page.offset_ids(offset);
+            }
+        }
+
+        explicit_ordering = explicit_ordering && file_ordering;
+        pages.extend(file_pages);
+    }
+
+    if pages.is_empty() {
+        return Err(eyre!(
+            "No Roam EDN or JSON export found in {}",
+            path.display()
+        ));
+    }
+
+    Ok((explicit_ordering, pages))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use itertools::Itertools;
+
+    use super::*;
+
+    #[test]
+    fn roam_backup_with_duplicate_exports() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("backup.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        let export = |title: &str| {
+            format!(
+                r#"[{{"title": "{title}", "uid": "{title}", "children": [{{"string": "Text", "uid": "{title}-1"}}]}}]"#
+            )
+        };
+        for (name, contents) in [
+            ("graph.json", export("Shared")),
+            ("graph-copy.json", export("Shared")),
+            ("other.json", export("Other")),
+        ] {
+            zip.start_file(name, Default::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let (_, pages) = read_roam_export(&path).unwrap();
+        let titles = pages
+            .iter()
+            .map(|page| page.blocks[&page.root_block].page_title.clone().unwrap())
+            .sorted()
+            .collect::<Vec<_>>();
+        assert_eq!(titles, vec!["Other", "Shared"]);
+    }
+}
@@ -8,19 +8,40 @@ use ahash::{HashMap, HashSet};
 use eyre::{eyre, Result, WrapErr};
 use itertools::Itertools;
 use rayon::prelude::*;
-use rhai::{packages::Package, Engine};
 use serde::Serialize;
 
 use crate::{
-    config::{Config, PkmProduct},
+    config::{Config, OutputFormat, PkmProduct},
+    diff::unified_diff,
+    epub::{write_epub, EpubChapter},
+    feed::{make_feeds, FeedContent, FeedEntry},
     graph::{BlockInclude, Graph, ParsedPage},
     image::{image_full_path, Images},
-    logseq::db::MetadataDb,
-    page::{IdSlugUid, ManifestItem, Page, TitleSlugUid},
-    parse_string::{ContentStyle, Expression},
+    journal_archive::{JournalArchive, JournalLink, JournalMember},
+    link_report::{link_report_json, read_link_report, LinkProblems, LinkReportEntry},
+    logseq::{
+        db::{MetadataDb, PageSlug},
+        journal_date,
+    },
+    mentions::{find_unlinked_mentions, MentionMatcher, UnlinkedMentionsMode},
+    namespace::{namespace_parts, nested_url_name, Breadcrumb, NamespaceMember, Namespaces},
+    obsidian::vault_path_name,
+    output_plan::{
+        find_stale_outputs, handle_stale_outputs, print_plan, rewritten_file_change, DryRunOptions,
+        OutputChange, PlannedOutput,
+    },
+    page::{lookup_page_by_title, normalize_title, IdSlugUid, ManifestItem, Page, TitleSlugUid},
+    parse_string::Expression,
     pic_store::PicStoreClient,
-    script::{run_script_on_page, AllowEmbed, PageConfig, TemplateSelection},
+    plain_text::{excerpt, PlainTextRenderer},
+    redirect::{nginx_map, redirect_page, redirects_file, SlugRedirect, SlugRedirects},
+    references::{Backlink, BacklinkIndex},
+    script::{run_script_on_page, AllowEmbed, PageConfig, PageScript, TemplateSelection},
+    search::{page_entries, search_index_json, SearchEntry},
+    sitemap::{make_sitemaps, SitemapEntry, MAX_SITEMAP_URLS},
     syntax_highlight,
+    tag_index::{TagCount, TagMember, TagPages},
+    template::DedupingTemplateRegistry,
 };
 
 #[derive(Serialize, Debug)]
@@ -31,6 +52,45 @@ struct TemplateArgs<'a> {
     attrs: HashMap<&'a str, String>,
     created_time: u64,
     edited_time: u64,
+    backlinks: Vec<Backlink>,
+    unlinked_mentions: Vec<Backlink>,
+    /// The text of the page without any markup
+    plain_text: String,
+    /// The start of `plain_text`, shortened to `excerpt_length` characters
+    excerpt: String,
+    /// The namespaces above this page, from the top level down
+    breadcrumbs: Vec<Breadcrumb>,
+    /// Every tag with the number of pages that have it. This is only filled in for the tags
+    /// overview page.
+    tag_counts: Vec<TagCount>,
+    /// The journal pages before and after this one, when writing the journal archive
+    previous_journal: Option<JournalLink>,
+    next_journal: Option<JournalLink>,
+}
+
+/// The results of exporting a page.
+struct PageOutput {
+    /// How the page's existing output changed.
+    change: OutputChange,
+    manifest_item: ManifestItem,
+    search_entries: Vec<SearchEntry>,
+    epub_chapter: Option<EpubChapter>,
+    feed_entry: Option<FeedEntry>,
+    sitemap_entry: Option<SitemapEntry>,
+    link_problems: Vec<LinkReportEntry>,
+}
+
+/// A generated page that lists other pages, like a tag page or a journal archive page.
+pub struct ListingPage {
+    pub title: String,
+    /// The path of the page relative to the output directory, without the extension
+    pub url_name: String,
+    pub slug: String,
+    pub body: String,
+    pub create_time: u64,
+    pub edit_time: u64,
+    /// The tags with their counts, for the tags overview
+    pub tag_counts: Vec<TagCount>,
 }
 
 pub fn title_to_slug(s: &str) -> String {
@@ -55,6 +115,132 @@ fn create_path(page_base: &str, default_base: &str, filename: &str) -> String {
     format!("{base}/{filename}")
 }
 
+/// The file that a page is written to.
+fn page_output_path(config: &PageConfig, global_config: &Config) -> String {
+    let filename = if config.path_name.is_empty() {
+        format!("{}.{}", config.url_name, global_config.extension)
+    } else {
+        config.path_name.clone()
+    };
+
+    create_path(
+        config.path_base.as_str(),
+        global_config.output.to_string_lossy().as_ref(),
+        &filename,
+    )
+}
+
+/// Write a rendered page, unless the existing file already has the same contents. A dry run only
+/// compares the contents.
+fn write_page(
+    output_path: &str,
+    contents: &str,
+    safe_write: bool,
+    dry_run: Option<&DryRunOptions>,
+) -> Result<OutputChange> {
+    let change = match std::fs::read_to_string(output_path) {
+        Ok(existing) if existing == contents => return Ok(OutputChange::Unchanged),
+        Ok(existing) => OutputChange::Updated {
+            diff: dry_run
+                .filter(|options| options.diff)
+                .map(|_| unified_diff(output_path, &existing, contents)),
+        },
+        Err(_) => OutputChange::Created,
+    };
+
+    if dry_run.is_some() {
+        return Ok(change);
+    }
+
+    // Namespaced pages may go in directories that don't exist yet.
+    if let Some(parent) = Path::new(output_path).parent() {
+        std::fs::create_dir_all(parent).with_context(|| format!("Writing {output_path}"))?;
+    }
+
+    if safe_write {
+        let mut temp_out =
+            tempfile::NamedTempFile::new().with_context(|| format!("Writing {output_path}"))?;
+        temp_out.write_all(contents.as_bytes())?;
+        temp_out.flush()?;
+
+        let temp_path = temp_out.into_temp_path();
+        temp_path
+            .persist(output_path)
+            .with_context(|| format!("Writing {output_path}"))?;
+    } else {
+        let mut writer =
+            std::fs::File::create(output_path).with_context(|| format!("Writing {output_path}"))?;
+        writer.write_all(contents.as_bytes())?;
+        writer.flush()?;
+    }
+
+    Ok(change)
+}
+
+/// Write a generated file that isn't a page, like the slug redirects, at `path` relative to the
+/// output directory. It goes in the manifest like a page, so that dry runs list it and a later
+/// export notices when it's no longer written.
+fn write_generated_file(
+    path: &str,
+    title: &str,
+    contents: &str,
+    global_config: &Config,
+) -> Result<(String, PageOutput)> {
+    let output_path = create_path("", global_config.output.to_string_lossy().as_ref(), path);
+    let change = write_page(
+        &output_path,
+        contents,
+        global_config.safe_write,
+        global_config.dry_run.as_ref(),
+    )?;
+
+    Ok((
+        output_path,
+        PageOutput {
+            change,
+            manifest_item: ManifestItem {
+                title: title.to_string(),
+                slug: create_path("", global_config.base_url.as_deref().unwrap_or(""), path),
+                uid: String::new(),
+                redirect_to: None,
+            },
+            search_entries: Vec::new(),
+            epub_chapter: None,
+            feed_entry: None,
+            sitemap_entry: None,
+            link_problems: Vec::new(),
+        },
+    ))
+}
+
+/// A file that every export writes along with the pages. These aren't in the manifest, since they
+/// never go stale, but dry runs list them with the pages.
+struct ExportFile {
+    path: PathBuf,
+    title: &'static str,
+    contents: String,
+}
+
+/// Write the export files, or in a dry run just find out how they would change.
+fn write_export_files(
+    files: &[ExportFile],
+    global_config: &Config,
+) -> Result<Vec<(String, &'static str, OutputChange)>> {
+    files
+        .iter()
+        .map(|file| {
+            let path = file.path.to_string_lossy().to_string();
+            let change = write_page(
+                &path,
+                &file.contents,
+                global_config.safe_write,
+                global_config.dry_run.as_ref(),
+            )?;
+            Ok((path, file.title, change))
+        })
+        .collect()
+}
+
 struct ExtractedImage {
     path: PathBuf,
 }
@@ -105,6 +291,32 @@ fn examine_tags(
     }
 }
 
+/// The pages to export.
+pub struct ExportInput {
+    pub pages: Vec<ParsedPage>,
+    pub explicit_ordering: bool,
+    /// When set, only pages from these source files are rendered. Other pages keep their entries
+    /// from the existing manifest. Watch mode uses this to rerender just the affected pages.
+    pub render_only: Option<HashSet<PathBuf>>,
+}
+
+/// Read the manifest written by a previous export, if there is one.
+fn read_manifest(path: &Path) -> BTreeMap<String, ManifestItem> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+/// A redirect stub to write at the URL for one of a page's aliases.
+struct AliasRedirect {
+    alias: String,
+    output_path: String,
+    slug: String,
+    target_title: String,
+    target_slug: String,
+}
+
 struct ProcessedPage {
     config: PageConfig,
     blocks: ParsedPage,
@@ -114,32 +326,91 @@ struct ProcessedPage {
 }
 
 pub fn make_pages_from_script(
-    pages: Vec<ParsedPage>,
-    content_style: ContentStyle,
-    explicit_ordering: bool,
-    mut templates: crate::template::DedupingTemplateRegistry,
+    input: ExportInput,
+    script: &PageScript,
+    templates: &mut DedupingTemplateRegistry,
     highlighter: &syntax_highlight::Highlighter,
     global_config: &Config,
     metadata_db: Option<MetadataDb>,
 ) -> Result<(usize, usize)> {
-    let package = crate::script::ParsePackage::new();
-    let mut parse_engine = Engine::new_raw();
-    package.register_into_engine(&mut parse_engine);
+    let ExportInput {
+        pages,
+        explicit_ordering,
+        render_only,
+    } = input;
+
+    // The previous manifest lists the files to check for staleness, and in watch mode it also
+    // supplies the entries for the pages that aren't rerendered.
+    let manifest_path = global_config.output.join("manifest.json");
+    let previous_manifest = read_manifest(&manifest_path);
 
-    let ast = parse_engine
-        .compile_file(global_config.script.clone())
-        .wrap_err("Parsing script")?;
+    let link_report_path = global_config.output.join("link-report.json");
+    let previous_link_report = if render_only.is_some() {
+        read_link_report(&link_report_path)
+    } else {
+        Vec::new()
+    };
 
     let base_dir = match global_config.product {
-        PkmProduct::Logseq => global_config.path.canonicalize().unwrap(),
-        PkmProduct::Roam => global_config.path.parent().unwrap().canonicalize().unwrap(),
+        PkmProduct::Logseq | PkmProduct::Obsidian => global_config.path.canonicalize().unwrap(),
+        PkmProduct::Roam | PkmProduct::LogseqExport => {
+            global_config.path.parent().unwrap().canonicalize().unwrap()
+        }
+    };
+
+    let mention_matcher = global_config
+        .unlinked_mentions
+        .is_some()
+        .then(|| {
+            MentionMatcher::new(
+                pages
+                    .iter()
+                    .filter_map(|page| page.blocks.get(&page.root_block)),
+            )
+        })
+        .transpose()?
+        .flatten();
+
+    let unlinked_mentions = mention_matcher
+        .as_ref()
+        .map(|matcher| find_unlinked_mentions(&pages, matcher))
+        .unwrap_or_default();
+
+    let mut mentioning_page_titles = {
+        let page_titles = pages
+            .iter()
+            .filter_map(|page| {
+                let title = page.blocks.get(&page.root_block)?.page_title.clone()?;
+                Some((page.root_block, title))
+            })
+            .collect::<HashMap<_, _>>();
+        let block_pages = pages
+            .iter()
+            .flat_map(|page| page.blocks.keys().map(|id| (*id, page.root_block)))
+            .collect::<HashMap<_, _>>();
+
+        unlinked_mentions
+            .iter()
+            .map(|(target, blocks)| {
+                let titles = blocks
+                    .iter()
+                    .filter_map(|block_id| block_pages.get(block_id))
+                    .unique()
+                    .filter_map(|page_id| page_titles.get(page_id).cloned())
+                    .collect::<Vec<_>>();
+                (*target, titles)
+            })
+            .collect::<HashMap<_, _>>()
     };
 
     let mut pages = pages
         .into_iter()
         .map(|parsed_page| {
+            let mentioned_by = mentioning_page_titles
+                .remove(&parsed_page.root_block)
+                .unwrap_or_default();
             let (page_config, page_blocks) =
-                run_script_on_page(&package, &ast, &global_config, parsed_page)
+                run_script_on_page(script, global_config, parsed_page, mentioned_by)
                     .wrap_err("Running script")?;
 
             let slug = create_path(
@@ -203,13 +474,18 @@ pub fn make_pages_from_script(
 
     let embedded_pages = pages
         .iter()
-        .flat_map(|page| page.notable.page_embeds.iter().map(|s| s.to_string()))
+        .flat_map(|page| page.notable.page_embeds.iter().map(|s| normalize_title(s)))
         .collect::<HashSet<_>>();
 
     // Sync the images with the CDN
-    let image_info = if let Some(pc_config) = global_config.pic_store.as_ref() {
+    let (image_info, skipped_uploads) = if let Some(pc_config) = global_config.pic_store.as_ref() {
         let pc_client = PicStoreClient::new(pc_config)?;
-        let images = Images::new(base_dir.to_path_buf(), pc_client, metadata_db.unwrap());
+        let images = Images::new(
+            base_dir.to_path_buf(),
+            pc_client,
+            metadata_db.clone().unwrap(),
+            global_config.dry_run.is_some(),
+        );
 
         let image_paths = pages
             .iter_mut()
@@ -229,7 +505,7 @@ pub fn make_pages_from_script(
                     .as_deref()
                     .unwrap_or("");
 
-                embedded_pages.contains(orig_title)
+                embedded_pages.contains(&normalize_title(orig_title))
             })
             .flat_map(
                 |ProcessedPage {
@@ -247,9 +523,10 @@ pub fn make_pages_from_script(
             .into_par_iter()
             .try_for_each(|(profile_override, path)| images.add(path.path, profile_override))?;
 
-        images.finish()?
+        let skipped_uploads = images.skipped_uploads();
+        (images.finish()?, skipped_uploads)
     } else {
-        HashMap::default()
+        (HashMap::default(), Vec::new())
     };
 
     let page_templates = pages
@@ -278,7 +555,7 @@ pub fn make_pages_from_script(
         })
         .collect::<Result<HashMap<_, _>>>()?;
 
-    let pages_by_title = pages
+    let page_infos = pages
         .iter()
         .map(
             |ProcessedPage {
@@ -316,27 +593,16 @@ pub fn make_pages_from_script(
                 )
             },
         )
-        .collect::<HashMap<_, _>>();
-
-    let pages_by_filename_title = pages
-        .iter()
-        .filter_map(|ProcessedPage { config, blocks, .. }| {
-            let page_block = blocks.blocks.get(&config.root_block).unwrap();
-
-            page_block
-                .original_title
-                .clone()
-                .zip(page_block.page_title.clone())
-        })
-        .collect::<HashMap<_, _>>();
+        .collect::<Vec<_>>();
 
-    let pages_by_id = pages_by_title
+    let pages_by_id = page_infos
         .iter()
         .map(|(title, isu)| {
             (
                 isu.id,
                 TitleSlugUid {
                     title: title.clone(),
+                    output_title: isu.output_title.clone(),
                     slug: isu.slug.clone(),
                     uid: isu.uid.clone(),
                     include: isu.include,
@@ -346,17 +612,176 @@ pub fn make_pages_from_script(
         })
         .collect::<HashMap<_, _>>();
 
+    // Links match titles the same way that Logseq does, so the lookup maps use normalized titles.
+    let pages_by_title = page_infos
+        .into_iter()
+        .map(|(title, isu)| (normalize_title(&title), isu))
+        .collect::<HashMap<_, _>>();
+
+    // Other names that links can use for a page. The title from a page's filename is added after
+    // the aliases so that it wins if another page uses the same name as an alias.
+    let pages_by_filename_title = pages
+        .iter()
+        .flat_map(|ProcessedPage { config, blocks, .. }| {
+            let page_block = blocks.blocks.get(&config.root_block).unwrap();
+            let title = page_block.page_title.clone().unwrap_or_default();
+            page_block
+                .aliases()
+                .into_iter()
+                .map(move |alias| (normalize_title(&alias), normalize_title(&title)))
+        })
+        .chain(
+            pages
+                .iter()
+                .filter_map(|ProcessedPage { config, blocks, .. }| {
+                    let page_block = blocks.blocks.get(&config.root_block).unwrap();
+
+                    let original_title = page_block.original_title.as_deref()?;
+                    let title = page_block.page_title.as_deref()?;
+                    Some((normalize_title(original_title), normalize_title(title)))
+                }),
+        )
+        .chain(
+            // Obsidian links can also name a note by its path in the vault.
+            pages
+                .iter()
+                .filter(|_| matches!(global_config.product, PkmProduct::Obsidian))
+                .filter_map(|ProcessedPage { config, blocks, .. }| {
+                    let page_block = blocks.blocks.get(&config.root_block).unwrap();
+
+                    let path_name = vault_path_name(&base_dir, &blocks.path)?;
+                    let title = page_block.page_title.as_deref()?;
+                    Some((normalize_title(&path_name), normalize_title(title)))
+                }),
+        )
+        .collect::<HashMap<_, _>>();
+
     let default_output_dir = global_config.output.to_string_lossy();
 
+    let alias_redirects = if global_config.alias_redirects {
+        pages
+            .iter()
+            .filter(|p| p.config.include)
+            .flat_map(
+                |ProcessedPage {
+                     config,
+                     blocks,
+                     slug,
+                     ..
+                 }| {
+                    let page_block = blocks.blocks.get(&config.root_block).unwrap();
+                    page_block
+                        .aliases()
+                        .into_iter()
+                        .map(|alias| {
+                            let url_name = if global_config.namespace_dirs {
+                                nested_url_name(&namespace_parts(&alias))
+                            } else {
+                                title_to_slug(&alias)
+                            };
+
+                            AliasRedirect {
+                                output_path: create_path(
+                                    config.path_base.as_str(),
+                                    default_output_dir.as_ref(),
+                                    &format!("{url_name}.{}", global_config.extension),
+                                ),
+                                slug: create_path(
+                                    config.url_base.as_str(),
+                                    global_config.base_url.as_deref().unwrap_or(""),
+                                    &url_name,
+                                ),
+                                alias,
+                                target_title: config.title.clone(),
+                                target_slug: slug.clone(),
+                            }
+                        })
+                        .filter(|redirect| redirect.slug != *slug)
+                        .collect::<Vec<_>>()
+                },
+            )
+            .collect::<Vec<_>>()
+    } else {
+        Vec::new()
+    };
+
+    // Compare each page's slug with the one saved by the last export, to redirect from the URLs
+    // that changed.
+    let slug_redirects = match (global_config.slug_redirects, metadata_db.as_ref()) {
+        (Some(_), Some(db)) => {
+            let exported = pages
+                .iter()
+                .filter(|p| p.config.include)
+                .map(|p| {
+                    (
+                        p,
+                        page_output_path(&p.config, global_config),
+                        db.relative_filename(&p.blocks.path),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            let page_slugs = exported
+                .iter()
+                .map(|(p, output_path, _)| PageSlug {
+                    filename: &p.blocks.path,
+                    slug: &p.slug,
+                    output_path,
+                })
+                .collect::<Vec<_>>();
+            let previous_slugs =
+                db.update_page_slugs(&page_slugs, global_config.dry_run.is_none())?;
+
+            let pages_by_filename = exported
+                .iter()
+                .map(|(p, _, filename)| (filename.as_str(), *p))
+                .collect::<HashMap<_, _>>();
+            previous_slugs
+                .into_iter()
+                .filter_map(|previous| {
+                    let page = pages_by_filename.get(previous.filename.as_str())?;
+                    Some(SlugRedirect {
+                        old_slug: previous.old_slug,
+                        old_output_path: previous.old_output_path,
+                        title: page.config.title.clone(),
+                        new_slug: page.slug.clone(),
+                    })
+                })
+                .collect::<Vec<_>>()
+        }
+        _ => Vec::new(),
+    };
+
     let omitted_attributes = global_config
         .omit_attributes
         .iter()
         .map(|x| x.as_str())
         .collect::<HashSet<_>>();
 
-    let handlebars = templates.into_inner();
+    let mut listing_template = |template: Option<&Path>, section: &str| match template {
+        Some(path) => templates.add_file(path),
+        None if global_config.template.is_some() => Ok("default".to_string()),
+        None => Err(eyre!(
+            "{section} uses the default template unless {section}.template is set, \
+            but the config has no default template"
+        )),
+    };
+    let tag_template = global_config
+        .tag_pages
+        .as_ref()
+        .map(|tag_config| listing_template(tag_config.template.as_deref(), "tag_pages"))
+        .transpose()?;
+    let journal_template = global_config
+        .journal_archive
+        .as_ref()
+        .map(|archive_config| {
+            listing_template(archive_config.template.as_deref(), "journal_archive")
+        })
+        .transpose()?;
+
+    let handlebars = templates.handlebars();
 
-    let mut graph = Graph::new(content_style, explicit_ordering);
+    let mut graph = Graph::new(explicit_ordering);
     for ProcessedPage { blocks, .. } in pages.iter_mut() {
         let blocks = std::mem::take(&mut blocks.blocks);
         for (_, block) in blocks {
@@ -364,7 +789,95 @@ pub fn make_pages_from_script(
         }
     }
 
-    let results = pages
+    let backlink_index = BacklinkIndex::new(&graph, |title| {
+        lookup_page_by_title(&pages_by_title, &pages_by_filename_title, title).map(|p| p.id)
+    });
+
+    let namespaces = {
+        let members = pages
+            .iter()
+            .filter(|p| p.config.include)
+            .filter_map(|p| {
+                let block = graph.blocks.get(&p.config.root_block)?;
+                Some(NamespaceMember {
+                    parts: &p.config.namespace_parts,
+                    slug: &p.slug,
+                    create_time: block.create_time,
+                    edit_time: block.edit_time,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        Namespaces::new(&members, global_config.namespace_dirs, |url_name| {
+            create_path(
+                "",
+                global_config.base_url.as_deref().unwrap_or(""),
+                url_name,
+            )
+        })
+    };
+
+    let tag_pages = global_config.tag_pages.as_ref().map(|tag_config| {
+        let members = pages
+            .iter()
+            .filter(|p| p.config.include)
+            .filter_map(|p| {
+                let block = graph.blocks.get(&p.config.root_block)?;
+                Some(TagMember {
+                    title: &p.config.title,
+                    slug: &p.slug,
+                    tags: &p.config.tags,
+                    create_time: block.create_time,
+                    edit_time: block.edit_time,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        TagPages::new(&tag_config.dir, &members, |url_name| {
+            create_path(
+                "",
+                global_config.base_url.as_deref().unwrap_or(""),
+                url_name,
+            )
+        })
+    });
+
+    let journal_archive = global_config
+        .journal_archive
+        .as_ref()
+        .map(|archive_config| {
+            let members = pages
+                .iter()
+                .filter(|p| p.config.include && p.config.is_journal)
+                .filter_map(|p| {
+                    let block = graph.blocks.get(&p.config.root_block)?;
+                    Some(JournalMember {
+                        title: &p.config.title,
+                        slug: &p.slug,
+                        date: journal_date(block.page_title.as_deref()?)?,
+                        create_time: block.create_time,
+                        edit_time: block.edit_time,
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            JournalArchive::new(archive_config, &members, |url_name| {
+                create_path(
+                    "",
+                    global_config.base_url.as_deref().unwrap_or(""),
+                    url_name,
+                )
+            })
+        });
+
+    let plain_text_renderer = PlainTextRenderer {
+        graph: &graph,
+        pages_by_title: &pages_by_title,
+        pages_by_filename_title: &pages_by_filename_title,
+        omitted_attributes: &omitted_attributes,
+    };
+
+    let mut results = pages
         .into_par_iter()
         .map(
             |ProcessedPage {
@@ -378,17 +891,73 @@ pub fn make_pages_from_script(
                     return Ok(None);
                 }
 
-                let filename = if config.path_name.is_empty() {
-                    format!("{}.{}", config.url_name, global_config.extension)
-                } else {
-                    config.path_name
-                };
+                let output_path = page_output_path(&config, global_config);
 
-                let output_path = create_path(
-                    config.path_base.as_str(),
-                    default_output_dir.as_ref(),
-                    &filename,
-                );
+                let breadcrumbs = namespaces.breadcrumbs(&config.namespace_parts);
+
+                let mut tags = config.tags.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+                tags.sort_by_key(|k| k.to_lowercase());
+                tags.dedup();
+
+                // The search entries are always rebuilt since the index is written from scratch.
+                let search_entries = global_config
+                    .search_index
+                    .as_ref()
+                    .map(|search_config| {
+                        page_entries(
+                            &plain_text_renderer,
+                            config.root_block,
+                            &config.title,
+                            &slug,
+                            &tags,
+                            search_config.per_block,
+                        )
+                    })
+                    .unwrap_or_default();
+
+                let journal_nav = journal_archive
+                    .as_ref()
+                    .and_then(|archive| archive.nav(&slug));
+
+                // Every page goes into the EPUB and could go in the feeds, so they all need to be
+                // rendered when writing those. Journal pages link to their neighbors, which may
+                // have changed.
+                let render_only = render_only.as_ref().filter(|_| {
+                    global_config.epub.is_none()
+                        && global_config.feed.is_none()
+                        && journal_nav.is_none()
+                });
+                if let Some(render_only) = render_only {
+                    if !render_only.contains(&blocks.path) {
+                        // This page is unaffected by the changes, so keep its existing output.
+                        return Ok(previous_manifest.get(&output_path).map(|item| {
+                            (
+                                output_path,
+                                PageOutput {
+                                    change: OutputChange::Unchanged,
+                                    manifest_item: item.clone(),
+                                    search_entries,
+                                    epub_chapter: None,
+                                    feed_entry: None,
+                                    sitemap_entry: config.in_sitemap.then(|| SitemapEntry {
+                                        slug: item.slug.clone(),
+                                        edited_time: graph
+                                            .blocks
+                                            .get(&config.root_block)
+                                            .map(|block| block.edit_time)
+                                            .unwrap_or(0),
+                                        priority: config.sitemap_priority,
+                                    }),
+                                    link_problems: previous_link_report
+                                        .iter()
+                                        .filter(|entry| entry.slug == item.slug)
+                                        .cloned()
+                                        .collect(),
+                                },
+                            )
+                        }));
+                    }
+                }
 
                 let (template_key, picture_template_key) =
                     page_templates
@@ -402,6 +971,7 @@ pub fn make_pages_from_script(
                     base_dir: &base_dir,
                     path: blocks.path,
                     latest_found_edit_time: std::cell::Cell::new(0),
+                    link_problems: LinkProblems::default(),
                     graph: &graph,
                     config: global_config,
                     pages_by_title: &pages_by_title,
@@ -409,24 +979,32 @@ pub fn make_pages_from_script(
                     pages_by_id: &pages_by_id,
                     omitted_attributes: &omitted_attributes,
                     highlighter,
-                    handlebars: &handlebars,
+                    handlebars,
                     picture_template_key,
                     image_info: &image_info,
                     heading_delta,
+                    mention_matcher: mention_matcher.as_ref().filter(|_| {
+                        global_config.unlinked_mentions == Some(UnlinkedMentionsMode::Autolink)
+                    }),
+                    whiteboard: blocks.whiteboard,
                 };
 
                 let block = graph.blocks.get(&page.id).unwrap();
 
                 let rendered = page.render()?;
+                // Take these now, since rendering the backlinks below would add problems from
+                // other pages.
+                let link_problems = page
+                    .link_problems
+                    .take()
+                    .into_iter()
+                    .map(|problem| LinkReportEntry::new(&page.title, &slug, &page.path, problem))
+                    .collect::<Vec<_>>();
 
                 if rendered.is_empty() {
                     return Ok(None);
                 }
 
-                let mut tags = config.tags.iter().map(|s| s.as_str()).collect::<Vec<_>>();
-                tags.sort_by_key(|k| k.to_lowercase());
-                tags.dedup();
-
                 // println!("{:?} {:?}", title, tags);
 
                 let edited_time = block.edit_time.max(page.latest_found_edit_time.get());
@@ -437,6 +1015,80 @@ pub fn make_pages_from_script(
                     .map(|(k, v)| (k.as_str(), v.join(", ")))
                     .collect::<HashMap<_, _>>();
 
+                // Render the blocks that reference this page, for the backlinks and unlinked
+                // mentions lists.
+                let render_references = |block_ids: &[usize]| {
+                    block_ids
+                        .iter()
+                        .filter(|block_id| graph.is_block_rendered(**block_id))
+                        .filter_map(|block_id| {
+                            let block = graph.blocks.get(block_id)?;
+                            let source = pages_by_id
+                                .get(&block.containing_page)
+                                .filter(|p| p.include)?;
+                            Some((source, block))
+                        })
+                        .map(|(source, block)| {
+                            Ok::<_, eyre::Report>(Backlink {
+                                title: source.output_title.clone(),
+                                slug: source.slug.clone(),
+                                uid: block.uid.clone(),
+                                snippet: page.render_snippet(block)?,
+                            })
+                        })
+                        .filter(|backlink| {
+                            backlink
+                                .as_ref()
+                                .map(|b| !b.snippet.trim().is_empty())
+                                .unwrap_or(true)
+                        })
+                        .collect::<Result<Vec<_>>>()
+                        .map(|backlinks| {
+                            backlinks
+                                .into_iter()
+                                .sorted_by(|a, b| a.title.cmp(&b.title))
+                                .collect::<Vec<_>>()
+                        })
+                };
+
+                let backlinks = render_references(backlink_index.referencing_blocks(page.id))?;
+                let unlinked_mentions = render_references(
+                    unlinked_mentions
+                        .get(&page.id)
+                        .map(|v| v.as_slice())
+                        .unwrap_or_default(),
+                )?;
+
+                let plain_text = plain_text_renderer.page_text(page.id);
+                let excerpt = excerpt(&plain_text, global_config.excerpt_length);
+
+                let feed_entry = global_config
+                    .feed
+                    .as_ref()
+                    .map(|feed_config| {
+                        let content = match (feed_config.content, global_config.output_format) {
+                            (FeedContent::Excerpt, _) => excerpt.clone(),
+                            (FeedContent::Full, OutputFormat::Html) => rendered.clone(),
+                            (FeedContent::Full, OutputFormat::Markdown) => page.render_html()?,
+                        };
+
+                        Ok::<_, eyre::Report>(FeedEntry {
+                            title: page.title.clone(),
+                            slug: slug.clone(),
+                            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+                            created_time: block.create_time,
+                            edited_time,
+                            content,
+                        })
+                    })
+                    .transpose()?;
+
+                let sitemap_entry = config.in_sitemap.then(|| SitemapEntry {
+                    slug: slug.clone(),
+                    edited_time,
+                    priority: config.sitemap_priority,
+                });
+
                 let template_data = TemplateArgs {
                     title: page.title.as_str(),
                     body: &rendered,
@@ -444,46 +1096,63 @@ pub fn make_pages_from_script(
                     attrs: template_attrs,
                     created_time: block.create_time,
                     edited_time,
+                    backlinks,
+                    unlinked_mentions,
+                    plain_text,
+                    excerpt,
+                    breadcrumbs,
+                    tag_counts: Vec::new(),
+                    previous_journal: journal_nav.and_then(|nav| nav.previous.clone()),
+                    next_journal: journal_nav.and_then(|nav| nav.next.clone()),
                 };
 
                 let full_page = handlebars.render(template_key, &template_data)?;
 
-                let content_matches = match std::fs::read_to_string(&output_path) {
-                    Ok(existing) => existing == full_page,
-                    Err(_) => false,
-                };
-
-                if !content_matches {
-                    if page.config.safe_write {
-                        let mut temp_out = tempfile::NamedTempFile::new()
-                            .with_context(|| format!("Writing {output_path}"))?;
-                        temp_out.write_all(full_page.as_bytes())?;
-                        temp_out.flush()?;
-
-                        let temp_path = temp_out.into_temp_path();
-                        temp_path
-                            .persist(&output_path)
-                            .with_context(|| format!("Writing {output_path}"))?;
-                    } else {
-                        let mut writer = std::fs::File::create(&output_path)
-                            .with_context(|| format!("Writing {output_path}"))?;
-                        writer.write_all(full_page.as_bytes())?;
-                        writer.flush()?;
-                    }
+                let change = write_page(
+                    &output_path,
+                    &full_page,
+                    page.config.safe_write,
+                    global_config.dry_run.as_ref(),
+                )?;
 
+                if change.is_changed() && global_config.dry_run.is_none() {
                     println!("Wrote: \"{title}\" to {slug}", title = page.title);
                 }
 
+                let epub_chapter = global_config
+                    .epub
+                    .as_ref()
+                    .map(|_| {
+                        let body = match global_config.output_format {
+                            OutputFormat::Html => rendered.clone(),
+                            OutputFormat::Markdown => page.render_html()?,
+                        };
+
+                        Ok::<_, eyre::Report>(EpubChapter {
+                            title: page.title.clone(),
+                            slug: slug.clone(),
+                            path: page.path.clone(),
+                            body,
+                        })
+                    })
+                    .transpose()?;
+
                 Ok::<_, eyre::Report>(Some((
                     output_path,
-                    (
-                        content_matches,
-                        ManifestItem {
+                    PageOutput {
+                        change,
+                        manifest_item: ManifestItem {
                             title: page.title.to_string(),
                             slug,
                             uid: block.uid.clone(),
+                            redirect_to: None,
                         },
-                    ),
+                        search_entries,
+                        epub_chapter,
+                        feed_entry,
+                        sitemap_entry,
+                        link_problems,
+                    },
                 )))
             },
         )
@@ -492,23 +1161,612 @@ pub fn make_pages_from_script(
         // minimizing Git churn on the manifest.
         .collect::<Result<Vec<_>>>()?;
 
-    let manifest_data = results
+    if !namespaces.indexes.is_empty() && global_config.template.is_none() {
+        return Err(eyre!(
+            "Namespace index pages use the default template, but the config has no default template"
+        ));
+    }
+
+    for index in &namespaces.indexes {
+        let title = index.title();
+        let body = index.render(global_config.output_format);
+        let template_data = TemplateArgs {
+            title: &title,
+            body: &body,
+            tags: Vec::new(),
+            attrs: HashMap::default(),
+            created_time: index.create_time,
+            edited_time: index.edit_time,
+            backlinks: Vec::new(),
+            unlinked_mentions: Vec::new(),
+            plain_text: String::new(),
+            excerpt: String::new(),
+            breadcrumbs: namespaces.breadcrumbs(&index.parts),
+            tag_counts: Vec::new(),
+            previous_journal: None,
+            next_journal: None,
+        };
+        let full_page = handlebars.render("default", &template_data)?;
+
+        let output_path = create_path(
+            "",
+            default_output_dir.as_ref(),
+            &format!("{}.{}", index.url_name, global_config.extension),
+        );
+        let change = write_page(
+            &output_path,
+            &full_page,
+            global_config.safe_write,
+            global_config.dry_run.as_ref(),
+        )?;
+        if change.is_changed() && global_config.dry_run.is_none() {
+            println!("Wrote: namespace index \"{title}\" to {}", index.slug);
+        }
+
+        results.push((
+            output_path,
+            PageOutput {
+                change,
+                manifest_item: ManifestItem {
+                    title,
+                    slug: index.slug.clone(),
+                    uid: String::new(),
+                    redirect_to: None,
+                },
+                search_entries: Vec::new(),
+                epub_chapter: None,
+                feed_entry: None,
+                sitemap_entry: Some(SitemapEntry {
+                    slug: index.slug.clone(),
+                    edited_time: index.edit_time,
+                    priority: None,
+                }),
+                link_problems: Vec::new(),
+            },
+        ));
+    }
+
+    let mut listing_pages = Vec::new();
+    if let (Some(tag_pages), Some(tag_config), Some(template)) = (
+        tag_pages.as_ref(),
+        global_config.tag_pages.as_ref(),
+        tag_template.as_deref(),
+    ) {
+        listing_pages.extend(
+            tag_pages
+                .render(&tag_config.overview_title, global_config.output_format)
+                .into_iter()
+                .map(|page| ("tag page", template, page)),
+        );
+    }
+    if let (Some(archive), Some(template)) = (journal_archive.as_ref(), journal_template.as_deref())
+    {
+        listing_pages.extend(
+            archive
+                .render(global_config.output_format)
+                .into_iter()
+                .map(|page| ("journal archive", template, page)),
+        );
+    }
+
+    if !listing_pages.is_empty() {
+        let page_paths = results
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect::<HashSet<_>>();
+
+        for (kind, template, page) in listing_pages {
+            let output_path = create_path(
+                "",
+                default_output_dir.as_ref(),
+                &format!("{}.{}", page.url_name, global_config.extension),
+            );
+            if page_paths.contains(&output_path) {
+                return Err(eyre!(
+                    "The {kind} \"{}\" would overwrite the exported page at {output_path}. Set a different dir for the {kind} pages in the config.",
+                    page.title
+                ));
+            }
+
+            let template_data = TemplateArgs {
+                title: &page.title,
+                body: &page.body,
+                tags: Vec::new(),
+                attrs: HashMap::default(),
+                created_time: page.create_time,
+                edited_time: page.edit_time,
+                backlinks: Vec::new(),
+                unlinked_mentions: Vec::new(),
+                plain_text: String::new(),
+                excerpt: String::new(),
+                breadcrumbs: Vec::new(),
+                tag_counts: page.tag_counts,
+                previous_journal: None,
+                next_journal: None,
+            };
+            let full_page = handlebars.render(template, &template_data)?;
+
+            let change = write_page(
+                &output_path,
+                &full_page,
+                global_config.safe_write,
+                global_config.dry_run.as_ref(),
+            )?;
+            if change.is_changed() && global_config.dry_run.is_none() {
+                println!("Wrote: {kind} \"{}\" to {}", page.title, page.slug);
+            }
+
+            results.push((
+                output_path,
+                PageOutput {
+                    change,
+                    manifest_item: ManifestItem {
+                        title: page.title,
+                        slug: page.slug.clone(),
+                        uid: String::new(),
+                        redirect_to: None,
+                    },
+                    search_entries: Vec::new(),
+                    epub_chapter: None,
+                    feed_entry: None,
+                    sitemap_entry: Some(SitemapEntry {
+                        slug: page.slug,
+                        edited_time: page.edit_time,
+                        priority: None,
+                    }),
+                    link_problems: Vec::new(),
+                },
+            ));
+        }
+    }
+
+    // Skip any alias whose URL already belongs to an exported page or an earlier alias.
+    let mut used_paths = results
         .iter()
-        .map(|(k, (_, manifest_item))| (k, manifest_item))
-        .collect::<BTreeMap<_, _>>();
+        .map(|(path, _)| path.clone())
+        .collect::<HashSet<_>>();
+    for redirect in alias_redirects {
+        if !used_paths.insert(redirect.output_path.clone()) {
+            continue;
+        }
 
-    let manifest_path = global_config.output.join("manifest.json");
-    let mut manifest_writer = std::fs::File::create(&manifest_path)
-        .with_context(|| format!("Writing {}", manifest_path.display()))?;
-    serde_json::to_writer_pretty(&manifest_writer, &manifest_data)?;
-    manifest_writer.flush()?;
-    drop(manifest_writer);
+        let contents = redirect_page(&redirect.target_slug, &redirect.target_title);
+        let change = write_page(
+            &redirect.output_path,
+            &contents,
+            global_config.safe_write,
+            global_config.dry_run.as_ref(),
+        )?;
+        if change.is_changed() && global_config.dry_run.is_none() {
+            println!(
+                "Wrote: redirect from alias \"{}\" to {}",
+                redirect.alias, redirect.target_slug
+            );
+        }
+
+        results.push((
+            redirect.output_path,
+            PageOutput {
+                change,
+                manifest_item: ManifestItem {
+                    title: redirect.alias,
+                    slug: redirect.slug,
+                    uid: String::new(),
+                    redirect_to: Some(redirect.target_slug),
+                },
+                search_entries: Vec::new(),
+                epub_chapter: None,
+                feed_entry: None,
+                sitemap_entry: None,
+                link_problems: Vec::new(),
+            },
+        ));
+    }
+
+    match global_config.slug_redirects {
+        Some(SlugRedirects::Html) => {
+            for redirect in slug_redirects {
+                if !used_paths.insert(redirect.old_output_path.clone()) {
+                    continue;
+                }
+
+                let contents = redirect_page(&redirect.new_slug, &redirect.title);
+                let change = write_page(
+                    &redirect.old_output_path,
+                    &contents,
+                    global_config.safe_write,
+                    global_config.dry_run.as_ref(),
+                )?;
+                if change.is_changed() && global_config.dry_run.is_none() {
+                    println!(
+                        "Wrote: redirect from {} to {}",
+                        redirect.old_slug, redirect.new_slug
+                    );
+                }
+
+                results.push((
+                    redirect.old_output_path,
+                    PageOutput {
+                        change,
+                        manifest_item: ManifestItem {
+                            title: redirect.title,
+                            slug: redirect.old_slug,
+                            uid: String::new(),
+                            redirect_to: Some(redirect.new_slug),
+                        },
+                        search_entries: Vec::new(),
+                        epub_chapter: None,
+                        feed_entry: None,
+                        sitemap_entry: None,
+                        link_problems: Vec::new(),
+                    },
+                ));
+            }
+        }
+        Some(format @ (SlugRedirects::Netlify | SlugRedirects::Nginx)) => {
+            // Leave out any old slug that another page or alias now uses.
+            let used_slugs = results
+                .iter()
+                .map(|(_, output)| output.manifest_item.slug.as_str())
+                .collect::<HashSet<_>>();
+            let slug_redirects = slug_redirects
+                .into_iter()
+                .filter(|redirect| !used_slugs.contains(redirect.old_slug.as_str()))
+                .collect::<Vec<_>>();
+
+            let (filename, contents) = match format {
+                SlugRedirects::Netlify => ("_redirects", redirects_file(&slug_redirects)),
+                _ => ("nginx-redirects.map", nginx_map(&slug_redirects)),
+            };
+            let (path, output) =
+                write_generated_file(filename, "Slug redirects", &contents, global_config)?;
+            if output.change.is_changed() && global_config.dry_run.is_none() {
+                println!("Wrote: {} redirects to {path}", slug_redirects.len());
+            }
+            results.push((path, output));
+        }
+        None => {}
+    }
+
+    if let Some(feed_config) = global_config.feed.as_ref() {
+        let entries = results
+            .iter()
+            .filter_map(|(_, output)| output.feed_entry.clone())
+            .collect::<Vec<_>>();
+        let feeds = make_feeds(
+            feed_config,
+            global_config.site_origin.as_deref().unwrap_or_default(),
+            global_config.base_url.as_deref().unwrap_or("/"),
+            &entries,
+        );
+
+        for feed in feeds {
+            let (path, output) =
+                write_generated_file(&feed.path, "Feed", &feed.contents, global_config)?;
+            if output.change.is_changed() && global_config.dry_run.is_none() {
+                println!("Wrote: feed {path}");
+            }
+            results.push((path, output));
+        }
+    }
+
+    if global_config.sitemap {
+        let entries = results
+            .iter()
+            .filter_map(|(_, output)| output.sitemap_entry.clone())
+            .collect::<Vec<_>>();
+        let sitemaps = make_sitemaps(
+            global_config.site_origin.as_deref().unwrap_or_default(),
+            global_config.base_url.as_deref().unwrap_or("/"),
+            &entries,
+            MAX_SITEMAP_URLS,
+        );
+
+        for sitemap in sitemaps {
+            let (path, output) =
+                write_generated_file(&sitemap.path, "Sitemap", &sitemap.contents, global_config)?;
+            if output.change.is_changed() && global_config.dry_run.is_none() {
+                println!("Wrote: sitemap {path}");
+            }
+            results.push((path, output));
+        }
+    }
+
+    let current_paths = results
+        .iter()
+        .map(|(path, _)| path.as_str())
+        .collect::<HashSet<_>>();
+    let stale_outputs = find_stale_outputs(&previous_manifest, &current_paths);
+
+    let mut link_report = results
+        .iter()
+        .flat_map(|(_, output)| output.link_problems.iter().cloned())
+        .collect::<Vec<_>>();
 
     let skipped = results
         .iter()
-        .filter(|(_, (content_matched, _))| *content_matched)
+        .filter(|(_, output)| !output.change.is_changed())
         .count();
     let wrote = results.len() - skipped;
 
+    let manifest_data = results
+        .iter()
+        .map(|(k, output)| (k, &output.manifest_item))
+        .collect::<BTreeMap<_, _>>();
+    let mut export_files = vec![ExportFile {
+        path: manifest_path,
+        title: "Manifest",
+        contents: serde_json::to_string_pretty(&manifest_data)?,
+    }];
+
+    if let Some(search_config) = global_config.search_index.as_ref() {
+        let mut search_entries = results
+            .iter()
+            .flat_map(|(_, output)| output.search_entries.iter().cloned())
+            .collect::<Vec<SearchEntry>>();
+        search_entries.sort_by(|a, b| a.slug.cmp(&b.slug));
+        export_files.push(ExportFile {
+            path: global_config.output.join("search-index.json"),
+            title: "Search index",
+            contents: search_index_json(search_config, &search_entries)?,
+        });
+    }
+
+    export_files.push(ExportFile {
+        path: link_report_path.clone(),
+        title: "Link report",
+        contents: link_report_json(&mut link_report)?,
+    });
+
+    if global_config.dry_run.is_some() {
+        let mut export_changes = write_export_files(&export_files, global_config)?;
+        if let Some(epub_config) = global_config.epub.as_ref() {
+            export_changes.push((
+                epub_config.path.to_string_lossy().to_string(),
+                "EPUB",
+                rewritten_file_change(&epub_config.path),
+            ));
+        }
+
+        let planned = results
+            .iter()
+            .map(|(path, output)| PlannedOutput {
+                path,
+                title: &output.manifest_item.title,
+                change: &output.change,
+            })
+            .chain(
+                export_changes
+                    .iter()
+                    .map(|(path, title, change)| PlannedOutput {
+                        path,
+                        title,
+                        change,
+                    }),
+            )
+            .collect::<Vec<_>>();
+        print_plan(
+            &planned,
+            &stale_outputs,
+            global_config.stale_outputs,
+            &global_config.stale_output_dir,
+            &global_config.output,
+            &skipped_uploads,
+        );
+    } else {
+        handle_stale_outputs(
+            &stale_outputs,
+            global_config.stale_outputs,
+            &global_config.output,
+            &global_config.stale_output_dir,
+        )?;
+
+        write_export_files(&export_files, global_config)?;
+
+        if let Some(epub_config) = global_config.epub.as_ref() {
+            let chapters = results
+                .into_iter()
+                .filter_map(|(_, output)| output.epub_chapter)
+                .collect::<Vec<_>>();
+            write_epub(epub_config, &base_dir, chapters)?;
+            println!("Wrote EPUB to {}", epub_config.path.display());
+        }
+    }
+
+    if !link_report.is_empty() {
+        if global_config.dry_run.is_some() {
+            println!(
+                "Found {} unresolved references, which an export lists in {}",
+                link_report.len(),
+                link_report_path.display()
+            );
+        } else {
+            println!(
+                "Found {} unresolved references, listed in {}",
+                link_report.len(),
+                link_report_path.display()
+            );
+        }
+
+        if global_config.strict {
+            return Err(eyre!(
+                "Stopping because of unresolved references in strict mode"
+            ));
+        }
+    }
+
     Ok((wrote, skipped))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::{logseq::LogseqGraph, obsidian, template};
+
+    /// Export a graph made of `files`, with `config` added to the config file, and return the
+    /// directory containing the graph and the output. `$DIR` in `config` is replaced with the
+    /// directory.
+    fn export(files: &[(&str, &str)], config: &str) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let graph_dir = dir.path().join("graph");
+        std::fs::create_dir_all(graph_dir.join("journals")).unwrap();
+        for (name, contents) in files {
+            let path = graph_dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+
+        let script = dir.path().join("script.rhai");
+        std::fs::write(&script, "page.include = true;").unwrap();
+        let template = dir.path().join("page.tmpl");
+        std::fs::write(&template, "{{{body}}}").unwrap();
+
+        let config = Config::from_toml(&format!(
+            "data = {graph_dir:?}\noutput = {output:?}\nscript = {script:?}\ntemplate = {template:?}\ntrack_logseq_timestamps = false\n{config}",
+            output = dir.path().join("out"),
+            config = config.replace("$DIR", &dir.path().to_string_lossy()),
+        ))
+        .unwrap();
+        std::fs::create_dir_all(&config.output).unwrap();
+
+        let (explicit_ordering, pages) = match config.product {
+            PkmProduct::Obsidian => obsidian::read_vault(&config.path).unwrap(),
+            _ => LogseqGraph::build(config.path.clone(), None, false).unwrap(),
+        };
+        make_pages_from_script(
+            ExportInput {
+                pages,
+                explicit_ordering,
+                render_only: None,
+            },
+            &PageScript::compile(&config.script).unwrap(),
+            &mut template::create_registry(&config).unwrap(),
+            &syntax_highlight::Highlighter::new(None),
+            &config,
+            None,
+        )
+        .unwrap();
+
+        dir
+    }
+
+    const QUERY_PAGES: &[(&str, &str)] = &[
+        ("pages/Rust.md", "- Notes\n- {{query [[Rust]]}}\n"),
+        ("pages/DB.md", "- TODO fix the [[Rust]] index\n"),
+    ];
+
+    #[test]
+    fn epub_from_markdown_output_has_html_query_results() {
+        let dir = export(
+            QUERY_PAGES,
+            "output_format = \"markdown\"\nextension = \"md\"\n[epub]\npath = \"$DIR/book.epub\"\n",
+        );
+
+        let mut archive =
+            zip::ZipArchive::new(std::fs::File::open(dir.path().join("book.epub")).unwrap())
+                .unwrap();
+        let mut rust = String::new();
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).unwrap();
+            if file.name().ends_with(".xhtml") {
+                let mut chapter = String::new();
+                file.read_to_string(&mut chapter).unwrap();
+                if chapter.contains("<title>Rust</title>") {
+                    rust = chapter;
+                }
+            }
+        }
+
+        assert!(rust.contains(r#"<a class="query-result-page""#), "{rust}");
+        assert!(!rust.contains("]("), "{rust}");
+        assert!(!rust.contains("[ ]"), "{rust}");
+    }
+
+    #[test]
+    fn full_feed_from_markdown_output_has_html_query_results() {
+        let dir = export(
+            QUERY_PAGES,
+            "site_origin = \"https://example.com\"\noutput_format = \"markdown\"\nextension = \"md\"\n[feed]\ntitle = \"Notes\"\ncontent = \"full\"\n",
+        );
+
+        let feed = std::fs::read_to_string(dir.path().join("out/feed.xml")).unwrap();
+        assert!(feed.contains("query-result-page"), "{feed}");
+        assert!(!feed.contains("]("), "{feed}");
+        assert!(!feed.contains("[ ]"), "{feed}");
+    }
+
+    #[test]
+    fn obsidian_links_by_vault_path() {
+        let dir = export(
+            &[
+                (
+                    "Index.md",
+                    "See [[sub/Third]] and [[Third|the third note]]\n",
+                ),
+                ("sub/Third.md", "Nested\n"),
+            ],
+            "product = \"obsidian\"\nextension = \"html\"\n",
+        );
+
+        let index = std::fs::read_to_string(dir.path().join("out/index.html")).unwrap();
+        assert_eq!(index.matches(r#"href="/third""#).count(), 2, "{index}");
+
+        let report = std::fs::read_to_string(dir.path().join("out/link-report.json")).unwrap();
+        assert_eq!(report, "[]");
+    }
+
+    #[test]
+    fn dry_run_plans_export_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::from_toml(&format!(
+            "data = {path:?}\noutput = {path:?}\nscript = \"script.rhai\"\n",
+            path = dir.path()
+        ))
+        .unwrap();
+        config.dry_run = Some(DryRunOptions { diff: false });
+
+        std::fs::write(dir.path().join("manifest.json"), "{}").unwrap();
+        std::fs::write(dir.path().join("link-report.json"), "[]").unwrap();
+        let file = |name: &'static str, contents: &str| ExportFile {
+            path: dir.path().join(name),
+            title: name,
+            contents: contents.to_string(),
+        };
+
+        let changes = write_export_files(
+            &[
+                file("manifest.json", "{}"),
+                file("search-index.json", "[]"),
+                file("link-report.json", "[{}]"),
+            ],
+            &config,
+        )
+        .unwrap();
+        assert_eq!(
+            changes
+                .into_iter()
+                .map(|(_, title, change)| (title, change))
+                .collect::<Vec<_>>(),
+            vec![
+                ("manifest.json", OutputChange::Unchanged),
+                ("search-index.json", OutputChange::Created),
+                ("link-report.json", OutputChange::Updated { diff: None }),
+            ]
+        );
+        assert!(!dir.path().join("search-index.json").exists());
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("link-report.json")).unwrap(),
+            "[]"
+        );
+
+        let epub_path = dir.path().join("book.epub");
+        assert_eq!(rewritten_file_change(&epub_path), OutputChange::Created);
+        std::fs::write(&epub_path, "").unwrap();
+        assert_eq!(
+            rewritten_file_change(&epub_path),
+            OutputChange::Updated { diff: None }
+        );
+    }
+}
@@ -0,0 +1,989 @@
+//! Render pages as Markdown instead of HTML, for publishing to systems that want Markdown
+//! bodies. The output is CommonMark, plus the GitHub Flavored Markdown extensions for tables,
+//! strikethrough, and task lists.
+
+use std::{borrow::Cow, cell::Cell, path::Path};
+
+use ahash::{HashMap, HashSet};
+use eyre::{eyre, Result};
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use urlencoding::encode;
+
+use crate::{
+    graph::{Block, BlockInclude, Graph, ListType, ViewType},
+    image::{image_full_path, ImageInfo},
+    link_report::{LinkKind, LinkProblemReason, LinkProblems},
+    mentions::MentionMatcher,
+    page::{lookup_page_by_title, visible_query_result, IdSlugUid, TitleSlugUid},
+    parse_string::{parse, Expression},
+    plain_text::PlainTextRenderer,
+    query::{
+        advanced::{QueryResults, Value},
+        evaluate_query,
+        simple::query_from_directive,
+        PageQuery,
+    },
+};
+
+fn escape_char(c: char) -> bool {
+    matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '|' | '~')
+}
+
+/// Escape the characters that Markdown would otherwise treat as markup.
+pub fn escape(input: &str) -> Cow<'_, str> {
+    if !input.contains(escape_char) {
+        return Cow::Borrowed(input);
+    }
+
+    let mut output = String::with_capacity(input.len() + 8);
+    for c in input.chars() {
+        if escape_char(c) {
+            output.push('\\');
+        }
+        output.push(c);
+    }
+
+    Cow::Owned(output)
+}
+
+/// Escape the characters that would end a link destination early.
+pub fn escape_url(url: &str) -> String {
+    url.replace(' ', "%20")
+        .replace('(', "%28")
+        .replace(')', "%29")
+}
+
+/// Make sure the output ends with a blank line, so that the next thing written starts a new
+/// Markdown block.
+fn push_block_separator(output: &mut String) {
+    if output.is_empty() {
+        return;
+    }
+
+    while !output.ends_with("\n\n") {
+        output.push('\n');
+    }
+}
+
+/// Prefix the first line of `text` with `first`, and indent the remaining lines to match.
+fn indent(text: &str, first: &str) -> String {
+    let rest = " ".repeat(first.len());
+    let mut output = String::with_capacity(text.len() + first.len());
+    for (i, line) in text.split('\n').enumerate() {
+        if i == 0 {
+            output.push_str(first);
+        } else {
+            output.push('\n');
+            if !line.is_empty() {
+                output.push_str(&rest);
+            }
+        }
+        output.push_str(line);
+    }
+
+    output
+}
+
+/// Render rows of cells into a table. The first row is used as the header, since Markdown
+/// tables require one.
+fn render_table_rows(rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    if columns == 0 {
+        return String::new();
+    }
+
+    let render_row = |row: &[String]| {
+        let cells = (0..columns)
+            .map(|i| {
+                row.get(i)
+                    .map(|cell| cell.replace('\n', "<br>"))
+                    .unwrap_or_default()
+            })
+            .join(" | ");
+        format!("| {cells} |")
+    };
+
+    let mut lines = Vec::with_capacity(rows.len() + 1);
+    lines.push(render_row(&rows[0]));
+    lines.push(format!("|{}", " --- |".repeat(columns)));
+    lines.extend(rows[1..].iter().map(|row| render_row(row)));
+    lines.join("\n")
+}
+
+pub struct MarkdownRenderer<'a> {
+    pub page_id: usize,
+    pub title: &'a str,
+    pub graph: &'a Graph,
+    pub base_dir: &'a Path,
+    pub path: &'a Path,
+    pub heading_delta: isize,
+    pub pages_by_title: &'a HashMap<String, IdSlugUid>,
+    pub pages_by_filename_title: &'a HashMap<String, String>,
+    pub pages_by_id: &'a HashMap<usize, TitleSlugUid>,
+    pub omitted_attributes: &'a HashSet<&'a str>,
+    pub image_info: &'a HashMap<String, ImageInfo>,
+    /// When set, plain-text mentions of exported pages are rendered as links.
+    pub mention_matcher: Option<&'a MentionMatcher>,
+    pub filter_link_only_blocks: bool,
+    pub convert_emdash: bool,
+    pub latest_found_edit_time: &'a Cell<u64>,
+    pub link_problems: &'a LinkProblems,
+}
+
+impl<'a> MarkdownRenderer<'a> {
+    /// Render text as Markdown, escaping any markup characters and performing any enabled
+    /// transformations such as converting -- into an emdash.
+    fn render_text<'tx>(&self, text: &'tx str) -> Cow<'tx, str> {
+        static TWODASH: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(^|[^-])--([^-]|$)"#).unwrap());
+
+        let escaped = escape(text);
+        if self.convert_emdash && escaped.contains("--") {
+            let rep = TWODASH
+                .replace_all(&escaped, |caps: &Captures| {
+                    format!("{}\u{2014}{}", &caps[1], &caps[2])
+                })
+                .into_owned();
+            Cow::from(rep)
+        } else {
+            escaped
+        }
+    }
+
+    fn render_text_with_mentions(&self, text: &str) -> String {
+        let Some(matcher) = self.mention_matcher else {
+            return self.render_text(text).into_owned();
+        };
+
+        let mut output = String::with_capacity(text.len());
+        let mut last_end = 0;
+        for (range, title) in matcher.find_iter(text) {
+            let Some(page) = self
+                .lookup_page_by_title(title)
+                .filter(|p| p.include && p.id != self.page_id)
+            else {
+                continue;
+            };
+
+            output.push_str(&self.render_text(&text[last_end..range.start]));
+            output.push_str(&format!(
+                "[{label}]({slug})",
+                label = self.render_text(&text[range.clone()]),
+                slug = escape_url(&page.slug)
+            ));
+            last_end = range.end;
+        }
+
+        output.push_str(&self.render_text(&text[last_end..]));
+        output
+    }
+
+    fn lookup_page_by_title(&self, title: &str) -> Option<&IdSlugUid> {
+        lookup_page_by_title(self.pages_by_title, self.pages_by_filename_title, title)
+    }
+
+    fn lookup_page_id(&self, title: &str) -> Option<usize> {
+        self.lookup_page_by_title(title).map(|page| page.id)
+    }
+
+    fn plain_text(&self) -> PlainTextRenderer<'a> {
+        PlainTextRenderer {
+            graph: self.graph,
+            pages_by_title: self.pages_by_title,
+            pages_by_filename_title: self.pages_by_filename_title,
+            omitted_attributes: self.omitted_attributes,
+        }
+    }
+
+    fn link_if_allowed_with_label(
+        &self,
+        page: &str,
+        label: Option<&str>,
+        omit_unexported_links: bool,
+    ) -> String {
+        match self.lookup_page_by_title(page).filter(|p| p.include) {
+            Some(IdSlugUid {
+                slug, output_title, ..
+            }) => format!(
+                "[{title}]({slug})",
+                title = self.render_text(label.unwrap_or(output_title.as_str())),
+                slug = escape_url(slug)
+            ),
+            None if omit_unexported_links => String::new(),
+            None => self.render_text(label.unwrap_or(page)).into_owned(),
+        }
+    }
+
+    fn render_block_ref(&self, containing_block: &Block, s: &str) -> Result<(String, bool, bool)> {
+        let Some(block) = self.graph.block_from_uid(s) else {
+            self.link_problems
+                .check_missing_block_ref(containing_block, s);
+
+            // Block ref syntax can also be expandable text. So if we don't match on a block then just render it.
+            let expressions = parse(*containing_block.contents.borrow_style(), s)
+                .map_err(|e| eyre!("Parse Error: {}", e))?;
+            let (rendered, render_children) =
+                self.render_expressions(containing_block, &expressions, false, false)?;
+            return Ok((rendered, true, render_children));
+        };
+
+        let page = self
+            .pages_by_id
+            .get(&block.containing_page)
+            .filter(|p| p.include);
+        if page.is_none() {
+            self.link_problems.add(
+                containing_block,
+                LinkKind::BlockRef,
+                s,
+                LinkProblemReason::UnexportedPage,
+            );
+        }
+
+        let rendered = match page {
+            // When the referenced page is exported, make this a link to the block. Links can't
+            // contain other links, so the block is rendered as plain text.
+            Some(page) => format!(
+                "[{text}]({slug}#{uid})",
+                text = self.render_text(&self.plain_text().block_text(block)),
+                slug = escape_url(&page.slug),
+                uid = block.uid
+            ),
+            None => self.render_line_without_header(block)?.0,
+        };
+
+        Ok((rendered, true, true))
+    }
+
+    fn render_image(&self, url: &str, alt: &str) -> String {
+        let uploaded_url = image_full_path(self.base_dir, self.path, url)
+            .and_then(|path| self.image_info.get(path.to_string_lossy().as_ref()))
+            .and_then(|info| {
+                let output = &info.data.output;
+                output
+                    .iter()
+                    .find(|o| o.format == "jpg" || o.format == "png")
+                    .or_else(|| output.first())
+            })
+            .map(|output| output.url.as_str());
+
+        format!(
+            "![{alt}]({url})",
+            alt = escape(alt),
+            url = escape_url(uploaded_url.unwrap_or(url))
+        )
+    }
+
+    fn render_embedded_block(&self, block: &Block) -> Result<String> {
+        let rendered =
+            self.render_block_and_children(block, ViewType::default_view_type(), None)?;
+        let mut output = String::from("\n\n");
+        output.push_str(&rendered);
+        push_block_separator(&mut output);
+        Ok(output)
+    }
+
+    fn render_page_embed(&self, containing_block: &Block, title: &str) -> Result<String> {
+        let page = self.lookup_page_by_title(title);
+        self.link_problems
+            .check_page(containing_block, LinkKind::PageEmbed, title, page);
+
+        let Some(block) = page
+            .filter(|p| p.allow_embed)
+            .and_then(|p| self.graph.blocks.get(&p.id))
+        else {
+            return Ok(String::new());
+        };
+
+        let rendered =
+            self.render_block_and_children(block, ViewType::default_view_type(), None)?;
+        Ok(format!(
+            "\n\n**{title}**\n\n{rendered}\n\n",
+            title = self.render_text(title)
+        ))
+    }
+
+    fn descend_table_child(&self, row: Vec<String>, id: usize) -> Result<Vec<Vec<String>>> {
+        let Some(block) = self.graph.blocks.get(&id) else {
+            return Ok(vec![row]);
+        };
+
+        let mut row = row;
+        row.push(self.render_line_without_header(block)?.0);
+
+        if block.children.is_empty() {
+            return Ok(vec![row]);
+        }
+
+        let mut rows = Vec::new();
+        for &child in &block.children {
+            rows.extend(self.descend_table_child(row.clone(), child)?);
+        }
+        Ok(rows)
+    }
+
+    /// Render a block containing a table into a Markdown table.
+    fn render_table(&self, block: &Block) -> Result<String> {
+        let mut rows = Vec::new();
+        for &id in &block.children {
+            rows.extend(self.descend_table_child(Vec::new(), id)?);
+        }
+
+        Ok(format!("\n\n{}\n\n", render_table_rows(&rows)))
+    }
+
+    fn query_result(&self, block: &Block, id: usize) -> Option<(&'a Block, &'a TitleSlugUid)> {
+        visible_query_result(self.graph, self.pages_by_id, block, id)
+    }
+
+    /// Render a link to a query result. Blocks are shown with their contents.
+    fn render_query_result(&self, result: &Block, page: &TitleSlugUid) -> Result<String> {
+        let title = self.render_text(&page.output_title);
+        let slug = escape_url(&page.slug);
+        if result.page_title.is_some() {
+            return Ok(format!("[{title}]({slug})"));
+        }
+
+        let href = if result.uid.is_empty() {
+            slug
+        } else {
+            format!("{slug}#{}", result.uid)
+        };
+
+        let snippet = self.render_snippet(result)?;
+        // A task marker has to come first for the result to be shown as a task list item.
+        let task = match result.contents.borrow_parsed().first() {
+            Some(Expression::Todo { .. }) => ["[ ] ", "[x] "]
+                .into_iter()
+                .find_map(|marker| Some((marker, snippet.strip_prefix(marker)?))),
+            _ => None,
+        };
+
+        Ok(match task {
+            Some((marker, rest)) => format!("{marker}[{title}]({href}) {rest}"),
+            None => format!("[{title}]({href}) {snippet}"),
+        })
+    }
+
+    fn render_query_list(&self, block: &Block, ids: Vec<usize>) -> Result<String> {
+        let items = ids
+            .into_iter()
+            .filter_map(|id| self.query_result(block, id))
+            .map(|(result, page)| {
+                self.render_query_result(result, page)
+                    .map(|link| indent(&link, "- "))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if items.is_empty() {
+            return Ok(String::new());
+        }
+
+        Ok(format!("\n\n{}\n\n", items.join("\n")))
+    }
+
+    fn render_query_value(&self, block: &Block, value: &Value) -> Result<Option<String>> {
+        let rendered = match value {
+            Value::Entity(id) => match self.query_result(block, *id) {
+                Some((result, page)) => self.render_query_result(result, page)?,
+                None => return Ok(None),
+            },
+            Value::Str(s) => self.render_text(s).into_owned(),
+            Value::Int(i) => i.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Key(k) => format!(":{}", escape(k)),
+            Value::Set(values) => {
+                let mut rendered = Vec::with_capacity(values.len());
+                for value in values {
+                    match self.render_query_value(block, value)? {
+                        Some(v) => rendered.push(v),
+                        None => return Ok(None),
+                    }
+                }
+                rendered.join(", ")
+            }
+            Value::Properties(id) => self
+                .graph
+                .blocks
+                .get(id)
+                .map(|b| {
+                    b.attrs
+                        .iter()
+                        .sorted_by(|a, b| a.0.cmp(b.0))
+                        .map(|(name, values)| {
+                            self.render_text(&format!("{name}: {}", values.join(", ")))
+                                .into_owned()
+                        })
+                        .join("<br>")
+                })
+                .unwrap_or_default(),
+        };
+
+        Ok(Some(rendered))
+    }
+
+    fn render_query_table(
+        &self,
+        block: &Block,
+        columns: &[String],
+        rows: &[Vec<Value>],
+    ) -> Result<String> {
+        let mut rendered_rows = vec![columns.iter().map(|c| escape(c).into_owned()).collect()];
+        'rows: for row in rows {
+            let mut cells = Vec::with_capacity(row.len());
+            for value in row {
+                // Leave out rows that refer to anything that isn't exported.
+                let Some(cell) = self.render_query_value(block, value)? else {
+                    continue 'rows;
+                };
+                cells.push(cell);
+            }
+            rendered_rows.push(cells);
+        }
+
+        if rendered_rows.len() == 1 {
+            return Ok(String::new());
+        }
+
+        Ok(format!("\n\n{}\n\n", render_table_rows(&rendered_rows)))
+    }
+
+    fn render_query(&self, block: &Block, query: PageQuery) -> Result<String> {
+        let lookup_page = |title: &str| self.lookup_page_id(title);
+        let Some((title, results)) = evaluate_query(self.graph, query, self.title, &lookup_page)
+        else {
+            return Ok(String::new());
+        };
+
+        let rendered = match results {
+            QueryResults::Entities(ids) => self.render_query_list(block, ids)?,
+            QueryResults::Table { columns, rows } => {
+                self.render_query_table(block, &columns, &rows)?
+            }
+        };
+
+        match title {
+            Some(title) if !rendered.trim().is_empty() => Ok(format!(
+                "\n\n**{title}**{rendered}",
+                title = self.render_text(&title)
+            )),
+            _ => Ok(rendered),
+        }
+    }
+
+    fn render_brace_directive(&self, block: &Block, s: &str) -> Result<(String, bool, bool)> {
+        let (value, render_children) = match s {
+            "table" => (self.render_table(block)?, false),
+            _ => {
+                if let Some(query) = query_from_directive(s) {
+                    (self.render_query(block, PageQuery::Simple(&query))?, true)
+                } else if s.starts_with("renderer ") {
+                    (String::new(), true)
+                } else {
+                    (format!("`` {s} ``"), true)
+                }
+            }
+        };
+
+        Ok((value, true, render_children))
+    }
+
+    fn render_style(
+        &self,
+        block: &Block,
+        open: &str,
+        close: &str,
+        e: &[Expression],
+    ) -> Result<(String, bool, bool)> {
+        self.render_expressions(block, e, false, false)
+            .map(|(s, rc)| (format!("{open}{s}{close}"), true, rc))
+    }
+
+    fn render_expressions(
+        &self,
+        block: &Block,
+        e: &[Expression],
+        first: bool,
+        omit_unexported_links: bool,
+    ) -> Result<(String, bool)> {
+        let mut line = String::new();
+        let mut should_render = false;
+        let mut render_children = true;
+        for (i, e) in e.iter().enumerate() {
+            let (s, must_render, this_render_children) =
+                self.render_expression(block, e, first && i == 0, omit_unexported_links)?;
+            should_render = should_render || (!s.trim().is_empty() && must_render);
+            render_children = render_children && this_render_children;
+
+            // Keep exactly one blank line around block-level output like code and tables.
+            if s.starts_with('\n') && !line.is_empty() {
+                line.truncate(line.trim_end().len());
+                line.push_str("\n\n");
+                line.push_str(s.trim_start_matches('\n'));
+            } else if line.ends_with("\n\n") {
+                line.push_str(s.trim_start());
+            } else {
+                line.push_str(&s);
+            }
+        }
+
+        if should_render {
+            Ok((line, render_children))
+        } else {
+            Ok((String::new(), render_children))
+        }
+    }
+
+    fn render_attribute(
+        &self,
+        block: &Block,
+        name: &str,
+        contents: &[Expression],
+        first: bool,
+    ) -> Result<(String, bool, bool)> {
+        if self.omitted_attributes.contains(name) {
+            return Ok((String::new(), false, true));
+        }
+
+        self.render_expressions(block, contents, false, false)
+            .map(|(s, rc)| {
+                let separator = if first { "" } else { "\\\n" };
+                (format!("{separator}**{}:** {s}", escape(name)), true, rc)
+            })
+    }
+
+    fn render_expression(
+        &self,
+        block: &Block,
+        e: &Expression,
+        first: bool,
+        omit_unexported_links: bool,
+    ) -> Result<(String, bool, bool)> {
+        let rendered = match e {
+            Expression::Hashtag(s, _) | Expression::Link(s) => {
+                let kind = match e {
+                    Expression::Hashtag(..) => LinkKind::Tag,
+                    _ => LinkKind::Link,
+                };
+                self.link_problems
+                    .check_page(block, kind, s, self.lookup_page_by_title(s));
+                (
+                    self.link_if_allowed_with_label(s, None, omit_unexported_links),
+                    true,
+                    true,
+                )
+            }
+            Expression::RawHtml(s) => (s.to_string(), true, true),
+            Expression::Image { alt, url } => {
+                self.link_problems
+                    .check_image(block, self.base_dir, self.path, url);
+                (self.render_image(url, alt), true, true)
+            }
+            Expression::Video { url } => (
+                format!(
+                    r##"<video controls src="{u}" width="800" height="450"></video>"##,
+                    u = encode(url)
+                ),
+                true,
+                true,
+            ),
+            Expression::Todo { done } => {
+                let marker = if *done { "[x]" } else { "[ ]" };
+                (marker.to_string(), false, true)
+            }
+            Expression::MarkdownInternalLink { page, label } => {
+                self.link_problems.check_page(
+                    block,
+                    LinkKind::Link,
+                    page,
+                    self.lookup_page_by_title(page),
+                );
+                (
+                    self.link_if_allowed_with_label(page, Some(label), false),
+                    true,
+                    true,
+                )
+            }
+            Expression::MarkdownExternalLink { title, url } => (
+                format!(
+                    "[{title}]({url})",
+                    title = self.render_text(title),
+                    url = escape_url(url)
+                ),
+                true,
+                true,
+            ),
+            Expression::RawHyperlink(h) => (format!("<{h}>"), true, true),
+            Expression::SingleBacktick(s) => {
+                // Use a longer fence when the code itself contains backticks.
+                let fence = if s.contains('`') { "``" } else { "`" };
+                (format!("{fence}{s}{fence}"), true, true)
+            }
+            Expression::TripleBacktick(s) => {
+                let newline = if s.ends_with('\n') { "" } else { "\n" };
+                (format!("\n\n```{s}{newline}```\n\n"), true, true)
+            }
+            Expression::Bold(e) => self.render_style(block, "**", "**", e)?,
+            Expression::Italic(e) => self.render_style(block, "_", "_", e)?,
+            Expression::Strike(e) => self.render_style(block, "~~", "~~", e)?,
+            Expression::Highlight(e) => self.render_style(block, "<mark>", "</mark>", e)?,
+            Expression::Latex(e) => (format!("$${e}$$"), true, true),
+            Expression::BlockQuote(e) => {
+                let (s, rc) = self.render_expressions(block, e, false, false)?;
+                let quoted = s
+                    .trim_matches('\n')
+                    .split('\n')
+                    .map(|line| {
+                        if line.is_empty() {
+                            String::from(">")
+                        } else {
+                            format!("> {line}")
+                        }
+                    })
+                    .join("\n");
+                (format!("\n\n{quoted}\n\n"), true, rc)
+            }
+            Expression::Text(s) => (self.render_text_with_mentions(s), true, true),
+            Expression::BlockRef(s) => self.render_block_ref(block, s)?,
+            Expression::BraceDirective(s) => self.render_brace_directive(block, s)?,
+            Expression::AdvancedQuery(s) => (
+                self.render_query(block, PageQuery::Advanced(s))?,
+                true,
+                true,
+            ),
+            Expression::Table => (self.render_table(block)?, true, false),
+            Expression::HRule => (String::from("\n\n---\n\n"), true, true),
+            Expression::BlockEmbed(s) => {
+                let rendered = match self.graph.block_from_uid(s) {
+                    Some(embedded) => self.render_embedded_block(embedded)?,
+                    None => {
+                        self.link_problems.add(
+                            block,
+                            LinkKind::BlockEmbed,
+                            s,
+                            LinkProblemReason::MissingBlock,
+                        );
+                        String::new()
+                    }
+                };
+                (rendered, true, true)
+            }
+            Expression::PageEmbed(s) => (self.render_page_embed(block, s)?, true, true),
+            Expression::Attribute { name, value } => {
+                self.render_attribute(block, name, value, first)?
+            }
+        };
+
+        Ok(rendered)
+    }
+
+    fn render_line_without_header(&self, block: &Block) -> Result<(String, bool)> {
+        let parsed = block.contents.borrow_parsed();
+        let filter_links = self.filter_link_only_blocks
+            && parsed.iter().all(|e| match e {
+                Expression::Link(_) => true,
+                Expression::Hashtag(_, _) => true,
+                Expression::Todo { .. } => true,
+                Expression::Text(t) => t.trim().is_empty(),
+                _ => false,
+            });
+
+        self.render_expressions(block, parsed, true, filter_links)
+    }
+
+    /// Render a block's contents, with the heading and an anchor for the block references that
+    /// link to it.
+    fn render_line(&self, block: &Block) -> Result<(String, bool)> {
+        let (rendered, render_children) = self.render_line_without_header(block)?;
+        // Block-level output such as code and tables starts with a blank line, and can't have
+        // a heading marker or anything else in front of it.
+        let starts_with_block = rendered.starts_with('\n');
+        let rendered = rendered.trim_matches('\n');
+        if rendered.trim().is_empty() {
+            return Ok((String::new(), render_children));
+        }
+
+        let heading_level = if block.heading > 0 && !starts_with_block {
+            (block.heading as isize + self.heading_delta).clamp(1, 6) as usize
+        } else {
+            0
+        };
+
+        let mut output = String::with_capacity(rendered.len() + 16);
+        if heading_level > 0 {
+            output.push_str(&"#".repeat(heading_level));
+            output.push(' ');
+        }
+
+        if !block.uid.is_empty() {
+            output.push_str(&format!(r##"<a id="{}"></a>"##, block.uid));
+            if starts_with_block {
+                output.push('\n');
+            }
+        }
+
+        // Keep text that looks like Markdown block syntax from being treated as such.
+        if heading_level == 0
+            && !starts_with_block
+            && rendered.starts_with(['#', '>', '-', '+', '='])
+        {
+            output.push('\\');
+        }
+
+        output.push_str(rendered);
+        Ok((output, render_children))
+    }
+
+    /// Render a block and its children. `list_marker` is the marker to use when the block is
+    /// an item in its parent's list.
+    fn render_block_and_children(
+        &self,
+        block: &Block,
+        inherited_view_type: ViewType,
+        list_marker: Option<&str>,
+    ) -> Result<String> {
+        let (rendered, render_children, own_item) = match block.include_type {
+            BlockInclude::Exclude => return Ok(String::new()),
+            BlockInclude::JustBlock => (self.render_line(block)?.0, false, true),
+            BlockInclude::AndChildren | BlockInclude::IfChildrenPresent => {
+                let (rendered, render_children) = self.render_line(block)?;
+                (rendered, render_children, true)
+            }
+            BlockInclude::OnlyChildren => (String::new(), true, false),
+        };
+
+        if block.edit_time > self.latest_found_edit_time.get() {
+            self.latest_found_edit_time.set(block.edit_time);
+        }
+
+        let view_type = block.view_type.resolve_with_parent(inherited_view_type);
+        let children = if render_children {
+            self.graph.children(block)
+        } else {
+            Vec::new()
+        };
+
+        // When the block isn't rendered, its children take its place in the parent's list.
+        let child_marker = if !own_item {
+            list_marker.filter(|_| view_type != ViewType::Document)
+        } else if view_type == ViewType::Numbered
+            || children
+                .iter()
+                .any(|c| c.this_block_list_type == ListType::Number)
+        {
+            Some("1.")
+        } else if view_type == ViewType::Bullet {
+            Some("-")
+        } else {
+            None
+        };
+
+        let mut rendered_children = Vec::with_capacity(children.len());
+        for child in children {
+            let child_content = self.render_block_and_children(child, view_type, child_marker)?;
+            if !child_content.is_empty() {
+                rendered_children.push(child_content);
+            }
+        }
+
+        if block.include_type == BlockInclude::IfChildrenPresent && rendered_children.is_empty() {
+            return Ok(String::new());
+        }
+
+        let children_separator = if child_marker.is_some() { "\n" } else { "\n\n" };
+        let children_text = rendered_children.join(children_separator);
+
+        let mut output = rendered;
+        if !output.is_empty() && !children_text.is_empty() {
+            // A nested list can directly follow a list item's text, but anything else needs to
+            // be a separate paragraph.
+            if list_marker.is_some() && child_marker.is_some() {
+                output.push('\n');
+            } else {
+                output.push_str("\n\n");
+            }
+        }
+        output.push_str(&children_text);
+
+        match list_marker {
+            Some(marker) if own_item && !output.is_empty() => {
+                Ok(indent(&output, &format!("{marker} ")))
+            }
+            _ => Ok(output),
+        }
+    }
+
+    /// Render just the contents of a block, without its heading or children.
+    pub fn render_snippet(&self, block: &Block) -> Result<String> {
+        self.render_line_without_header(block)
+            .map(|(result, _)| result.trim_matches('\n').to_string())
+    }
+
+    pub fn render(&self) -> Result<String> {
+        let block = self.graph.blocks.get(&self.page_id).unwrap();
+        let mut output =
+            self.render_block_and_children(block, ViewType::default_view_type(), None)?;
+        if !output.is_empty() {
+            output.push('\n');
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(id: usize, parent: usize, contents: &str, children: &[usize]) -> Block {
+        Block {
+            parent: (id != 0).then_some(parent),
+            children: children.iter().copied().collect(),
+            ..Block::test(id, 0, contents)
+        }
+    }
+
+    fn page_info(id: usize, title: &str, slug: &str, include: bool) -> IdSlugUid {
+        IdSlugUid {
+            id,
+            output_title: title.to_string(),
+            slug: slug.to_string(),
+            uid: String::new(),
+            include,
+            allow_embed: false,
+        }
+    }
+
+    fn render(graph: &Graph, heading_delta: isize) -> String {
+        let pages_by_title = HashMap::from_iter([
+            (
+                String::from("exported"),
+                page_info(10, "Exported", "/exported", true),
+            ),
+            (
+                String::from("private"),
+                page_info(11, "Private", "/private", false),
+            ),
+        ]);
+        let pages_by_filename_title = HashMap::default();
+        let pages_by_id = HashMap::from_iter([(
+            10,
+            TitleSlugUid {
+                title: String::from("Exported"),
+                output_title: String::from("Exported"),
+                slug: String::from("/exported"),
+                uid: String::new(),
+                include: true,
+                allow_embed: false,
+            },
+        )]);
+        let omitted_attributes = HashSet::default();
+        let image_info = HashMap::default();
+        let latest_found_edit_time = Cell::new(0);
+        let link_problems = LinkProblems::default();
+        let renderer = MarkdownRenderer {
+            page_id: 0,
+            title: "Page",
+            graph,
+            base_dir: Path::new("."),
+            path: Path::new("page.md"),
+            heading_delta,
+            pages_by_title: &pages_by_title,
+            pages_by_filename_title: &pages_by_filename_title,
+            pages_by_id: &pages_by_id,
+            omitted_attributes: &omitted_attributes,
+            image_info: &image_info,
+            mention_matcher: None,
+            filter_link_only_blocks: true,
+            convert_emdash: false,
+            latest_found_edit_time: &latest_found_edit_time,
+            link_problems: &link_problems,
+        };
+
+        renderer.render().unwrap()
+    }
+
+    #[test]
+    fn lists_and_headings() {
+        let mut graph = Graph::new(false);
+        let mut page = block(0, 0, "", &[1, 2, 5]);
+        page.page_title = Some(String::from("Page"));
+        graph.add_block(page);
+
+        let mut heading = block(1, 0, "A heading", &[]);
+        heading.heading = 1;
+        graph.add_block(heading);
+
+        let mut numbered = block(2, 0, "Steps with [[Exported]] and [[Private]]", &[3, 4]);
+        numbered.view_type = ViewType::Numbered;
+        graph.add_block(numbered);
+        graph.add_block(block(3, 2, "**First** `step`", &[]));
+        graph.add_block(block(4, 2, "TODO Second_step", &[]));
+        graph.add_block(block(5, 0, "[[Private]]", &[]));
+
+        assert_eq!(
+            render(&graph, 1),
+            "- ## A heading\n- Steps with [Exported](/exported) and Private\n  1. **First** `step`\n  1. [ ] Second\\_step\n"
+        );
+    }
+
+    #[test]
+    fn document_view_and_code() {
+        let mut graph = Graph::new(false);
+        let mut page = block(0, 0, "", &[1, 3]);
+        page.page_title = Some(String::from("Page"));
+        page.view_type = ViewType::Document;
+        graph.add_block(page);
+
+        graph.add_block(block(1, 0, "Intro\n```rust\nlet x = 1;\n```", &[2]));
+        graph.add_block(block(2, 1, "A child paragraph", &[]));
+        let mut excluded = block(3, 0, "Excluded", &[]);
+        excluded.include_type = BlockInclude::Exclude;
+        graph.add_block(excluded);
+
+        assert_eq!(
+            render(&graph, 0),
+            "Intro\n\n```rust\nlet x = 1;\n```\n\nA child paragraph\n"
+        );
+    }
+
+    #[test]
+    fn query_results() {
+        let mut graph = Graph::new(false);
+        let mut page = block(0, 0, "", &[1]);
+        page.page_title = Some(String::from("Page"));
+        graph.add_block(page);
+        graph.add_block(block(1, 0, "{{query [[Exported]]}}", &[]));
+
+        let mut exported = Block {
+            children: smallvec::smallvec![11, 12],
+            ..Block::test(10, 10, "")
+        };
+        exported.page_title = Some(String::from("Exported"));
+        graph.add_block(exported);
+        graph.add_block(Block {
+            parent: Some(10),
+            ..Block::test(11, 10, "TODO Write the docs")
+        });
+        graph.add_block(Block {
+            parent: Some(10),
+            ..Block::test(12, 10, "Published")
+        });
+
+        assert_eq!(
+            render(&graph, 0),
+            "- - [ ] [Exported](/exported) Write the docs\n  - [Exported](/exported) Published\n"
+        );
+    }
+
+    #[test]
+    fn tables() {
+        assert_eq!(
+            render_table_rows(&[
+                vec![String::from("Name"), String::from("Value")],
+                vec![String::from("a")],
+            ]),
+            "| Name | Value |\n| --- | --- |\n| a |  |"
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use std::ops::Range;
+
+use ahash::{HashMap, HashSet};
+use eyre::Result;
+use itertools::Itertools;
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+use crate::{
+    graph::{Block, ParsedPage},
+    parse_string::Expression,
+    references::{collect_references, Reference},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnlinkedMentionsMode {
+    /// Find unlinked mentions and pass them to the script and templates.
+    Detect,
+    /// Find unlinked mentions, and also render them as links when the mentioned page is exported.
+    Autolink,
+}
+
+/// Finds plain-text mentions of page titles and aliases.
+pub struct MentionMatcher {
+    regex: Regex,
+    /// Lowercased mention text to the title of the page it refers to.
+    titles: HashMap<String, String>,
+}
+
+impl MentionMatcher {
+    /// Create a matcher from the titles and aliases of the pages in the graph.
+    /// Returns `None` if there is nothing to match.
+    pub fn new<'a>(pages: impl Iterator<Item = &'a Block>) -> Result<Option<MentionMatcher>> {
+        let mut titles = HashMap::default();
+        for block in pages {
+            let Some(title) = block.page_title.as_deref() else {
+                continue;
+            };
+
+            for mention in std::iter::once(title.to_string()).chain(block.aliases()) {
+                let mention = mention.trim().to_lowercase();
+                if !mention.is_empty() {
+                    titles.entry(mention).or_insert_with(|| title.to_string());
+                }
+            }
+        }
+
+        if titles.is_empty() {
+            return Ok(None);
+        }
+
+        // Put the longest titles first so that they take precedence over any shorter titles that
+        // they contain.
+        let pattern = titles
+            .keys()
+            .sorted_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)))
+            .map(|title| {
+                let starts_word = title.starts_with(|c: char| c.is_alphanumeric() || c == '_');
+                let ends_word = title.ends_with(|c: char| c.is_alphanumeric() || c == '_');
+                format!(
+                    "{}{}{}",
+                    if starts_word { r"\b" } else { "" },
+                    regex::escape(title),
+                    if ends_word { r"\b" } else { "" }
+                )
+            })
+            .join("|");
+
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .size_limit(1 << 28)
+            .build()?;
+
+        Ok(Some(MentionMatcher { regex, titles }))
+    }
+
+    /// Find the mentions in a piece of text, returning the location of each mention and the title
+    /// of the page it refers to.
+    pub fn find_iter<'m, 't>(
+        &'m self,
+        text: &'t str,
+    ) -> impl Iterator<Item = (Range<usize>, &'m str)> + 'm
+    where
+        't: 'm,
+    {
+        self.regex.find_iter(text).filter_map(|m| {
+            self.titles
+                .get(&m.as_str().to_lowercase())
+                .map(|title| (m.range(), title.as_str()))
+        })
+    }
+
+    /// Find the titles of pages mentioned in the plain text portions of a list of expressions.
+    fn find_in_expressions<'e>(&self, expressions: &[Expression<'e>], output: &mut Vec<String>) {
+        for expr in expressions {
+            match expr {
+                Expression::Text(s) => {
+                    output.extend(self.find_iter(s).map(|(_, title)| title.to_string()))
+                }
+                _ => {
+                    let contained = expr.contained_expressions();
+                    if !contained.is_empty() {
+                        self.find_in_expressions(contained, output);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Scan every block for plain-text mentions of other pages. The returned map goes from the ID of
+/// each mentioned page's root block to the IDs of the blocks that mention it. Blocks that already
+/// link to the page are not included.
+pub fn find_unlinked_mentions(
+    pages: &[ParsedPage],
+    matcher: &MentionMatcher,
+) -> HashMap<usize, Vec<usize>> {
+    let page_ids_by_title = pages
+        .iter()
+        .filter_map(|page| {
+            let block = page.blocks.get(&page.root_block)?;
+            let title = block.page_title.as_deref()?;
+            Some((title.to_lowercase(), page.root_block))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut mentions: HashMap<usize, Vec<usize>> = HashMap::default();
+    let mut found = Vec::new();
+    let mut references = Vec::new();
+    let mut linked = HashSet::default();
+
+    for page in pages {
+        let mut block_ids = page.blocks.keys().copied().collect::<Vec<_>>();
+        block_ids.sort_unstable();
+
+        for block_id in block_ids {
+            let block = page.blocks.get(&block_id).unwrap();
+            let parsed = block.contents.borrow_parsed();
+
+            found.clear();
+            matcher.find_in_expressions(parsed, &mut found);
+            if found.is_empty() {
+                continue;
+            }
+
+            references.clear();
+            collect_references(parsed, &mut references);
+            linked.clear();
+            linked.extend(references.iter().filter_map(|r| match r {
+                Reference::Page(title) => Some(title.to_lowercase()),
+                Reference::Block(_) => None,
+            }));
+
+            let targets = found
+                .iter()
+                .filter(|title| !linked.contains(&title.to_lowercase()))
+                .filter_map(|title| page_ids_by_title.get(&title.to_lowercase()))
+                .filter(|target| **target != page.root_block)
+                .unique();
+
+            for target in targets {
+                mentions.entry(*target).or_default().push(block_id);
+            }
+        }
+    }
+
+    mentions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page_block(id: usize, title: &str) -> Block {
+        Block {
+            page_title: Some(title.to_string()),
+            ..Block::test(id, id, "")
+        }
+    }
+
+    #[test]
+    fn matches_whole_words_case_insensitive() {
+        let blocks = [page_block(0, "Rust"), page_block(1, "Rust Book")];
+        let matcher = MentionMatcher::new(blocks.iter()).unwrap().unwrap();
+
+        let found = matcher
+            .find_iter("I read the rust book, then some Rustacean stuff in RUST.")
+            .map(|(range, title)| (range, title.to_string()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            found,
+            vec![
+                (11..20, String::from("Rust Book")),
+                (51..55, String::from("Rust")),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_linked_mentions() {
+        let target = page_block(0, "Databases");
+        let mut source = page_block(1, "Notes");
+        source.children.push(2);
+
+        let linked = Block::test(2, 1, "Databases are great, see [[Databases]]");
+        let unlinked = Block::test(3, 1, "More about **databases** here");
+
+        let pages = vec![
+            ParsedPage {
+                path: Default::default(),
+                root_block: 0,
+                blocks: HashMap::from_iter([(0, target.clone())]),
+                whiteboard: None,
+            },
+            ParsedPage {
+                path: Default::default(),
+                root_block: 1,
+                blocks: HashMap::from_iter([(1, source), (2, linked), (3, unlinked)]),
+                whiteboard: None,
+            },
+        ];
+
+        let matcher = MentionMatcher::new(pages.iter().map(|p| &p.blocks[&p.root_block]))
+            .unwrap()
+            .unwrap();
+        let mentions = find_unlinked_mentions(&pages, &matcher);
+        assert_eq!(mentions.get(&0), Some(&vec![3]));
+        assert_eq!(mentions.get(&1), None);
+    }
+}
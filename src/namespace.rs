@@ -0,0 +1,318 @@
+//! Logseq namespaces, where a page titled `Books/Notes/A Book` lives inside the `Books/Notes`
+//! namespace, which lives inside `Books`.
+
+use std::collections::BTreeMap;
+
+use ahash::HashMap;
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::{config::OutputFormat, html, make_pages::title_to_slug, markdown};
+
+/// Split a page title into its namespace levels. Logseq writes namespaced pages to files like
+/// `Books___Notes___A Book.md`, so `___` separates levels too when the title comes from the
+/// filename.
+pub fn namespace_parts(title: &str) -> Vec<String> {
+    title
+        .replace("___", "/")
+        .split('/')
+        .map(|part| part.trim())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.to_string())
+        .collect()
+}
+
+/// The URL name for a namespaced page, with a directory for each namespace level.
+pub fn nested_url_name(parts: &[String]) -> String {
+    parts
+        .iter()
+        .map(|part| title_to_slug(part))
+        .filter(|slug| !slug.is_empty())
+        .join("/")
+}
+
+fn namespace_key(parts: &[String]) -> String {
+    parts.iter().map(|p| p.to_lowercase()).join("/")
+}
+
+/// A link to one of the namespaces containing a page.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Breadcrumb {
+    /// The last part of the namespace's title
+    pub title: String,
+    /// The page or index page for the namespace, if one is exported.
+    pub slug: Option<String>,
+}
+
+/// An exported page, as far as the namespace index cares.
+pub struct NamespaceMember<'a> {
+    pub parts: &'a [String],
+    pub slug: &'a str,
+    pub create_time: u64,
+    pub edit_time: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub title: String,
+    pub slug: String,
+}
+
+/// A generated page listing the contents of a namespace that has no page of its own.
+#[derive(Debug)]
+pub struct NamespaceIndex {
+    pub parts: Vec<String>,
+    pub url_name: String,
+    pub slug: String,
+    pub create_time: u64,
+    pub edit_time: u64,
+    pub children: Vec<IndexEntry>,
+}
+
+impl NamespaceIndex {
+    pub fn title(&self) -> String {
+        self.parts.join("/")
+    }
+
+    /// Render the list of pages and namespaces inside this one.
+    pub fn render(&self, format: OutputFormat) -> String {
+        match format {
+            OutputFormat::Html => {
+                let items = self
+                    .children
+                    .iter()
+                    .map(|entry| {
+                        format!(
+                            "  <li><a href=\"{slug}\">{title}</a></li>\n",
+                            slug = html::escape(&entry.slug),
+                            title = html::escape(&entry.title)
+                        )
+                    })
+                    .join("");
+                format!("<ul class=\"namespace-index\">\n{items}</ul>\n")
+            }
+            OutputFormat::Markdown => self
+                .children
+                .iter()
+                .map(|entry| {
+                    format!(
+                        "- [{title}]({slug})\n",
+                        title = markdown::escape(&entry.title),
+                        slug = markdown::escape_url(&entry.slug)
+                    )
+                })
+                .join(""),
+        }
+    }
+}
+
+pub struct Namespaces {
+    slugs: HashMap<String, String>,
+    pub indexes: Vec<NamespaceIndex>,
+}
+
+impl Namespaces {
+    /// Find the namespaces of the exported pages. When `build_indexes` is set, the namespaces
+    /// without an exported page of their own get an index page instead, with a URL made by
+    /// `make_slug`.
+    pub fn new(
+        members: &[NamespaceMember],
+        build_indexes: bool,
+        make_slug: impl Fn(&str) -> String,
+    ) -> Namespaces {
+        let mut slugs = members
+            .iter()
+            .map(|m| (namespace_key(m.parts), m.slug.to_string()))
+            .collect::<HashMap<_, _>>();
+
+        if !build_indexes {
+            return Namespaces {
+                slugs,
+                indexes: Vec::new(),
+            };
+        }
+
+        // Keyed by the lowercase namespace, so the indexes come out in a stable order.
+        let mut indexes = BTreeMap::new();
+        for member in members {
+            for level in 1..member.parts.len() {
+                let parts = &member.parts[..level];
+                let key = namespace_key(parts);
+                if slugs.contains_key(&key) {
+                    continue;
+                }
+
+                let index = indexes.entry(key).or_insert_with(|| {
+                    let url_name = nested_url_name(parts);
+                    NamespaceIndex {
+                        parts: parts.to_vec(),
+                        slug: make_slug(&url_name),
+                        url_name,
+                        create_time: u64::MAX,
+                        edit_time: 0,
+                        children: Vec::new(),
+                    }
+                });
+
+                index.create_time = index.create_time.min(member.create_time);
+                index.edit_time = index.edit_time.max(member.edit_time);
+            }
+        }
+
+        for (key, index) in indexes.iter() {
+            slugs.insert(key.clone(), index.slug.clone());
+        }
+
+        // Link each index to the pages and namespaces one level down.
+        for index in indexes.values_mut() {
+            let depth = index.parts.len() + 1;
+            let prefix = namespace_key(&index.parts);
+
+            index.children = members
+                .iter()
+                .map(|m| m.parts)
+                .filter(|parts| parts.len() >= depth)
+                .map(|parts| &parts[..depth])
+                .filter(|parts| namespace_key(&parts[..depth - 1]) == prefix)
+                .unique_by(|parts| namespace_key(parts))
+                .filter_map(|parts| {
+                    Some(IndexEntry {
+                        title: parts.last()?.clone(),
+                        slug: slugs.get(&namespace_key(parts))?.clone(),
+                    })
+                })
+                .sorted_by_key(|entry| entry.title.to_lowercase())
+                .collect();
+        }
+
+        Namespaces {
+            slugs,
+            indexes: indexes.into_values().collect(),
+        }
+    }
+
+    /// Links to each namespace above a page, starting from the top level.
+    pub fn breadcrumbs(&self, parts: &[String]) -> Vec<Breadcrumb> {
+        (1..parts.len())
+            .map(|level| Breadcrumb {
+                title: parts[level - 1].clone(),
+                slug: self.slugs.get(&namespace_key(&parts[..level])).cloned(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(title: &str) -> Vec<String> {
+        namespace_parts(title)
+    }
+
+    #[test]
+    fn split_titles() {
+        assert_eq!(
+            parts("Books/Notes/A Book"),
+            vec!["Books", "Notes", "A Book"]
+        );
+        assert_eq!(parts("Books___Notes"), vec!["Books", "Notes"]);
+        assert_eq!(parts("Plain"), vec!["Plain"]);
+        assert_eq!(
+            nested_url_name(&parts("Books/Notes/A Book")),
+            "books/notes/a_book"
+        );
+    }
+
+    #[test]
+    fn indexes_and_breadcrumbs() {
+        let book = parts("Books/Notes/A Book");
+        let other = parts("Books/Notes/Another");
+        let fiction = parts("Books/Fiction/Novel");
+        let fiction_page = parts("books/fiction");
+        let members = [
+            NamespaceMember {
+                parts: &book,
+                slug: "/books/notes/a_book",
+                create_time: 5,
+                edit_time: 10,
+            },
+            NamespaceMember {
+                parts: &other,
+                slug: "/books/notes/another",
+                create_time: 3,
+                edit_time: 20,
+            },
+            NamespaceMember {
+                parts: &fiction,
+                slug: "/books/fiction/novel",
+                create_time: 1,
+                edit_time: 1,
+            },
+            NamespaceMember {
+                parts: &fiction_page,
+                slug: "/fiction",
+                create_time: 1,
+                edit_time: 1,
+            },
+        ];
+
+        let namespaces = Namespaces::new(&members, true, |name| format!("/{name}"));
+
+        // Books/Fiction has its own page, so only Books and Books/Notes get an index.
+        let titles = namespaces
+            .indexes
+            .iter()
+            .map(|index| index.title())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, vec!["Books", "Books/Notes"]);
+
+        let books = &namespaces.indexes[0];
+        assert_eq!(books.slug, "/books");
+        assert_eq!(
+            books.children,
+            vec![
+                IndexEntry {
+                    title: String::from("Fiction"),
+                    slug: String::from("/fiction"),
+                },
+                IndexEntry {
+                    title: String::from("Notes"),
+                    slug: String::from("/books/notes"),
+                },
+            ]
+        );
+
+        let notes = &namespaces.indexes[1];
+        assert_eq!(notes.url_name, "books/notes");
+        assert_eq!(notes.create_time, 3);
+        assert_eq!(notes.edit_time, 20);
+        assert_eq!(notes.children.len(), 2);
+        assert_eq!(
+            notes.render(OutputFormat::Markdown),
+            "- [A Book](/books/notes/a_book)\n- [Another](/books/notes/another)\n"
+        );
+
+        assert_eq!(
+            namespaces.breadcrumbs(&book),
+            vec![
+                Breadcrumb {
+                    title: String::from("Books"),
+                    slug: Some(String::from("/books")),
+                },
+                Breadcrumb {
+                    title: String::from("Notes"),
+                    slug: Some(String::from("/books/notes")),
+                },
+            ]
+        );
+
+        let without_indexes = Namespaces::new(&members, false, |name| format!("/{name}"));
+        assert!(without_indexes.indexes.is_empty());
+        assert_eq!(without_indexes.breadcrumbs(&fiction)[0].slug, None);
+        assert_eq!(
+            without_indexes.breadcrumbs(&fiction)[1].slug.as_deref(),
+            Some("/fiction")
+        );
+    }
+}
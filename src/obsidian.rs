@@ -0,0 +1,683 @@
+use std::{
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use ahash::{HashMap, HashMapExt};
+use eyre::{Result, WrapErr};
+use itertools::{put_back, Itertools};
+use once_cell::sync::Lazy;
+use rayon::prelude::*;
+use regex::{Captures, Regex};
+use smallvec::SmallVec;
+
+use crate::{
+    content::BlockContent,
+    graph::{AttrList, Block, BlockInclude, ListType, ParsedPage, ViewType},
+    logseq::{blocks::content_hashtags, page_header::parse_page_header},
+    parse_string::{is_image_url, ContentStyle},
+};
+
+static HEADING: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(#{1,6})\s+(.*)$").unwrap());
+static LIST_ITEM: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(\s*)([-*+]|\d+[.)])(?:\s+(.*))?$").unwrap());
+static HRULE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\s*(-{3,}|\*{3,}|_{3,})\s*$").unwrap());
+/// A `^block-id` at the end of a line, or on a line by itself after the block.
+static BLOCK_ID: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?:^|\s+)\^([A-Za-z0-9-]+)$").unwrap());
+static IMAGE_EMBED: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"!\[\[([^\]|#]+)(\|[^\]]*)?\]\]").unwrap());
+/// Daily notes use this name by default.
+static DAILY_NOTE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\d{4}-\d{2}-\d{2}$").unwrap());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RawBlockKind {
+    Heading,
+    Paragraph,
+    ListItem,
+    Quote,
+    Rule,
+}
+
+#[derive(Debug)]
+struct RawBlock {
+    kind: RawBlockKind,
+    parent_idx: Option<usize>,
+    indent: usize,
+    heading: usize,
+    list_type: ListType,
+    uid: String,
+    lines: Vec<String>,
+}
+
+struct RawPage {
+    path: PathBuf,
+    title: String,
+    attrs: HashMap<String, AttrList>,
+    blocks: Vec<RawBlock>,
+    create_time: u64,
+    edit_time: u64,
+}
+
+/// The width of a line's leading whitespace, counting tabs as four spaces.
+fn indent_width(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| c.is_whitespace())
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum()
+}
+
+fn is_fence(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("```") || line.starts_with("~~~")
+}
+
+/// Splits the lines of a note into blocks. Headings contain everything up to the next heading of
+/// the same or higher level, and list items contain the items indented under them.
+#[derive(Default)]
+struct BlockBuilder {
+    blocks: Vec<RawBlock>,
+    /// The open headings, with their levels
+    headings: Vec<(usize, usize)>,
+    /// The open list items, with their indents
+    list_items: Vec<(usize, usize)>,
+    /// The block that following lines are added to
+    current: Option<usize>,
+    in_code_block: bool,
+}
+
+impl BlockBuilder {
+    fn heading_parent(&self) -> Option<usize> {
+        self.headings.last().map(|(_, idx)| *idx)
+    }
+
+    /// Find the parent for a block at this indent, closing the list items that it's outside of.
+    fn parent_for_indent(&mut self, indent: usize) -> Option<usize> {
+        while self
+            .list_items
+            .last()
+            .map(|(item_indent, _)| *item_indent >= indent)
+            .unwrap_or(false)
+        {
+            self.list_items.pop();
+        }
+
+        self.list_items
+            .last()
+            .map(|(_, idx)| *idx)
+            .or_else(|| self.heading_parent())
+    }
+
+    fn push(&mut self, block: RawBlock) -> usize {
+        self.blocks.push(block);
+        self.blocks.len() - 1
+    }
+
+    fn current_kind(&self) -> Option<RawBlockKind> {
+        self.current.map(|idx| self.blocks[idx].kind)
+    }
+
+    fn new_block(
+        &mut self,
+        kind: RawBlockKind,
+        parent_idx: Option<usize>,
+        indent: usize,
+        line: String,
+    ) -> usize {
+        self.push(RawBlock {
+            kind,
+            parent_idx,
+            indent,
+            heading: 0,
+            list_type: ListType::Default,
+            uid: String::new(),
+            lines: vec![line],
+        })
+    }
+
+    fn add_line(&mut self, line: &str) {
+        if self.in_code_block {
+            if is_fence(line) {
+                self.in_code_block = false;
+            }
+
+            if let Some(current) = self.current {
+                let indent = self.blocks[current].indent;
+                let line = strip_indent(line, indent).to_string();
+                self.blocks[current].lines.push(line);
+            }
+            return;
+        }
+
+        let trimmed = line.trim();
+        let indent = indent_width(line);
+
+        if trimmed.is_empty() {
+            self.current = None;
+            return;
+        }
+
+        if let Some(id) = BLOCK_ID
+            .captures(trimmed)
+            .filter(|c| c.get(0).map(|m| m.start()) == Some(0))
+        {
+            // An ID on its own line belongs to the block before it.
+            if let Some(block) = self.blocks.last_mut() {
+                block.uid = id[1].to_string();
+            }
+            return;
+        }
+
+        if indent == 0 {
+            if let Some(heading) = HEADING.captures(line) {
+                let level = heading[1].len();
+                while self
+                    .headings
+                    .last()
+                    .map(|(l, _)| *l >= level)
+                    .unwrap_or(false)
+                {
+                    self.headings.pop();
+                }
+
+                let parent = self.heading_parent();
+                let idx = self.new_block(RawBlockKind::Heading, parent, 0, heading[2].to_string());
+                self.blocks[idx].heading = level;
+                self.headings.push((level, idx));
+                self.list_items.clear();
+                self.current = None;
+                return;
+            }
+        }
+
+        if HRULE.is_match(line) {
+            let parent = self.heading_parent();
+            self.new_block(RawBlockKind::Rule, parent, 0, String::from("---"));
+            self.list_items.clear();
+            self.current = None;
+            return;
+        }
+
+        if let Some(item) = LIST_ITEM.captures(line) {
+            let parent = self.parent_for_indent(indent);
+            let contents = item.get(3).map(|m| m.as_str()).unwrap_or_default();
+            let idx = self.new_block(RawBlockKind::ListItem, parent, indent, contents.to_string());
+            if item[2].starts_with(|c: char| c.is_ascii_digit()) {
+                self.blocks[idx].list_type = ListType::Number;
+            }
+            // Continuation lines are indented past the list marker.
+            self.blocks[idx].indent = indent + item[2].len() + 1;
+            self.list_items.push((indent, idx));
+            self.current = Some(idx);
+            self.in_code_block = is_fence(contents);
+            return;
+        }
+
+        // Code fences interrupt a list item unless they're indented under it.
+        let continues_item = (self.current_kind() == Some(RawBlockKind::ListItem)
+            && (indent > 0 || !is_fence(line)))
+            || (self.current.is_none() && indent > 0 && !self.list_items.is_empty());
+
+        if let Some(quote) = trimmed.strip_prefix('>') {
+            let quote = quote.trim_start();
+            if self.current_kind() == Some(RawBlockKind::Quote) {
+                self.blocks[self.current.unwrap()]
+                    .lines
+                    .push(quote.to_string());
+            } else {
+                let parent = if indent > 0 {
+                    self.parent_for_indent(indent)
+                } else {
+                    self.list_items.clear();
+                    self.heading_parent()
+                };
+                let idx = self.new_block(RawBlockKind::Quote, parent, indent, format!("> {quote}"));
+                self.current = Some(idx);
+            }
+            return;
+        }
+
+        match self.current {
+            Some(current)
+                if self.blocks[current].kind != RawBlockKind::Quote
+                    && (continues_item || self.blocks[current].kind == RawBlockKind::Paragraph) =>
+            {
+                let text = if continues_item { trimmed } else { line };
+                self.blocks[current].lines.push(text.to_string());
+            }
+            _ => {
+                let parent = if continues_item {
+                    self.parent_for_indent(indent)
+                } else {
+                    self.list_items.clear();
+                    self.heading_parent()
+                };
+                let text = if continues_item { trimmed } else { line };
+                let idx = self.new_block(RawBlockKind::Paragraph, parent, indent, text.to_string());
+                self.current = Some(idx);
+            }
+        }
+
+        if is_fence(line) {
+            self.in_code_block = true;
+        }
+    }
+}
+
+/// Remove up to `width` columns of leading whitespace.
+fn strip_indent(line: &str, width: usize) -> &str {
+    let mut seen = 0;
+    for (i, c) in line.char_indices() {
+        if seen >= width || !c.is_whitespace() {
+            return &line[i..];
+        }
+        seen += if c == '\t' { 4 } else { 1 };
+    }
+
+    ""
+}
+
+/// Find the attachment that an embed refers to. Obsidian resolves these by filename when the path
+/// isn't unique, so try both.
+fn resolve_attachment<'a>(
+    attachments: &'a HashMap<String, PathBuf>,
+    root: &Path,
+    target: &str,
+) -> Option<&'a PathBuf> {
+    attachments.get(target).or_else(|| {
+        let name = Path::new(target).file_name()?.to_string_lossy();
+        attachments
+            .values()
+            .find(|path| {
+                path.file_name()
+                    .map(|f| f.to_string_lossy() == name)
+                    .unwrap_or(false)
+            })
+            .filter(|path| path.starts_with(root))
+    })
+}
+
+/// Point image embeds at the attachment's path relative to the note.
+fn rewrite_image_embeds(
+    contents: &str,
+    attachments: &HashMap<String, PathBuf>,
+    root: &Path,
+    note_path: &Path,
+) -> String {
+    let depth = note_path
+        .parent()
+        .and_then(|dir| dir.strip_prefix(root).ok())
+        .map(|dir| dir.components().count())
+        .unwrap_or(0);
+
+    IMAGE_EMBED
+        .replace_all(contents, |caps: &Captures| {
+            let target = &caps[1];
+            if !is_image_url(target) {
+                return caps[0].to_string();
+            }
+
+            match resolve_attachment(attachments, root, target)
+                .and_then(|path| path.strip_prefix(root).ok())
+            {
+                Some(relative) => {
+                    let prefix = "../".repeat(depth);
+                    format!("![[{prefix}{}]]", relative.to_string_lossy())
+                }
+                None => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+fn file_times(path: &Path) -> (u64, u64) {
+    let to_millis = |t: std::io::Result<SystemTime>| {
+        t.ok()
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as u64)
+    };
+
+    let metadata = std::fs::metadata(path).ok();
+    let edit_time = metadata
+        .as_ref()
+        .and_then(|m| to_millis(m.modified()))
+        .unwrap_or_default();
+    let create_time = metadata
+        .as_ref()
+        .and_then(|m| to_millis(m.created()))
+        .unwrap_or(edit_time);
+    (create_time, edit_time)
+}
+
+fn read_note(path: &Path) -> Result<RawPage> {
+    let file = std::fs::File::open(path)?;
+    let mut lines = put_back(BufReader::new(file).lines());
+    let attrs = parse_page_header(&mut lines)?;
+
+    let mut builder = BlockBuilder::default();
+    for line in lines {
+        builder.add_line(&line?);
+    }
+
+    let mut blocks = builder.blocks;
+    for block in blocks.iter_mut() {
+        // Pull a trailing `^block-id` off of the block's last line.
+        let last = block.lines.last_mut();
+        if let Some(last) = last {
+            if let Some(caps) = BLOCK_ID.captures(last) {
+                block.uid = caps[1].to_string();
+                let start = caps.get(0).unwrap().start();
+                last.truncate(start);
+            }
+        }
+    }
+
+    let (create_time, edit_time) = file_times(path);
+    let title = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    Ok(RawPage {
+        path: path.to_path_buf(),
+        title,
+        attrs: attrs.into_iter().collect(),
+        blocks,
+        create_time,
+        edit_time,
+    })
+}
+
+/// List the notes and other files in the vault, skipping hidden directories like `.obsidian`.
+fn vault_files(dir: &Path, notes: &mut Vec<PathBuf>, attachments: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir).with_context(|| format!("{dir:?}"))? {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .map(|n| n.to_string_lossy().starts_with('.'))
+            .unwrap_or(false);
+        if hidden {
+            continue;
+        }
+
+        if path.is_dir() {
+            vault_files(&path, notes, attachments)?;
+        } else if path.extension().map(|e| e == "md").unwrap_or(false) {
+            notes.push(path);
+        } else {
+            attachments.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn process_page(
+    mut page: RawPage,
+    base_id: usize,
+    root: &Path,
+    attachments: &HashMap<String, PathBuf>,
+) -> Result<ParsedPage> {
+    let title = page
+        .attrs
+        .remove("title")
+        .and_then(|values| values.into_iter().next());
+    let (title, original_title) = match title {
+        Some(title) => (title, Some(page.title)),
+        None => (page.title, None),
+    };
+
+    let tags = page.attrs.get("tags").cloned().unwrap_or_default();
+    let view_type = page
+        .attrs
+        .get("view-mode")
+        .and_then(|values| values.first())
+        .map(ViewType::from)
+        // Notes are written as documents rather than outlines.
+        .unwrap_or(ViewType::Document);
+    let is_journal = DAILY_NOTE.is_match(original_title.as_deref().unwrap_or(&title));
+
+    // The view type applies to a block's children, so blocks whose children are all list items
+    // render them as a list. Numbered items make their list numbered on their own. List items
+    // only do this when they are in a list themselves, since otherwise they render without their
+    // anchor. The index is offset by one so that 0 is the page.
+    let mut has_list_items = vec![false; page.blocks.len() + 1];
+    let mut has_other_children = vec![false; page.blocks.len() + 1];
+    for block in &page.blocks {
+        let parent = block.parent_idx.map(|i| i + 1).unwrap_or(0);
+        if block.kind == RawBlockKind::ListItem {
+            has_list_items[parent] = true;
+        } else {
+            has_other_children[parent] = true;
+        }
+    }
+
+    let mut is_list = vec![false; page.blocks.len() + 1];
+    is_list[0] = has_list_items[0] && !has_other_children[0];
+    for (i, block) in page.blocks.iter().enumerate() {
+        let parent = block.parent_idx.map(|i| i + 1).unwrap_or(0);
+        let in_list = block.kind != RawBlockKind::ListItem || is_list[parent];
+        is_list[i + 1] = in_list && has_list_items[i + 1] && !has_other_children[i + 1];
+    }
+    let list_view_type = |i: usize| is_list[i].then_some(ViewType::Bullet);
+
+    let view_type = list_view_type(0).unwrap_or(view_type);
+
+    let mut blocks = HashMap::with_capacity(page.blocks.len() + 1);
+    blocks.insert(
+        base_id,
+        Block {
+            id: base_id,
+            uid: String::new(),
+            include_type: BlockInclude::IfChildrenPresent,
+            containing_page: base_id,
+            page_title: Some(title),
+            original_title,
+            is_journal,
+            contents: BlockContent::new_empty(ContentStyle::Obsidian),
+            heading: 0,
+            view_type,
+            this_block_list_type: ListType::Default,
+            create_time: page.create_time,
+            edit_time: page.edit_time,
+            children: SmallVec::new(),
+
+            extra_classes: Vec::new(),
+            content_element: None,
+            wrapper_element: None,
+
+            tags,
+            attrs: page.attrs,
+            parent: None,
+            order: 0,
+        },
+    );
+
+    for (i, input) in page.blocks.into_iter().enumerate() {
+        let this_id = base_id + i + 1;
+        let parent_id = input.parent_idx.map(|i| base_id + i + 1).unwrap_or(base_id);
+        blocks.get_mut(&parent_id).unwrap().children.push(this_id);
+
+        let contents = input.lines.join("\n");
+        let contents = rewrite_image_embeds(contents.trim_end(), attachments, root, &page.path);
+        let contents = BlockContent::new_parsed(ContentStyle::Obsidian, contents)?;
+        let tags = content_hashtags(&contents);
+
+        let block = Block {
+            id: this_id,
+            uid: input.uid,
+            include_type: BlockInclude::default(),
+            order: 0,
+            parent: Some(parent_id),
+            children: SmallVec::new(),
+            attrs: HashMap::default(),
+            tags,
+            create_time: 0,
+            edit_time: 0,
+            view_type: list_view_type(i + 1).unwrap_or(ViewType::Inherit),
+            this_block_list_type: input.list_type,
+            contents,
+            heading: input.heading,
+            is_journal,
+            page_title: None,
+            original_title: None,
+            containing_page: base_id,
+            extra_classes: Vec::new(),
+            content_element: None,
+            wrapper_element: None,
+        };
+
+        blocks.insert(this_id, block);
+    }
+
+    Ok(ParsedPage {
+        path: page.path,
+        root_block: base_id,
+        blocks,
+        whiteboard: None,
+    })
+}
+
+/// The name that links can use for a note by its path in the vault, like `sub/Note` for
+/// `sub/Note.md`. Notes at the top of the vault don't have one, since it's the same as the title
+/// from the filename.
+pub fn vault_path_name(root: &Path, note: &Path) -> Option<String> {
+    let relative = note.strip_prefix(root).ok()?.with_extension("");
+    (relative.components().count() > 1).then(|| {
+        relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .join("/")
+    })
+}
+
+/// Read the notes in an Obsidian vault.
+pub fn read_vault(root: &Path) -> Result<(bool, Vec<ParsedPage>)> {
+    let root = root
+        .canonicalize()
+        .with_context(|| format!("{}", root.display()))?;
+
+    let mut notes = Vec::new();
+    let mut attachment_paths = Vec::new();
+    vault_files(&root, &mut notes, &mut attachment_paths)?;
+
+    let attachments = attachment_paths
+        .into_iter()
+        .filter_map(|path| {
+            let relative = path
+                .strip_prefix(&root)
+                .ok()?
+                .to_string_lossy()
+                .into_owned();
+            Some((relative, path))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let raw_pages = notes
+        .par_iter()
+        .map(|path| read_note(path).with_context(|| format!("{path:?}")))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut next_id = 0;
+    let pages = raw_pages
+        .into_iter()
+        .map(|page| {
+            let base_id = next_id;
+            next_id += page.blocks.len() + 1;
+            let path = page.path.clone();
+            process_page(page, base_id, &root, &attachments).with_context(|| format!("{path:?}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok((false, pages))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(input: &str) -> Vec<RawBlock> {
+        let mut builder = BlockBuilder::default();
+        for line in input.lines() {
+            builder.add_line(line);
+        }
+        builder.blocks
+    }
+
+    fn summary(blocks: &[RawBlock]) -> Vec<(Option<usize>, String)> {
+        blocks
+            .iter()
+            .map(|b| (b.parent_idx, b.lines.join("\n")))
+            .collect()
+    }
+
+    #[test]
+    fn headings_and_lists() {
+        let input = "Intro text\n\
+                     continues here\n\
+                     \n\
+                     # First\n\
+                     - item\n\
+                     \t- nested\n\
+                     \t  more of nested\n\
+                     - second item\n\
+                     ## Sub\n\
+                     Paragraph ^para-id\n\
+                     # Second\n\
+                     > [!note] Title\n\
+                     > body\n\
+                     1. numbered";
+
+        let blocks = build(input);
+        assert_eq!(
+            summary(&blocks),
+            vec![
+                (None, String::from("Intro text\ncontinues here")),
+                (None, String::from("First")),
+                (Some(1), String::from("item")),
+                (Some(2), String::from("nested\nmore of nested")),
+                (Some(1), String::from("second item")),
+                (Some(1), String::from("Sub")),
+                (Some(5), String::from("Paragraph ^para-id")),
+                (None, String::from("Second")),
+                (Some(7), String::from("> [!note] Title\nbody")),
+                (Some(7), String::from("numbered")),
+            ]
+        );
+        assert_eq!(blocks[1].heading, 1);
+        assert_eq!(blocks[5].heading, 2);
+        assert_eq!(blocks[9].list_type, ListType::Number);
+    }
+
+    #[test]
+    fn code_blocks() {
+        let input = "- item\n  ```rust\n  let x = 1;\n\n  ```\n```\n# not a heading\n```";
+        let blocks = build(input);
+        assert_eq!(
+            summary(&blocks),
+            vec![
+                (None, String::from("item\n```rust\nlet x = 1;\n\n```")),
+                (None, String::from("```\n# not a heading\n```")),
+            ]
+        );
+    }
+
+    #[test]
+    fn image_embeds() {
+        let root = Path::new("/vault");
+        let attachments = HashMap::from_iter([(
+            String::from("files/pic.png"),
+            PathBuf::from("/vault/files/pic.png"),
+        )]);
+
+        assert_eq!(
+            rewrite_image_embeds(
+                "See ![[pic.png|200]] and ![[Note]]",
+                &attachments,
+                root,
+                Path::new("/vault/notes/Note.md")
+            ),
+            "See ![[../files/pic.png]] and ![[Note]]"
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Tracking what an export changes in the output directory, for dry runs and for cleaning up the
+//! files of pages that are no longer exported.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use ahash::HashSet;
+use eyre::{Result, WrapErr};
+use serde::Deserialize;
+
+use crate::page::ManifestItem;
+
+#[derive(Debug, Clone)]
+pub struct DryRunOptions {
+    /// Include a unified diff for each file that would change.
+    pub diff: bool,
+}
+
+/// What an export did, or would do in a dry run, to an output file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputChange {
+    /// The file already had the rendered contents.
+    Unchanged,
+    Created,
+    /// The file existed with different contents. The diff is only filled in for dry runs that
+    /// asked for it.
+    Updated {
+        diff: Option<String>,
+    },
+}
+
+impl OutputChange {
+    pub fn is_changed(&self) -> bool {
+        *self != OutputChange::Unchanged
+    }
+}
+
+/// The change from writing a file whose contents aren't compared with the existing file, such as
+/// the EPUB, which records when it was made.
+pub fn rewritten_file_change(path: &Path) -> OutputChange {
+    if path.exists() {
+        OutputChange::Updated { diff: None }
+    } else {
+        OutputChange::Created
+    }
+}
+
+/// What to do with the output files of pages that the previous export wrote but this one didn't.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StaleOutputs {
+    /// Leave them in place, and just list them.
+    #[default]
+    Keep,
+    Delete,
+    /// Move them into `stale_output_dir`.
+    Move,
+}
+
+/// An output file from the previous export's manifest that this export didn't produce.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleOutput {
+    pub path: String,
+    pub title: String,
+    pub slug: String,
+}
+
+/// Find the files in the previous manifest that still exist but weren't written this time.
+pub fn find_stale_outputs(
+    previous_manifest: &BTreeMap<String, ManifestItem>,
+    current_paths: &HashSet<&str>,
+) -> Vec<StaleOutput> {
+    previous_manifest
+        .iter()
+        .filter(|(path, _)| !current_paths.contains(path.as_str()))
+        .filter(|(path, _)| Path::new(path).is_file())
+        .map(|(path, item)| StaleOutput {
+            path: path.clone(),
+            title: item.title.clone(),
+            slug: item.slug.clone(),
+        })
+        .collect()
+}
+
+/// Where a stale file goes when it's moved aside, keeping its place in the output directory.
+fn moved_path(output_dir: &Path, stale_dir: &Path, path: &Path) -> PathBuf {
+    let relative = path
+        .strip_prefix(output_dir)
+        .ok()
+        .or_else(|| path.file_name().map(Path::new))
+        .unwrap_or(path);
+    stale_dir.join(relative)
+}
+
+/// Remove the directories that held a deleted file if nothing else is left in them, stopping at
+/// the output directory.
+fn remove_empty_parents(output_dir: &Path, path: &Path) {
+    let mut dir = path.parent();
+    while let Some(d) = dir {
+        if !d.starts_with(output_dir) || d == output_dir || std::fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+}
+
+/// Delete, move, or just report the stale output files, according to `mode`.
+pub fn handle_stale_outputs(
+    stale: &[StaleOutput],
+    mode: StaleOutputs,
+    output_dir: &Path,
+    stale_dir: &Path,
+) -> Result<()> {
+    for output in stale {
+        let path = Path::new(&output.path);
+        match mode {
+            StaleOutputs::Keep => {
+                println!(
+                    "Stale: \"{}\" at {} is no longer exported",
+                    output.title, output.path
+                );
+            }
+            StaleOutputs::Delete => {
+                std::fs::remove_file(path)
+                    .with_context(|| format!("Deleting stale output {}", output.path))?;
+                remove_empty_parents(output_dir, path);
+                println!("Deleted: stale \"{}\" at {}", output.title, output.path);
+            }
+            StaleOutputs::Move => {
+                let destination = moved_path(output_dir, stale_dir, path);
+                if let Some(parent) = destination.parent() {
+                    std::fs::create_dir_all(parent)
+                        .with_context(|| format!("Creating {}", parent.display()))?;
+                }
+
+                // Renaming fails across filesystems, so fall back to copying.
+                if std::fs::rename(path, &destination).is_err() {
+                    std::fs::copy(path, &destination).with_context(|| {
+                        format!(
+                            "Moving stale output {} to {}",
+                            output.path,
+                            destination.display()
+                        )
+                    })?;
+                    std::fs::remove_file(path)
+                        .with_context(|| format!("Deleting stale output {}", output.path))?;
+                }
+                remove_empty_parents(output_dir, path);
+                println!(
+                    "Moved: stale \"{}\" from {} to {}",
+                    output.title,
+                    output.path,
+                    destination.display()
+                );
+            }
+        }
+    }
+
+    if mode == StaleOutputs::Keep && !stale.is_empty() {
+        println!(
+            "Found {} stale output files. Set stale_outputs to \"delete\" or \"move\" to remove them.",
+            stale.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// A file that the export would write, for the dry run's plan.
+pub struct PlannedOutput<'a> {
+    pub path: &'a str,
+    pub title: &'a str,
+    pub change: &'a OutputChange,
+}
+
+/// Print what the export would change, grouped by the kind of change.
+pub fn print_plan(
+    outputs: &[PlannedOutput],
+    stale: &[StaleOutput],
+    stale_mode: StaleOutputs,
+    stale_dir: &Path,
+    output_dir: &Path,
+    image_uploads: &[PathBuf],
+) {
+    println!("Dry run: nothing was written.");
+
+    let print_group = |heading: &str, lines: Vec<String>| {
+        if !lines.is_empty() {
+            println!("\n{heading} ({}):", lines.len());
+            for line in lines {
+                println!("  {line}");
+            }
+        }
+    };
+
+    let with_change = |f: fn(&OutputChange) -> bool| {
+        outputs
+            .iter()
+            .filter(|output| f(output.change))
+            .map(|output| format!("{} \"{}\"", output.path, output.title))
+            .collect::<Vec<_>>()
+    };
+
+    print_group(
+        "Create",
+        with_change(|c| matches!(c, OutputChange::Created)),
+    );
+    print_group(
+        "Update",
+        with_change(|c| matches!(c, OutputChange::Updated { .. })),
+    );
+
+    let stale_lines = stale.iter().map(|output| match stale_mode {
+        StaleOutputs::Move => format!(
+            "{} \"{}\" to {}",
+            output.path,
+            output.title,
+            moved_path(output_dir, stale_dir, Path::new(&output.path)).display()
+        ),
+        StaleOutputs::Keep | StaleOutputs::Delete => {
+            format!("{} \"{}\"", output.path, output.title)
+        }
+    });
+    let stale_heading = match stale_mode {
+        StaleOutputs::Keep => "Stale, kept",
+        StaleOutputs::Delete => "Delete",
+        StaleOutputs::Move => "Move aside",
+    };
+    print_group(stale_heading, stale_lines.collect());
+
+    print_group(
+        "Upload to Pic Store, unless it already has them",
+        image_uploads
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect(),
+    );
+
+    let unchanged = outputs
+        .iter()
+        .filter(|output| !output.change.is_changed())
+        .count();
+    println!("\nUnchanged: {unchanged}");
+
+    for output in outputs {
+        if let OutputChange::Updated { diff: Some(diff) } = output.change {
+            println!("\n{diff}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("out");
+        std::fs::create_dir_all(output_dir.join("books/notes")).unwrap();
+
+        let path = |name: &str| output_dir.join(name).to_string_lossy().to_string();
+        let kept = path("kept.html");
+        let removed = path("books/notes/removed.html");
+        let already_gone = path("gone.html");
+        std::fs::write(&kept, "kept").unwrap();
+        std::fs::write(&removed, "removed").unwrap();
+
+        let item = |title: &str| ManifestItem {
+            slug: format!("/{title}"),
+            title: title.to_string(),
+            uid: String::new(),
+            redirect_to: None,
+        };
+        let previous = [
+            (kept.clone(), item("kept")),
+            (removed.clone(), item("removed")),
+            (already_gone, item("gone")),
+        ]
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+
+        let current = [kept.as_str()].into_iter().collect::<HashSet<_>>();
+        let stale = find_stale_outputs(&previous, &current);
+        assert_eq!(
+            stale,
+            vec![StaleOutput {
+                path: removed.clone(),
+                title: String::from("removed"),
+                slug: String::from("/removed"),
+            }]
+        );
+
+        let stale_dir = dir.path().join("stale");
+        handle_stale_outputs(&stale, StaleOutputs::Move, &output_dir, &stale_dir).unwrap();
+        assert!(!Path::new(&removed).exists());
+        assert!(!output_dir.join("books").exists());
+        assert!(Path::new(&kept).exists());
+        assert_eq!(
+            std::fs::read_to_string(stale_dir.join("books/notes/removed.html")).unwrap(),
+            "removed"
+        );
+    }
+}
@@ -6,23 +6,35 @@ use std::{
 
 use ahash::{HashMap, HashSet};
 use eyre::{eyre, Result, WrapErr};
+use itertools::Itertools;
 use once_cell::sync::Lazy;
 use regex::{Captures, Regex};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use urlencoding::encode;
 
 use crate::{
-    config::Config,
+    config::{Config, OutputFormat},
     graph::{Block, BlockInclude, Graph, ListType, ViewType},
     html,
     image::{image_full_path, ImageInfo},
+    link_report::{LinkKind, LinkProblemReason, LinkProblems},
+    markdown::MarkdownRenderer,
+    mentions::MentionMatcher,
     parse_string::{parse, Expression},
+    query::{
+        advanced::{QueryResults, Value},
+        evaluate_query,
+        simple::query_from_directive,
+        PageQuery,
+    },
     string_builder::StringBuilder,
     syntax_highlight,
+    whiteboard::{Portal, PortalKind, Whiteboard},
 };
 
 pub struct TitleSlugUid {
     pub title: String,
+    pub output_title: String,
     pub slug: String,
     pub uid: String,
     pub include: bool,
@@ -38,11 +50,14 @@ pub struct IdSlugUid {
     pub allow_embed: bool,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ManifestItem {
     pub slug: String,
     pub title: String,
     pub uid: String,
+    /// For a redirect stub, the slug of the page that it redirects to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect_to: Option<String>,
 }
 
 pub struct Page<'a> {
@@ -51,6 +66,8 @@ pub struct Page<'a> {
     pub slug: &'a str,
 
     pub latest_found_edit_time: Cell<u64>,
+    /// References that didn't resolve, found while rendering.
+    pub link_problems: LinkProblems,
 
     pub graph: &'a Graph,
     pub base_dir: &'a Path,
@@ -66,6 +83,67 @@ pub struct Page<'a> {
 
     pub picture_template_key: &'a str,
     pub image_info: &'a HashMap<String, ImageInfo>,
+
+    /// When set, plain-text mentions of exported pages are rendered as links.
+    pub mention_matcher: Option<&'a MentionMatcher>,
+
+    /// The drawing, if this page is a Logseq whiteboard
+    pub whiteboard: Option<Whiteboard>,
+}
+
+/// Normalize a page title the way Logseq does when matching links to pages: trimmed and
+/// lowercased, with the `___` and `%2F` that stand for `/` in filenames decoded.
+pub fn normalize_title(title: &str) -> String {
+    title
+        .trim()
+        .replace("___", "/")
+        .replace("%2F", "/")
+        .replace("%2f", "/")
+        .to_lowercase()
+}
+
+/// Find a page by its title, or by another name for it: the title derived from its filename if
+/// the page's title was changed, or one of its aliases. Both maps are keyed by the normalized
+/// titles from [normalize_title].
+pub fn lookup_page_by_title<'p>(
+    pages_by_title: &'p HashMap<String, IdSlugUid>,
+    pages_by_filename_title: &HashMap<String, String>,
+    title: &str,
+) -> Option<&'p IdSlugUid> {
+    let title = normalize_title(title);
+    if let Some(page) = pages_by_title.get(&title) {
+        return Some(page);
+    }
+
+    pages_by_filename_title
+        .get(&title)
+        .and_then(|lookup_title| pages_by_title.get(lookup_title))
+}
+
+/// Return true if the block contains a query.
+pub fn is_query_block(block: &Block) -> bool {
+    block.contents.borrow_parsed().iter().any(|e| match e {
+        Expression::BraceDirective(s) => query_from_directive(s).is_some(),
+        Expression::AdvancedQuery(_) => true,
+        _ => false,
+    })
+}
+
+/// Look up a result of the query in `query_block`, if it should be shown. Results that aren't
+/// exported are left out, as are other queries so that they can't recurse.
+pub fn visible_query_result<'a>(
+    graph: &'a Graph,
+    pages_by_id: &'a HashMap<usize, TitleSlugUid>,
+    query_block: &Block,
+    id: usize,
+) -> Option<(&'a Block, &'a TitleSlugUid)> {
+    let result = graph.blocks.get(&id)?;
+    let page = pages_by_id
+        .get(&result.containing_page)
+        .filter(|p| p.include)?;
+
+    let exported = id == result.containing_page || graph.is_block_rendered(id);
+    (exported && id != query_block.id && !is_query_block(result)).then_some((result, page))
 }
 
 fn write_depth(depth: usize) -> String {
@@ -111,16 +189,52 @@ impl<'a> Page<'a> {
         with_emdash
     }
 
-    fn lookup_page_by_title(&self, title: &str) -> Option<&IdSlugUid> {
-        if let Some(page) = self.pages_by_title.get(title) {
-            return Some(page);
+    /// Render text as HTML, linking any mentions of other exported pages when autolinking is
+    /// enabled.
+    fn render_text_with_mentions(&self, text: &'a str) -> StringBuilder<'a> {
+        let Some(matcher) = self.mention_matcher else {
+            return self.render_text(text).into();
+        };
+
+        let mut output = StringBuilder::new();
+        let mut last_end = 0;
+        for (range, title) in matcher.find_iter(text) {
+            let Some(page) = self
+                .lookup_page_by_title(title)
+                .filter(|p| p.include && p.id != self.id)
+            else {
+                continue;
+            };
+
+            if range.start > last_end {
+                output.push(self.render_text(&text[last_end..range.start]));
+            }
+
+            output.push(format!(
+                r##"<a href="{slug}">{label}</a>"##,
+                slug = html::escape(&page.slug),
+                label = self.render_text(&text[range.clone()])
+            ));
+            last_end = range.end;
         }
 
-        if let Some(lookup_title) = self.pages_by_filename_title.get(title) {
-            self.pages_by_title.get(lookup_title)
-        } else {
-            None
+        if output.is_empty() {
+            return self.render_text(text).into();
+        }
+
+        if last_end < text.len() {
+            output.push(self.render_text(&text[last_end..]));
         }
+
+        output
+    }
+
+    fn lookup_page_by_title(&self, title: &str) -> Option<&IdSlugUid> {
+        lookup_page_by_title(self.pages_by_title, self.pages_by_filename_title, title)
+    }
+
+    fn lookup_page_id(&self, title: &str) -> Option<usize> {
+        self.lookup_page_by_title(title).map(|page| page.id)
     }
 
     fn link_if_allowed_with_label(
@@ -166,11 +280,20 @@ impl<'a> Page<'a> {
         match block {
             Some(block) => {
                 self.render_line_without_header(block).map(|(result, _)| {
-                    match self
+                    let page = self
                         .pages_by_id
                         .get(&block.containing_page)
-                        .filter(|p| p.include)
-                    {
+                        .filter(|p| p.include);
+                    if page.is_none() {
+                        self.link_problems.add(
+                            containing_block,
+                            LinkKind::BlockRef,
+                            s,
+                            LinkProblemReason::UnexportedPage,
+                        );
+                    }
+
+                    match page {
                         Some(page) => {
                             // When the referenced page is exported, make this a link to the block.
                             let linked = StringBuilder::Vec(vec![
@@ -190,8 +313,11 @@ impl<'a> Page<'a> {
                 })
             }
             None => {
+                self.link_problems
+                    .check_missing_block_ref(containing_block, s);
+
                 // Block ref syntax can also be expandable text. So if we don't match on a block then just render it.
-                parse(self.graph.content_style, s)
+                parse(*containing_block.contents.borrow_style(), s)
                     .map_err(|e| eyre!("Parse Error: {}", e))
                     .and_then(|expressions| {
                         self.render_expressions(containing_block, &expressions, first, false)
@@ -254,9 +380,18 @@ impl<'a> Page<'a> {
         ))
     }
 
-    fn render_block_embed(&'a self, s: &'a str) -> Result<StringBuilder<'a>> {
-        self.graph
-            .block_from_uid(s)
+    fn render_block_embed(&'a self, block: &'a Block, s: &'a str) -> Result<StringBuilder<'a>> {
+        let embedded = self.graph.block_from_uid(s);
+        if embedded.is_none() {
+            self.link_problems.add(
+                block,
+                LinkKind::BlockEmbed,
+                s,
+                LinkProblemReason::MissingBlock,
+            );
+        }
+
+        embedded
             .map(|block| {
                 self.render_block_and_children(block, ViewType::default_view_type(), 0)
                     .map(|rendered| {
@@ -325,15 +460,161 @@ impl<'a> Page<'a> {
         ])
     }
 
+    fn query_result(&self, block: &Block, id: usize) -> Option<(&'a Block, &'a TitleSlugUid)> {
+        visible_query_result(self.graph, self.pages_by_id, block, id)
+    }
+
+    /// Render a link to a query result. Blocks are shown with their contents.
+    fn render_query_result(&'a self, result: &'a Block, page: &TitleSlugUid) -> Result<String> {
+        let title = self.render_text(&page.output_title);
+        let slug = html::escape(&page.slug);
+        if result.page_title.is_some() {
+            return Ok(format!(r##"<a href="{slug}">{title}</a>"##));
+        }
+
+        let href = if result.uid.is_empty() {
+            slug.to_string()
+        } else {
+            format!("{slug}#{}", result.uid)
+        };
+
+        let snippet = self.render_line_without_header(result)?.0.build();
+        Ok(format!(
+            r##"<a class="query-result-page" href="{href}">{title}</a> {snippet}"##
+        ))
+    }
+
+    fn render_query_list(&'a self, block: &'a Block, ids: Vec<usize>) -> Result<StringBuilder<'a>> {
+        let items = ids
+            .into_iter()
+            .filter_map(|id| self.query_result(block, id))
+            .map(|(result, page)| {
+                self.render_query_result(result, page)
+                    .map(|link| format!("<li>{link}</li>"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if items.is_empty() {
+            return Ok(StringBuilder::Empty);
+        }
+
+        Ok(StringBuilder::from(format!(
+            "<ul class=\"query-results\">\n{}\n</ul>",
+            items.join("\n")
+        )))
+    }
+
+    fn render_query_value(&'a self, block: &'a Block, value: &Value) -> Result<Option<String>> {
+        let rendered = match value {
+            Value::Entity(id) => match self.query_result(block, *id) {
+                Some((result, page)) => self.render_query_result(result, page)?,
+                None => return Ok(None),
+            },
+            Value::Str(s) => self.render_text(s).into_owned(),
+            Value::Int(i) => i.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Key(k) => format!(":{}", self.render_plain_text(k)),
+            Value::Set(values) => {
+                let mut rendered = Vec::with_capacity(values.len());
+                for value in values {
+                    match self.render_query_value(block, value)? {
+                        Some(v) => rendered.push(v),
+                        None => return Ok(None),
+                    }
+                }
+                rendered.join(", ")
+            }
+            Value::Properties(id) => self
+                .graph
+                .blocks
+                .get(id)
+                .map(|b| {
+                    b.attrs
+                        .iter()
+                        .sorted_by(|a, b| a.0.cmp(b.0))
+                        .map(|(name, values)| {
+                            self.render_text(&format!("{name}: {}", values.join(", ")))
+                                .into_owned()
+                        })
+                        .join("<br>")
+                })
+                .unwrap_or_default(),
+        };
+
+        Ok(Some(rendered))
+    }
+
+    fn render_query_table(
+        &'a self,
+        block: &'a Block,
+        columns: &[String],
+        rows: &[Vec<Value>],
+    ) -> Result<StringBuilder<'a>> {
+        let mut rendered_rows = Vec::with_capacity(rows.len());
+        'rows: for row in rows {
+            let mut cells = Vec::with_capacity(row.len());
+            for value in row {
+                // Leave out rows that refer to anything that isn't exported.
+                let Some(cell) = self.render_query_value(block, value)? else {
+                    continue 'rows;
+                };
+                cells.push(format!("<td>{cell}</td>"));
+            }
+            rendered_rows.push(format!("<tr>{}</tr>", cells.join("")));
+        }
+
+        if rendered_rows.is_empty() {
+            return Ok(StringBuilder::Empty);
+        }
+
+        let header = columns
+            .iter()
+            .map(|c| format!("<th>{}</th>", self.render_plain_text(c)))
+            .join("");
+
+        Ok(StringBuilder::from(format!(
+            "<table class=\"query-results\"><thead><tr>{header}</tr></thead><tbody>\n{}\n</tbody></table>",
+            rendered_rows.join("\n")
+        )))
+    }
+
+    fn render_query(&'a self, block: &'a Block, query: PageQuery) -> Result<StringBuilder<'a>> {
+        let lookup_page = |title: &str| self.lookup_page_id(title);
+        let Some((title, results)) = evaluate_query(self.graph, query, &self.title, &lookup_page)
+        else {
+            return Ok(StringBuilder::Empty);
+        };
+
+        let rendered = match results {
+            QueryResults::Entities(ids) => self.render_query_list(block, ids)?,
+            QueryResults::Table { columns, rows } => {
+                self.render_query_table(block, &columns, &rows)?
+            }
+        };
+
+        match title {
+            Some(title) if !rendered.is_blank() => Ok(StringBuilder::Vec(vec![
+                StringBuilder::from(format!(
+                    "<div class=\"query-title\">{}</div>\n",
+                    self.render_text(&title)
+                )),
+                rendered,
+            ])),
+            _ => Ok(rendered),
+        }
+    }
+
     fn render_brace_directive(
         &'a self,
         block: &'a Block,
         s: &'a str,
-    ) -> (StringBuilder<'a>, bool, bool) {
+    ) -> Result<(StringBuilder<'a>, bool, bool)> {
         let (value, render_children) = match s {
             "table" => (self.render_table(block), false),
             _ => {
-                if s.starts_with("query:") || s.starts_with("renderer ") {
+                if let Some(query) = query_from_directive(s) {
+                    (self.render_query(block, PageQuery::Simple(&query))?, true)
+                } else if s.starts_with("renderer ") {
                     (StringBuilder::Empty, true)
                 } else {
                     (
@@ -344,7 +625,7 @@ impl<'a> Page<'a> {
             }
         };
 
-        (value, true, render_children)
+        Ok((value, true, render_children))
     }
 
     fn render_style<'ex>(
@@ -451,10 +732,20 @@ impl<'a> Page<'a> {
     {
         let rendered = match e {
             Expression::Hashtag(s, dot) => {
+                self.link_problems.check_page(
+                    block,
+                    LinkKind::Tag,
+                    s,
+                    self.lookup_page_by_title(s),
+                );
                 (self.hashtag(s, *dot, omit_unexported_links), true, true)
             }
             Expression::RawHtml(s) => (StringBuilder::String((*s).into()), true, true),
-            Expression::Image { alt, url } => (self.render_image(url, alt)?, true, true),
+            Expression::Image { alt, url } => {
+                self.link_problems
+                    .check_image(block, self.base_dir, &self.path, url);
+                (self.render_image(url, alt)?, true, true)
+            }
             Expression::Video { url } => (self.render_video(url), true, true),
             Expression::Todo { done } => {
                 let done = if *done { "checked" } else { "" };
@@ -465,12 +756,28 @@ impl<'a> Page<'a> {
                     true,
                 )
             }
-            Expression::Link(s) => (self.link_if_allowed(s, omit_unexported_links), true, true),
-            Expression::MarkdownInternalLink { page, label } => (
-                self.link_if_allowed_with_label(page, Some(label), false),
-                true,
-                true,
-            ),
+            Expression::Link(s) => {
+                self.link_problems.check_page(
+                    block,
+                    LinkKind::Link,
+                    s,
+                    self.lookup_page_by_title(s),
+                );
+                (self.link_if_allowed(s, omit_unexported_links), true, true)
+            }
+            Expression::MarkdownInternalLink { page, label } => {
+                self.link_problems.check_page(
+                    block,
+                    LinkKind::Link,
+                    page,
+                    self.lookup_page_by_title(page),
+                );
+                (
+                    self.link_if_allowed_with_label(page, Some(label), false),
+                    true,
+                    true,
+                )
+            }
             Expression::MarkdownExternalLink { title, url } => (
                 format!(
                     r##"<a href="{url}">{title}</a>"##,
@@ -519,9 +826,14 @@ impl<'a> Page<'a> {
                 self.config.class_blockquote.as_str(),
                 e,
             )?,
-            Expression::Text(s) => (self.render_text(s).into(), true, true),
+            Expression::Text(s) => (self.render_text_with_mentions(s), true, true),
             Expression::BlockRef(s) => self.render_block_ref(block, s, first)?,
-            Expression::BraceDirective(s) => self.render_brace_directive(block, s),
+            Expression::BraceDirective(s) => self.render_brace_directive(block, s)?,
+            Expression::AdvancedQuery(s) => (
+                self.render_query(block, PageQuery::Advanced(s))?,
+                true,
+                true,
+            ),
             Expression::Table => (self.render_table(block), true, false),
             Expression::HRule => {
                 let tag = if self.config.class_hr.is_empty() {
@@ -532,9 +844,12 @@ impl<'a> Page<'a> {
 
                 (tag, true, true)
             }
-            Expression::BlockEmbed(s) => (self.render_block_embed(s)?, true, true),
+            Expression::BlockEmbed(s) => (self.render_block_embed(block, s)?, true, true),
             Expression::PageEmbed(s) => {
-                let page = self.lookup_page_by_title(*s).filter(|p| p.allow_embed);
+                let page = self.lookup_page_by_title(s);
+                self.link_problems
+                    .check_page(block, LinkKind::PageEmbed, s, page);
+                let page = page.filter(|p| p.allow_embed);
 
                 let result = page
                     .map(|IdSlugUid { id: block_id, .. }| {
@@ -779,16 +1094,7 @@ impl<'a> Page<'a> {
                 result.push(*child_container_start);
             }
 
-            let mut children = block
-                .children
-                .iter()
-                .filter_map(|id| self.graph.blocks.get(id))
-                .collect::<Vec<_>>();
-            if self.graph.block_explicit_ordering {
-                children.sort_by_key(|b| b.order);
-            }
-
-            for child in &children {
+            for child in self.graph.children(block) {
                 let child_content =
                     self.render_block_and_children(child, view_type, child_depth)?;
 
@@ -824,7 +1130,117 @@ impl<'a> Page<'a> {
         Ok(result)
     }
 
+    /// Render just the contents of a block, without its heading or children. This is used for
+    /// showing a block in the context of another page, such as in the backlinks.
+    pub fn render_snippet(&'a self, block: &'a Block) -> Result<String> {
+        if self.config.output_format == OutputFormat::Markdown {
+            return self.markdown_renderer().render_snippet(block);
+        }
+
+        self.render_line_without_header(block)
+            .map(|(result, _)| result.build())
+    }
+
+    fn markdown_renderer(&'a self) -> MarkdownRenderer<'a> {
+        MarkdownRenderer {
+            page_id: self.id,
+            title: &self.title,
+            graph: self.graph,
+            base_dir: self.base_dir,
+            path: &self.path,
+            heading_delta: self.heading_delta,
+            pages_by_title: self.pages_by_title,
+            pages_by_filename_title: self.pages_by_filename_title,
+            pages_by_id: self.pages_by_id,
+            omitted_attributes: self.omitted_attributes,
+            image_info: self.image_info,
+            mention_matcher: self.mention_matcher,
+            filter_link_only_blocks: self.config.filter_link_only_blocks,
+            convert_emdash: self.config.convert_emdash,
+            latest_found_edit_time: &self.latest_found_edit_time,
+            link_problems: &self.link_problems,
+        }
+    }
+
+    /// Render the contents of a whiteboard portal, which shows either a page or a block.
+    fn render_portal(&'a self, portal: &Portal) -> Result<String> {
+        match portal.kind {
+            PortalKind::Page => {
+                // Portals refer to pages by their lowercased name, which the lookup handles.
+                let Some(page) = self.lookup_page_by_title(&portal.target) else {
+                    return Ok(html::escape(&portal.target).into_owned());
+                };
+
+                let title = if page.include {
+                    format!(
+                        r##"<a href="{}">{}</a>"##,
+                        html::escape(&page.slug),
+                        html::escape(&page.output_title)
+                    )
+                } else {
+                    html::escape(&page.output_title).into_owned()
+                };
+
+                let contents = if page.allow_embed {
+                    let block = self.graph.blocks.get(&page.id).unwrap();
+                    self.render_block_and_children(block, ViewType::default_view_type(), 0)?
+                        .build()
+                } else {
+                    String::new()
+                };
+
+                Ok(format!(
+                    r##"{}<div class="wb-portal-title">{title}</div>{contents}</div>"##,
+                    render_opening_tag("div", self.config.class_page_embed_container.as_str())
+                ))
+            }
+            PortalKind::Block => {
+                let Some(block) = self.graph.block_from_uid(&portal.target) else {
+                    return Ok(String::new());
+                };
+
+                let rendered = self
+                    .render_block_and_children(block, ViewType::default_view_type(), 0)?
+                    .build();
+
+                // Link back to the block when its page is exported.
+                let source = self
+                    .pages_by_id
+                    .get(&block.containing_page)
+                    .filter(|p| p.include)
+                    .map(|page| {
+                        format!(
+                            r##"<a class="wb-portal-source" href="{}#{}">{}</a>"##,
+                            html::escape(&page.slug),
+                            html::escape(&block.uid),
+                            html::escape(&page.output_title)
+                        )
+                    })
+                    .unwrap_or_default();
+
+                Ok(format!(
+                    "{}{rendered}{source}</div>",
+                    render_opening_tag("div", self.config.class_block_embed.as_str())
+                ))
+            }
+        }
+    }
+
     pub fn render(&'a self) -> Result<String> {
+        match self.config.output_format {
+            OutputFormat::Html => self.render_html(),
+            // Whiteboards are drawn as SVG, which Markdown can contain as raw HTML.
+            OutputFormat::Markdown if self.whiteboard.is_some() => self.render_html(),
+            OutputFormat::Markdown => self.markdown_renderer().render(),
+        }
+    }
+
+    /// Render the page as HTML, regardless of the configured output format.
+    pub fn render_html(&'a self) -> Result<String> {
+        if let Some(whiteboard) = self.whiteboard.as_ref() {
+            return whiteboard.render_svg(|portal| self.render_portal(portal));
+        }
+
         let block = self.graph.blocks.get(&self.id).unwrap();
         self.render_block_and_children(block, ViewType::default_view_type(), 0)
             .map(|results| (results.build()))
@@ -843,4 +1259,45 @@ mod tests {
 
     #[test]
     fn table_omits_children() {}
+
+    #[test]
+    fn lookup_by_other_names() {
+        let page = IdSlugUid {
+            id: 1,
+            output_title: String::from("Books/Canonical"),
+            slug: String::from("/canonical"),
+            uid: String::new(),
+            include: true,
+            allow_embed: true,
+        };
+        let pages_by_title = [(String::from("books/canonical"), page)]
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let other_names = [
+            (String::from("an alias"), String::from("books/canonical")),
+            (
+                String::from("canonical_file"),
+                String::from("books/canonical"),
+            ),
+        ]
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+        for title in [
+            "Books/Canonical",
+            " books/CANONICAL ",
+            "Books___Canonical",
+            "books%2Fcanonical",
+            "An Alias",
+            "canonical_file",
+        ] {
+            let found = lookup_page_by_title(&pages_by_title, &other_names, title);
+            assert_eq!(
+                found.map(|p| p.slug.as_str()),
+                Some("/canonical"),
+                "{title}"
+            );
+        }
+        assert!(lookup_page_by_title(&pages_by_title, &other_names, "Missing").is_none());
+    }
 }
@@ -1,12 +1,13 @@
 use nom::{
     branch::alt,
-    bytes::complete::{is_not, tag, take_until, take_while1},
+    bytes::complete::{is_not, tag, take_till, take_till1, take_until, take_while1},
     character::{
-        complete::{char, multispace0, multispace1},
+        complete::{char, multispace0, multispace1, not_line_ending, one_of},
         is_newline,
     },
     combinator::{all_consuming, cond, map, map_opt, map_parser, opt},
     error::context,
+    multi::separated_list1,
     sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
     IResult,
 };
@@ -16,6 +17,17 @@ use urlocator::{UrlLocation, UrlLocator};
 pub enum ContentStyle {
     Roam,
     Logseq,
+    /// Logseq pages written in org-mode
+    Org,
+    /// Notes in an Obsidian vault
+    Obsidian,
+}
+
+impl ContentStyle {
+    /// True for the styles used by Logseq, which share most of their syntax.
+    fn is_logseq(&self) -> bool {
+        matches!(self, ContentStyle::Logseq | ContentStyle::Org)
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -31,6 +43,8 @@ pub enum Expression<'a> {
         url: &'a str,
     },
     BraceDirective(&'a str),
+    /// The body of a `#+BEGIN_QUERY` block
+    AdvancedQuery(&'a str),
     Table,
     Todo {
         done: bool,
@@ -213,6 +227,146 @@ fn highlight(content_style: ContentStyle, input: &str) -> IResult<&str, Vec<Expr
     style(content_style, "^^")(input)
 }
 
+/// Parses org-mode emphasis like `*bold*` or `/italic/`. To avoid matching things like `and/or/not`,
+/// the contents can not start or end with whitespace, and the closing marker can not be followed
+/// directly by a letter or number.
+fn org_emphasis<'a>(marker: char) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    move |input| {
+        let (rest, inner) = delimited(
+            char(marker),
+            take_till1(|c| c == marker || c == '\n'),
+            char(marker),
+        )(input)?;
+
+        if inner.starts_with(char::is_whitespace)
+            || inner.ends_with(char::is_whitespace)
+            || rest.starts_with(char::is_alphanumeric)
+        {
+            return Err(nom::Err::Error(nom::error::Error::new(
+                input,
+                nom::error::ErrorKind::Verify,
+            )));
+        }
+
+        Ok((rest, inner))
+    }
+}
+
+fn org_styled<'a>(
+    content_style: ContentStyle,
+    marker: char,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<Expression<'a>>> {
+    map_parser(org_emphasis(marker), move |i| {
+        parse_inline(content_style, false, i)
+    })
+}
+
+pub fn is_image_url(url: &str) -> bool {
+    let extension = url.rsplit_once('.').map(|(_, ext)| ext.to_lowercase());
+    matches!(
+        extension.as_deref(),
+        Some("png" | "jpg" | "jpeg" | "gif" | "svg" | "webp" | "avif")
+    )
+}
+
+/// Parses org-mode links: `[[target][label]]`, and `[[target]]` when the target is a URL, file, or
+/// image. Other `[[target]]` links are page links and are handled by the same parser as Logseq.
+fn org_link<'a>(input: &'a str) -> IResult<&'a str, Expression<'a>> {
+    let (rest, (target, label)) = delimited(
+        tag("[["),
+        pair(is_not("[]"), opt(preceded(tag("]["), is_not("[]")))),
+        tag("]]"),
+    )(input)?;
+
+    let file = target.strip_prefix("file:");
+    let is_url = target.contains("://") || target.starts_with("mailto:");
+    let url = file.unwrap_or(target);
+
+    let expr = match label {
+        None if is_image_url(url) && (file.is_some() || is_url || url.starts_with('.')) => {
+            Expression::Image { alt: "", url }
+        }
+        None if file.is_some() || is_url => Expression::MarkdownExternalLink { title: url, url },
+        None => {
+            return Err(nom::Err::Error(nom::error::Error::new(
+                input,
+                nom::error::ErrorKind::Verify,
+            )))
+        }
+        Some(label) if file.is_some() || is_url => {
+            Expression::MarkdownExternalLink { title: label, url }
+        }
+        Some(label) => Expression::MarkdownInternalLink {
+            label,
+            page: target,
+        },
+    };
+
+    Ok((rest, expr))
+}
+
+/// Parses Obsidian wikilinks, which can have an alias like `[[page|alias]]` and point into a page
+/// like `[[page#heading]]` or `[[page#^block-id]]`. With a leading `!` they are embeds instead.
+fn obsidian_link(input: &str) -> IResult<&str, Expression<'_>> {
+    let (rest, (embed, inner)) = pair(opt(char('!')), link)(input)?;
+
+    let (target, alias) = match inner.split_once('|') {
+        Some((target, alias)) => (target, Some(alias)),
+        None => (inner, None),
+    };
+    let (page, subpath) = match target.split_once('#') {
+        Some((page, subpath)) => (page, Some(subpath)),
+        None => (target, None),
+    };
+    let block_id = subpath.and_then(|s| s.strip_prefix('^'));
+
+    let expr = match (embed.is_some(), block_id, alias) {
+        // The alias of an image embed is its size, which isn't useful as alt text.
+        (true, None, _) if is_image_url(page) => Expression::Image { alt: "", url: page },
+        (true, Some(id), _) => Expression::BlockEmbed(id),
+        (false, Some(id), _) => Expression::BlockRef(id),
+        // Links to a heading in the same page
+        (_, None, _) if page.is_empty() => Expression::Text(alias.unwrap_or(inner)),
+        (true, None, _) => Expression::PageEmbed(page),
+        (false, None, Some(label)) => Expression::MarkdownInternalLink { label, page },
+        (false, None, None) => Expression::Link(page),
+    };
+
+    Ok((rest, expr))
+}
+
+/// Parses an Obsidian task marker at the start of a list item, like `[ ]` or `[x]`.
+fn obsidian_task(input: &str) -> IResult<&str, Expression<'_>> {
+    map(
+        terminated(delimited(char('['), one_of(" xX"), char(']')), char(' ')),
+        |c| Expression::Todo { done: c != ' ' },
+    )(input)
+}
+
+/// Parses an Obsidian callout like `> [!note] Title` and the lines following it, as a block
+/// quote that starts with the title in bold. The type is used as the title when there isn't one.
+fn obsidian_callout(input: &str) -> IResult<&str, Vec<Expression<'_>>> {
+    let (body, (kind, title)) = preceded(
+        tag("> [!"),
+        pair(
+            terminated(is_not("]\n"), pair(char(']'), opt(one_of("+-")))),
+            take_till(|c| c == '\n'),
+        ),
+    )(input)?;
+
+    let title = title.trim();
+    let title = if title.is_empty() { kind } else { title };
+
+    let mut output = vec![Expression::Bold(vec![Expression::Text(title)])];
+    if let Some(body) = body.strip_prefix('\n') {
+        let (_, contents) = parse_inline(ContentStyle::Obsidian, false, body)?;
+        output.push(Expression::Text("\n"));
+        output.extend(contents);
+    }
+
+    Ok(("", output))
+}
+
 fn latex(input: &str) -> IResult<&str, &str> {
     fenced("$$", "$$")(input)
 }
@@ -253,10 +407,7 @@ fn brace_directive_contents(content_style: ContentStyle, input: &str) -> IResult
                         ),
                         |r| r,
                     ),
-                    map_opt(
-                        cond(content_style == ContentStyle::Logseq, multispace1),
-                        |r| r,
-                    ),
+                    map_opt(cond(content_style.is_logseq(), multispace1), |r| r),
                 )),
                 alt((
                     map(block_ref, Expression::BlockEmbed),
@@ -292,6 +443,14 @@ fn image(input: &str) -> IResult<&str, (&str, &str)> {
     preceded(char('!'), markdown_link)(input)
 }
 
+/// Parses a Logseq advanced query, between `#+BEGIN_QUERY` and `#+END_QUERY`
+fn advanced_query(input: &str) -> IResult<&str, &str> {
+    alt((
+        fenced("#+BEGIN_QUERY", "#+END_QUERY"),
+        fenced("#+begin_query", "#+end_query"),
+    ))(input)
+}
+
 fn raw_html(input: &str) -> IResult<&str, &str> {
     fenced("@@html: ", "@@")(input)
 }
@@ -329,7 +488,25 @@ fn directive(
         map(triple_backtick, Expression::TripleBacktick),
         map(single_backtick, Expression::SingleBacktick),
         |i| brace_directive(content_style, i),
+        map_opt(
+            cond(
+                content_style.is_logseq(),
+                map(advanced_query, Expression::AdvancedQuery),
+            ),
+            |r| r,
+        ),
         map(hashtag, |(v, dot)| Expression::Hashtag(v, dot)),
+        map_opt(cond(content_style == ContentStyle::Org, org_link), |r| r),
+        map_opt(
+            cond(
+                content_style == ContentStyle::Obsidian,
+                alt((
+                    obsidian_link,
+                    map(style(content_style, "=="), Expression::Highlight),
+                )),
+            ),
+            |r| r,
+        ),
         map(link, Expression::Link),
         map(block_ref, Expression::BlockRef),
         map(image, |(alt, url)| Expression::Image { alt, url }),
@@ -359,7 +536,7 @@ fn directive(
         ),
         map_opt(
             cond(
-                content_style == ContentStyle::Logseq,
+                matches!(content_style, ContentStyle::Logseq | ContentStyle::Obsidian),
                 alt((
                     map(
                         context("bold", |i| logseq_bold(content_style, i)),
@@ -370,6 +547,23 @@ fn directive(
             ),
             |r| r,
         ),
+        map_opt(
+            cond(
+                content_style == ContentStyle::Org,
+                alt((
+                    map(
+                        preceded(char('\n'), |i| quote_lines(content_style, i)),
+                        Expression::BlockQuote,
+                    ),
+                    map(org_styled(content_style, '*'), Expression::Bold),
+                    map(org_styled(content_style, '/'), Expression::Italic),
+                    map(org_styled(content_style, '+'), Expression::Strike),
+                    map(org_emphasis('~'), Expression::SingleBacktick),
+                    map(org_emphasis('='), Expression::SingleBacktick),
+                )),
+            ),
+            |r| r,
+        ),
         map(|i| strike(content_style, i), Expression::Strike),
         map(|i| highlight(content_style, i), Expression::Highlight),
         map(latex, Expression::Latex),
@@ -443,7 +637,7 @@ pub fn attribute(style: ContentStyle, input: &str) -> IResult<&str, (&str, Vec<E
             tag("::"),
             preceded(multispace0, |i| parse_inline(style, false, i)),
         )(input),
-        ContentStyle::Logseq => separated_pair(
+        ContentStyle::Logseq | ContentStyle::Org | ContentStyle::Obsidian => separated_pair(
             preceded(
                 multispace0,
                 take_while1(|c| nonws_char(c) && c != ',' && c != ':'),
@@ -464,12 +658,56 @@ fn logseq_todo(input: &str) -> IResult<&str, Expression> {
     ))(input)
 }
 
+/// Consecutive lines that start with `> `, which is how the `#+BEGIN_QUOTE` blocks in org pages
+/// reach the parser.
+fn quote_lines<'a>(
+    content_style: ContentStyle,
+    input: &'a str,
+) -> IResult<&'a str, Vec<Expression<'a>>> {
+    let (rest, lines) = separated_list1(char('\n'), preceded(tag("> "), not_line_ending))(input)?;
+
+    let mut output = Vec::new();
+    for (i, line) in lines.into_iter().enumerate() {
+        if i > 0 {
+            output.push(Expression::Text("\n"));
+        }
+        output.extend(parse_inline(content_style, false, line)?.1);
+    }
+
+    Ok((rest, output))
+}
+
 pub fn parse<'a>(
     content_style: ContentStyle,
     input: &'a str,
 ) -> Result<Vec<Expression<'a>>, nom::Err<nom::error::Error<&'a str>>> {
     alt((
         map(all_consuming(tag("---")), |_| vec![Expression::HRule]),
+        map_opt(
+            cond(
+                content_style == ContentStyle::Obsidian,
+                map(all_consuming(obsidian_callout), |values| {
+                    vec![Expression::BlockQuote(values)]
+                }),
+            ),
+            |r| r,
+        ),
+        map_opt(
+            cond(
+                content_style == ContentStyle::Org,
+                all_consuming(map(
+                    pair(
+                        |i| quote_lines(content_style, i),
+                        |i| parse_inline(content_style, true, i),
+                    ),
+                    |(quote, mut exprs)| {
+                        exprs.insert(0, Expression::BlockQuote(quote));
+                        exprs
+                    },
+                )),
+            ),
+            |r| r,
+        ),
         map(
             all_consuming(preceded(tag("> "), |i| {
                 parse_inline(content_style, true, i)
@@ -488,7 +726,7 @@ pub fn parse<'a>(
         ),
         map_opt(
             cond(
-                content_style == ContentStyle::Logseq,
+                content_style.is_logseq(),
                 all_consuming(map(
                     pair(logseq_todo, |i| parse_inline(content_style, true, i)),
                     |(todo_expr, mut exprs)| {
@@ -499,6 +737,19 @@ pub fn parse<'a>(
             ),
             |r| r,
         ),
+        map_opt(
+            cond(
+                content_style == ContentStyle::Obsidian,
+                all_consuming(map(
+                    pair(obsidian_task, |i| parse_inline(content_style, true, i)),
+                    |(todo_expr, mut exprs)| {
+                        exprs.insert(0, todo_expr);
+                        exprs
+                    },
+                )),
+            ),
+            |r| r,
+        ),
         all_consuming(|input| parse_inline(content_style, true, input)),
     ))(input)
     .map(|(_, results)| results)
@@ -591,3 +591,156 @@ fn blockquote_fake_2() {
 > and another"##;
     test_parse_all_styles(input, vec![Expression::Text("Some text\n> and another")]);
 }
+
+#[test]
+fn org_emphasis() {
+    let input = "some *bold* and /italic/ +struck+ ~code~ and/or/not";
+    assert_eq!(
+        parse(ContentStyle::Org, input).unwrap(),
+        vec![
+            Text("some "),
+            Bold(vec![Text("bold")]),
+            Text(" and "),
+            Italic(vec![Text("italic")]),
+            Text(" "),
+            Strike(vec![Text("struck")]),
+            Text(" "),
+            SingleBacktick("code"),
+            Text(" and/or/not"),
+        ]
+    );
+}
+
+#[test]
+fn org_links() {
+    let input =
+        "[[https://example.com][Example]] [[Some Page][label]] [[Other Page]] [[../assets/a.png]]";
+    assert_eq!(
+        parse(ContentStyle::Org, input).unwrap(),
+        vec![
+            MarkdownExternalLink {
+                title: "Example",
+                url: "https://example.com"
+            },
+            Text(" "),
+            MarkdownInternalLink {
+                label: "label",
+                page: "Some Page"
+            },
+            Text(" "),
+            Link("Other Page"),
+            Text(" "),
+            Image {
+                alt: "",
+                url: "../assets/a.png"
+            },
+        ]
+    );
+}
+
+#[test]
+fn org_quote_lines() {
+    let input = "> first *line*\n> second\nafter";
+    assert_eq!(
+        parse(ContentStyle::Org, input).unwrap(),
+        vec![
+            BlockQuote(vec![
+                Text("first "),
+                Bold(vec![Text("line")]),
+                Text("\n"),
+                Text("second"),
+            ]),
+            Text("\nafter"),
+        ]
+    );
+}
+
+#[test]
+fn org_todo() {
+    let input = "DONE finish the *report*";
+    assert_eq!(
+        parse(ContentStyle::Org, input).unwrap(),
+        vec![
+            Todo { done: true },
+            Text(" finish the "),
+            Bold(vec![Text("report")]),
+        ]
+    );
+}
+
+#[test]
+fn advanced_query() {
+    let input = "#+BEGIN_QUERY\n{:query [:find (pull ?b [*]) :where [?b :block/marker \"TODO\"]]}\n#+END_QUERY";
+    assert_eq!(
+        parse(ContentStyle::Logseq, input).unwrap(),
+        vec![AdvancedQuery(
+            "\n{:query [:find (pull ?b [*]) :where [?b :block/marker \"TODO\"]]}\n"
+        )]
+    );
+}
+
+#[test]
+fn obsidian_links() {
+    let input = "[[Page|alias]] [[Page#Heading]] [[Page#^abc-1]] ![[Other]] ![[Other#^def]] ![[pic.png|300]] #nested/tag ==marked==";
+    assert_eq!(
+        parse(ContentStyle::Obsidian, input).unwrap(),
+        vec![
+            MarkdownInternalLink {
+                label: "alias",
+                page: "Page"
+            },
+            Text(" "),
+            Link("Page"),
+            Text(" "),
+            BlockRef("abc-1"),
+            Text(" "),
+            PageEmbed("Other"),
+            Text(" "),
+            BlockEmbed("def"),
+            Text(" "),
+            Image {
+                alt: "",
+                url: "pic.png"
+            },
+            Text(" "),
+            Hashtag("nested/tag", false),
+            Text(" "),
+            Highlight(vec![Text("marked")]),
+        ]
+    );
+}
+
+#[test]
+fn obsidian_task() {
+    let input = "[x] finish the **report**";
+    assert_eq!(
+        parse(ContentStyle::Obsidian, input).unwrap(),
+        vec![
+            Todo { done: true },
+            Text("finish the "),
+            Bold(vec![Text("report")]),
+        ]
+    );
+}
+
+#[test]
+fn obsidian_callout() {
+    assert_eq!(
+        parse(
+            ContentStyle::Obsidian,
+            "> [!warning]- Be careful\nwith [[this]]"
+        )
+        .unwrap(),
+        vec![BlockQuote(vec![
+            Bold(vec![Text("Be careful")]),
+            Text("\n"),
+            Text("with "),
+            Link("this"),
+        ])]
+    );
+
+    assert_eq!(
+        parse(ContentStyle::Obsidian, "> [!note]").unwrap(),
+        vec![BlockQuote(vec![Bold(vec![Text("note")])])]
+    );
+}
@@ -0,0 +1,229 @@
+use ahash::{HashMap, HashSet};
+use itertools::Itertools;
+
+use crate::{
+    graph::{Block, Graph},
+    page::{lookup_page_by_title, IdSlugUid},
+    parse_string::{parse, Expression},
+};
+
+/// Block references and embeds can refer to blocks that refer back to them, so stop expanding
+/// them past this depth.
+const MAX_REF_DEPTH: usize = 5;
+
+/// Renders blocks as text without any markup, for uses like search and page descriptions.
+pub struct PlainTextRenderer<'a> {
+    pub graph: &'a Graph,
+    pub pages_by_title: &'a HashMap<String, IdSlugUid>,
+    pub pages_by_filename_title: &'a HashMap<String, String>,
+    pub omitted_attributes: &'a HashSet<&'a str>,
+}
+
+impl<'a> PlainTextRenderer<'a> {
+    /// The title to show for a link to a page.
+    fn link_title<'s>(&'s self, title: &'s str) -> &'s str {
+        lookup_page_by_title(self.pages_by_title, self.pages_by_filename_title, title)
+            .map(|page| page.output_title.as_str())
+            .unwrap_or(title)
+    }
+
+    fn render_block_ref(&self, uid: &str, depth: usize, output: &mut String) {
+        if depth >= MAX_REF_DEPTH {
+            return;
+        }
+
+        if let Some(block) = self.graph.block_from_uid(uid) {
+            self.render_expressions(block, block.contents.borrow_parsed(), depth + 1, output);
+        }
+    }
+
+    fn render_expressions(
+        &self,
+        block: &Block,
+        expressions: &[Expression],
+        depth: usize,
+        output: &mut String,
+    ) {
+        for expr in expressions {
+            match expr {
+                Expression::Text(s)
+                | Expression::SingleBacktick(s)
+                | Expression::TripleBacktick(s)
+                | Expression::Latex(s)
+                | Expression::RawHyperlink(s) => output.push_str(s),
+                Expression::Link(s) | Expression::Hashtag(s, false) => {
+                    output.push_str(self.link_title(s))
+                }
+                Expression::MarkdownInternalLink { label, .. } => output.push_str(label),
+                Expression::MarkdownExternalLink { title, .. } => output.push_str(title),
+                Expression::Image { alt, .. } => output.push_str(alt),
+                Expression::BlockRef(uid) => {
+                    if self.graph.block_from_uid(uid).is_some() {
+                        self.render_block_ref(uid, depth, output);
+                    } else if let Ok(expressions) = parse(*block.contents.borrow_style(), uid) {
+                        // Like the HTML renderer, text that isn't a real block ref is kept.
+                        self.render_expressions(block, &expressions, depth, output);
+                    }
+                }
+                Expression::BlockEmbed(uid) => {
+                    output.push('\n');
+                    self.render_block_ref(uid, depth, output);
+                    output.push('\n');
+                }
+                Expression::Attribute { name, value }
+                    if !self.omitted_attributes.contains(name) =>
+                {
+                    output.push_str(name);
+                    output.push_str(": ");
+                    self.render_expressions(block, value, depth, output);
+                    output.push('\n');
+                }
+                Expression::Bold(e)
+                | Expression::Italic(e)
+                | Expression::Strike(e)
+                | Expression::Highlight(e)
+                | Expression::BlockQuote(e) => self.render_expressions(block, e, depth, output),
+                // Directives, embeds, and other things that don't have any text of their own.
+                Expression::Hashtag(_, true)
+                | Expression::Attribute { .. }
+                | Expression::RawHtml(_)
+                | Expression::Video { .. }
+                | Expression::BraceDirective(_)
+                | Expression::AdvancedQuery(_)
+                | Expression::Table
+                | Expression::Todo { .. }
+                | Expression::PageEmbed(_)
+                | Expression::HRule => {}
+            }
+        }
+    }
+
+    /// Render the contents of a block, without its children. Whitespace is collapsed into single
+    /// spaces.
+    pub fn block_text(&self, block: &Block) -> String {
+        let mut output = String::new();
+        self.render_expressions(block, block.contents.borrow_parsed(), 0, &mut output);
+        output.split_whitespace().join(" ")
+    }
+
+    fn collect_rendered_blocks(&self, block: &'a Block, output: &mut Vec<&'a Block>) {
+        if self.graph.is_block_rendered(block.id) {
+            output.push(block);
+        }
+
+        for child in self.graph.children(block) {
+            self.collect_rendered_blocks(child, output);
+        }
+    }
+
+    /// The blocks of a page that are rendered, in order, along with their text. Blocks without
+    /// any text are omitted.
+    pub fn page_blocks(&self, page_id: usize) -> Vec<(&'a Block, String)> {
+        let Some(root) = self.graph.blocks.get(&page_id) else {
+            return Vec::new();
+        };
+
+        let mut blocks = Vec::new();
+        self.collect_rendered_blocks(root, &mut blocks);
+
+        blocks
+            .into_iter()
+            .map(|block| (block, self.block_text(block)))
+            .filter(|(_, text)| !text.is_empty())
+            .collect()
+    }
+
+    /// Render the text of a page, with each block on its own line.
+    pub fn page_text(&self, page_id: usize) -> String {
+        self.page_blocks(page_id)
+            .into_iter()
+            .map(|(_, text)| text)
+            .join("\n")
+    }
+}
+
+/// Shorten text to at most `max_chars` characters, breaking at a word boundary and adding an
+/// ellipsis if anything was cut off.
+pub fn excerpt(text: &str, max_chars: usize) -> String {
+    let text = text.split_whitespace().join(" ");
+    if text.chars().count() <= max_chars {
+        return text;
+    }
+
+    let cut = text
+        .char_indices()
+        .nth(max_chars)
+        .map(|(i, _)| i)
+        .unwrap_or(text.len());
+    let shortened = &text[..cut];
+    let shortened = match shortened.rfind(' ') {
+        Some(space) if space > 0 => &shortened[..space],
+        _ => shortened,
+    };
+
+    format!(
+        "{}…",
+        shortened.trim_end_matches(|c: char| c.is_ascii_punctuation() || c.is_whitespace())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(id: usize, uid: &str, contents: &str, children: &[usize]) -> Block {
+        Block {
+            uid: uid.to_string(),
+            children: children.iter().copied().collect(),
+            ..Block::test(id, 0, contents)
+        }
+    }
+
+    #[test]
+    fn plain_text() {
+        let mut graph = Graph::new(false);
+        let mut page = block(0, "", "", &[1, 2, 3]);
+        page.page_title = Some(String::from("Page"));
+        graph.add_block(page);
+        graph.add_block(block(
+            1,
+            "abc",
+            "TODO Some **bold** text about [[other page]] and `let x = 1;` {{table}}",
+            &[],
+        ));
+        graph.add_block(block(2, "", "See ((abc)) and ((not a ref))", &[]));
+        graph.add_block(block(3, "", "private:: yes", &[]));
+
+        let pages_by_title = HashMap::from_iter([(
+            String::from("other page"),
+            IdSlugUid {
+                id: 10,
+                output_title: String::from("The Other Page"),
+                slug: String::from("/other"),
+                uid: String::new(),
+                include: true,
+                allow_embed: false,
+            },
+        )]);
+        let pages_by_filename_title = HashMap::default();
+        let omitted_attributes = HashSet::from_iter(["private"]);
+        let renderer = PlainTextRenderer {
+            graph: &graph,
+            pages_by_title: &pages_by_title,
+            pages_by_filename_title: &pages_by_filename_title,
+            omitted_attributes: &omitted_attributes,
+        };
+
+        assert_eq!(
+            renderer.page_text(0),
+            "Some bold text about The Other Page and let x = 1;\nSee Some bold text about The Other Page and let x = 1; and not a ref"
+        );
+    }
+
+    #[test]
+    fn excerpts() {
+        assert_eq!(excerpt("Short   text", 20), "Short text");
+        assert_eq!(excerpt("One two three, four", 15), "One two three…");
+        assert_eq!(excerpt("Unbreakable", 5), "Unbre…");
+    }
+}
@@ -0,0 +1,1198 @@
+//! Evaluation of Logseq advanced queries, the Datalog queries written between `#+BEGIN_QUERY` and
+//! `#+END_QUERY`.
+//!
+//! Only a subset of Datascript is supported, and a query that uses anything else is skipped with
+//! a warning.
+//!
+//! - `:find` takes variables and `(pull ?var [...])`. A single `pull` returns a list of blocks or
+//!   pages, and anything else returns a table with a column for each variable.
+//! - `:in` binds variables to the values in `:inputs`. Relative dates like `:today` and `:-7d`
+//!   become journal days like `20230301`, and `:current-page` becomes the lowercased name of the
+//!   page containing the query.
+//! - `:where` supports:
+//!   - Data patterns like `[?b :block/marker ?m]` on the attributes `:block/name`,
+//!     `:block/original-name`, `:block/content`, `:block/marker`, `:block/page`, `:block/parent`,
+//!     `:block/refs`, `:block/path-refs`, `:block/tags`, `:block/properties`, `:block/uuid`,
+//!     `:block/journal?`, and `:block/journal-day`.
+//!   - The predicates `=`, `not=`, `<`, `<=`, `>`, `>=`, `contains?`, and
+//!     `clojure.string/includes?`, `starts-with?`, and `ends-with?`.
+//!   - Looking up a property with `[(get ?props :name) ?value]`, with an optional default value.
+//!   - `not`, `not-join`, `or`, `or-join`, and `and` inside of an `or`.
+//!   - The Logseq rules `(task ?b #{"TODO"})`, `(page-ref ?b "page")`,
+//!     `(property ?b :name "value")`, `(page-property ?p :name "value")`, and
+//!     `(page-tags ?p #{"tag"})`.
+//! - A `:query` that is a string is run as a simple query.
+//! - `:title` is shown above the results. `:view` and `:result-transform` can not be evaluated, so
+//!   they are ignored with a warning.
+
+use std::{cmp::Ordering, collections::BTreeSet, rc::Rc, str::FromStr};
+
+use ahash::HashMap;
+use chrono::{Datelike, NaiveDate};
+use edn_rs::Edn;
+use eyre::{eyre, Result};
+use itertools::Itertools;
+
+use super::{
+    block_page_references, block_path_references, journal_date, normalize_value, parse_date,
+    simple::SimpleQuery, sort_by_page, task_marker, PageLookup, PageReference,
+};
+use crate::graph::{Block, Graph};
+
+/// A value produced by a query.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Value {
+    /// A block or page, by ID
+    Entity(usize),
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    Key(String),
+    Set(BTreeSet<Value>),
+    /// The properties of the block with this ID
+    Properties(usize),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Term {
+    Var(String),
+    Any,
+    Const(Value),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Attr {
+    Name,
+    OriginalName,
+    Content,
+    Marker,
+    Page,
+    Parent,
+    Refs,
+    PathRefs,
+    Tags,
+    Properties,
+    Uuid,
+    Journal,
+    JournalDay,
+}
+
+impl Attr {
+    fn from_key(key: &str) -> Option<Attr> {
+        let attr = match key {
+            ":block/name" => Attr::Name,
+            ":block/original-name" => Attr::OriginalName,
+            ":block/content" => Attr::Content,
+            ":block/marker" => Attr::Marker,
+            ":block/page" => Attr::Page,
+            ":block/parent" => Attr::Parent,
+            ":block/refs" => Attr::Refs,
+            ":block/path-refs" => Attr::PathRefs,
+            ":block/tags" => Attr::Tags,
+            ":block/properties" => Attr::Properties,
+            ":block/uuid" => Attr::Uuid,
+            ":block/journal?" => Attr::Journal,
+            ":block/journal-day" => Attr::JournalDay,
+            _ => return None,
+        };
+
+        Some(attr)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Predicate {
+    Eq,
+    NotEq,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+    Contains,
+    Includes,
+    StartsWith,
+    EndsWith,
+}
+
+impl Predicate {
+    fn from_name(name: &str) -> Option<Predicate> {
+        let name = name
+            .strip_prefix("clojure.string/")
+            .or_else(|| name.strip_prefix("string/"))
+            .unwrap_or(name);
+
+        let predicate = match name {
+            "=" => Predicate::Eq,
+            "not=" => Predicate::NotEq,
+            "<" => Predicate::Lt,
+            "<=" => Predicate::Lte,
+            ">" => Predicate::Gt,
+            ">=" => Predicate::Gte,
+            "contains?" => Predicate::Contains,
+            "includes?" => Predicate::Includes,
+            "starts-with?" => Predicate::StartsWith,
+            "ends-with?" => Predicate::EndsWith,
+            _ => return None,
+        };
+
+        Some(predicate)
+    }
+
+    fn test(&self, a: &Value, b: &Value) -> bool {
+        let ordering = match (a, b) {
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+            _ => None,
+        };
+
+        match (self, a, b) {
+            (Predicate::Eq, _, _) => a == b,
+            (Predicate::NotEq, _, _) => a != b,
+            (Predicate::Lt, _, _) => ordering == Some(Ordering::Less),
+            (Predicate::Lte, _, _) => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            (Predicate::Gt, _, _) => ordering == Some(Ordering::Greater),
+            (Predicate::Gte, _, _) => {
+                matches!(ordering, Some(Ordering::Greater | Ordering::Equal))
+            }
+            (Predicate::Contains, Value::Set(values), _) => values.contains(b),
+            // Single property values are not sets, but should still work with `contains?`.
+            (Predicate::Contains, _, _) => a == b,
+            (Predicate::Includes, Value::Str(a), Value::Str(b)) => a.contains(b.as_str()),
+            (Predicate::StartsWith, Value::Str(a), Value::Str(b)) => a.starts_with(b.as_str()),
+            (Predicate::EndsWith, Value::Str(a), Value::Str(b)) => a.ends_with(b.as_str()),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Clause {
+    Pattern {
+        entity: Term,
+        attr: Attr,
+        value: Term,
+    },
+    Predicate {
+        predicate: Predicate,
+        args: Vec<Term>,
+    },
+    /// `[(get ?map :key default) ?output]`
+    Get {
+        map: Term,
+        key: String,
+        default: Option<Value>,
+        output: Term,
+    },
+    /// One of Logseq's built-in rules, which match the same way as the simple query filters.
+    Rule {
+        entity: Term,
+        pages: bool,
+        filter: SimpleQuery,
+    },
+    /// `not`, or `not-join` when `vars` is set
+    Not {
+        vars: Option<Vec<String>>,
+        clauses: Vec<Clause>,
+    },
+    /// `or`, or `or-join` when `vars` is set
+    Or {
+        vars: Option<Vec<String>>,
+        branches: Vec<Vec<Clause>>,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum FindElement {
+    Var(String),
+    Pull(String),
+}
+
+impl FindElement {
+    fn var(&self) -> &str {
+        match self {
+            FindElement::Var(var) | FindElement::Pull(var) => var,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum QueryBody {
+    Simple(SimpleQuery),
+    Datalog {
+        find: Vec<FindElement>,
+        inputs: Vec<(String, Value)>,
+        clauses: Vec<Clause>,
+    },
+}
+
+#[derive(Debug)]
+pub struct AdvancedQuery {
+    pub title: Option<String>,
+    /// Options in the query which can't be evaluated, and so were ignored
+    pub ignored_options: Vec<String>,
+    body: QueryBody,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum QueryResults {
+    /// Blocks or pages, sorted by page title and then by position in the page.
+    Entities(Vec<usize>),
+    Table {
+        columns: Vec<String>,
+        rows: Vec<Vec<Value>>,
+    },
+}
+
+/// Format EDN as it would appear in the query, for use in warnings.
+fn edn_text(edn: &Edn) -> String {
+    match edn {
+        Edn::Vector(_) => format!("[{}]", edn.iter_some().unwrap().map(edn_text).join(" ")),
+        Edn::List(_) => format!("({})", edn.iter_some().unwrap().map(edn_text).join(" ")),
+        Edn::Set(_) => format!("#{{{}}}", edn.set_iter().unwrap().map(edn_text).join(" ")),
+        Edn::Map(_) => format!(
+            "{{{}}}",
+            edn.map_iter()
+                .unwrap()
+                .map(|(key, value)| format!("{key} {}", edn_text(value)))
+                .join(" ")
+        ),
+        _ => edn.to_string(),
+    }
+}
+
+fn unsupported(what: &str, edn: &Edn) -> eyre::Report {
+    eyre!("Unsupported {what} `{}`", edn_text(edn))
+}
+
+fn constant(edn: &Edn) -> Option<Value> {
+    let value = match edn {
+        Edn::Str(s) => Value::Str(s.clone()),
+        Edn::Int(i) => Value::Int(*i as i64),
+        Edn::UInt(u) => Value::Int(*u as i64),
+        Edn::Bool(b) => Value::Bool(*b),
+        Edn::Key(k) => Value::Key(k.trim_start_matches(':').to_string()),
+        Edn::Set(_) => Value::Set(edn.set_iter()?.map(constant).collect::<Option<_>>()?),
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+fn term(edn: &Edn) -> Result<Term> {
+    match edn {
+        Edn::Symbol(s) if s == "_" => Ok(Term::Any),
+        Edn::Symbol(s) if s.starts_with('?') => Ok(Term::Var(s.clone())),
+        _ => constant(edn)
+            .map(Term::Const)
+            .ok_or_else(|| unsupported("value", edn)),
+    }
+}
+
+fn variable(edn: &Edn) -> Result<String> {
+    match edn {
+        Edn::Symbol(s) if s.starts_with('?') => Ok(s.clone()),
+        _ => Err(unsupported("variable", edn)),
+    }
+}
+
+/// The journal day format used by Logseq, like `20230301`
+fn journal_day(date: NaiveDate) -> i64 {
+    date.year() as i64 * 10000 + date.month() as i64 * 100 + date.day() as i64
+}
+
+fn pattern_clause(whole: &Edn, items: &[&Edn]) -> Result<Clause> {
+    let (entity, attr, value) = match items {
+        [entity, Edn::Key(attr)] => (entity, attr, Term::Any),
+        [entity, Edn::Key(attr), value] => (entity, attr, term(value)?),
+        _ => return Err(unsupported("clause", whole)),
+    };
+
+    let entity = match term(entity)? {
+        Term::Const(_) => return Err(unsupported("clause", whole)),
+        entity => entity,
+    };
+
+    let attr = Attr::from_key(attr).ok_or_else(|| eyre!("Unsupported attribute `{attr}`"))?;
+    Ok(Clause::Pattern {
+        entity,
+        attr,
+        value,
+    })
+}
+
+/// Parse a function call clause, which is either a predicate like `[(= ?a ?b)]` or a binding like
+/// `[(get ?props :type) ?type]`.
+fn function_clause(whole: &Edn, call: &Edn, outputs: &[&Edn]) -> Result<Clause> {
+    let mut call_items = call.iter_some().unwrap();
+    let Some(Edn::Symbol(name)) = call_items.next() else {
+        return Err(unsupported("clause", whole));
+    };
+    let args = call_items.map(term).collect::<Result<Vec<_>>>()?;
+
+    match (name.as_str(), outputs) {
+        ("get", [output]) if args.len() == 2 || args.len() == 3 => {
+            let Term::Const(Value::Key(key)) = &args[1] else {
+                return Err(unsupported("clause", whole));
+            };
+
+            let default = match args.get(2) {
+                Some(Term::Const(value)) => Some(value.clone()),
+                Some(_) => return Err(unsupported("clause", whole)),
+                None => None,
+            };
+
+            Ok(Clause::Get {
+                map: args[0].clone(),
+                key: key.to_lowercase(),
+                default,
+                output: term(output)?,
+            })
+        }
+        (name, []) if args.len() == 2 => Predicate::from_name(name)
+            .map(|predicate| Clause::Predicate { predicate, args })
+            .ok_or_else(|| unsupported("clause", whole)),
+        _ => Err(unsupported("clause", whole)),
+    }
+}
+
+/// Parse one of Logseq's built-in rules, like `(task ?b #{"TODO" "DOING"})`.
+fn rule_clause(whole: &Edn, name: &str, args: &[&Edn]) -> Result<Clause> {
+    let Some((entity, args)) = args.split_first() else {
+        return Err(unsupported("clause", whole));
+    };
+
+    let entity = match term(entity)? {
+        Term::Const(_) => return Err(unsupported("clause", whole)),
+        entity => entity,
+    };
+
+    let mut values = args
+        .iter()
+        .flat_map(|arg| match arg {
+            Edn::Set(_) => arg.set_iter().unwrap().collect::<Vec<_>>(),
+            _ => vec![*arg],
+        })
+        .map(|arg| match arg {
+            Edn::Str(s) => Ok(normalize_value(s)),
+            Edn::Key(k) => Ok(normalize_value(k.trim_start_matches(':'))),
+            _ => Err(unsupported("clause", whole)),
+        })
+        .collect::<Result<Vec<_>>>()?
+        .into_iter();
+
+    let (filter, pages) = match name {
+        "task" => (
+            SimpleQuery::Task(values.map(|v| v.to_uppercase()).collect()),
+            false,
+        ),
+        "page-ref" => (
+            SimpleQuery::Or(values.map(SimpleQuery::PageRef).collect()),
+            false,
+        ),
+        "page-tags" => (SimpleQuery::PageTags(values.collect()), true),
+        _ => {
+            let property = values.next().ok_or_else(|| unsupported("clause", whole))?;
+            (
+                SimpleQuery::Property {
+                    name: property,
+                    value: values.next(),
+                },
+                name == "page-property",
+            )
+        }
+    };
+
+    Ok(Clause::Rule {
+        entity,
+        pages,
+        filter,
+    })
+}
+
+/// Parse the branch of an `or`, which is either a single clause or several clauses in an `and`.
+fn or_branch(edn: &Edn) -> Result<Vec<Clause>> {
+    match edn {
+        Edn::List(_) => {
+            let mut items = edn.iter_some().unwrap();
+            match items.next() {
+                Some(Edn::Symbol(name)) if name == "and" => items.map(clause).collect(),
+                _ => Ok(vec![clause(edn)?]),
+            }
+        }
+        _ => Ok(vec![clause(edn)?]),
+    }
+}
+
+fn join_vars(edn: Option<&Edn>, whole: &Edn) -> Result<Vec<String>> {
+    edn.and_then(|vars| vars.iter_some())
+        .ok_or_else(|| unsupported("clause", whole))?
+        .map(variable)
+        .collect()
+}
+
+fn clause(edn: &Edn) -> Result<Clause> {
+    match edn {
+        Edn::Vector(_) => {
+            let items = edn.iter_some().unwrap().collect::<Vec<_>>();
+            // Skip the data source, since there is only the one.
+            let items = match items.first() {
+                Some(Edn::Symbol(source)) if source == "$" => &items[1..],
+                _ => &items[..],
+            };
+
+            match items {
+                [call @ Edn::List(_), outputs @ ..] => function_clause(edn, call, outputs),
+                _ => pattern_clause(edn, items),
+            }
+        }
+        Edn::List(_) => {
+            let items = edn.iter_some().unwrap().collect::<Vec<_>>();
+            let Some((Edn::Symbol(name), args)) = items.split_first() else {
+                return Err(unsupported("clause", edn));
+            };
+
+            match name.as_str() {
+                "not" => Ok(Clause::Not {
+                    vars: None,
+                    clauses: args.iter().map(|c| clause(c)).collect::<Result<_>>()?,
+                }),
+                "not-join" => Ok(Clause::Not {
+                    vars: Some(join_vars(args.first().copied(), edn)?),
+                    clauses: args
+                        .iter()
+                        .skip(1)
+                        .map(|c| clause(c))
+                        .collect::<Result<_>>()?,
+                }),
+                "or" => Ok(Clause::Or {
+                    vars: None,
+                    branches: args.iter().map(|c| or_branch(c)).collect::<Result<_>>()?,
+                }),
+                "or-join" => Ok(Clause::Or {
+                    vars: Some(join_vars(args.first().copied(), edn)?),
+                    branches: args
+                        .iter()
+                        .skip(1)
+                        .map(|c| or_branch(c))
+                        .collect::<Result<_>>()?,
+                }),
+                "task" | "page-ref" | "page-tags" | "property" | "page-property" => {
+                    rule_clause(edn, name, args)
+                }
+                _ => Err(unsupported("clause", edn)),
+            }
+        }
+        _ => Err(unsupported("clause", edn)),
+    }
+}
+
+fn find_element(edn: &Edn) -> Result<FindElement> {
+    match edn {
+        Edn::Symbol(_) => variable(edn).map(FindElement::Var),
+        Edn::List(_) => {
+            let items = edn.iter_some().unwrap().collect::<Vec<_>>();
+            match items.as_slice() {
+                [Edn::Symbol(pull), var, _] if pull == "pull" => {
+                    variable(var).map(FindElement::Pull)
+                }
+                _ => Err(unsupported("find", edn)),
+            }
+        }
+        _ => Err(unsupported("find", edn)),
+    }
+}
+
+fn input_value(edn: &Edn, today: NaiveDate, current_page: &str) -> Result<Value> {
+    match edn {
+        Edn::Key(key) => match key.trim_start_matches(':') {
+            "current-page" | "query-page" => Ok(Value::Str(current_page.to_lowercase())),
+            name => parse_date(name, today)
+                .map(|date| Value::Int(journal_day(date)))
+                .map_err(|_| unsupported("input", edn)),
+        },
+        _ => constant(edn).ok_or_else(|| unsupported("input", edn)),
+    }
+}
+
+/// Collect the text from a title, which may be a string or Hiccup like `[:h2 "Title"]`.
+fn title_text(edn: &Edn) -> String {
+    match edn {
+        Edn::Str(s) => s.clone(),
+        Edn::Vector(_) | Edn::List(_) => edn
+            .iter_some()
+            .unwrap()
+            .map(title_text)
+            .filter(|s| !s.is_empty())
+            .join(" "),
+        _ => String::new(),
+    }
+}
+
+impl AdvancedQuery {
+    /// Parse the EDN contents of a query. `today` and `current_page` are used to resolve the
+    /// query's inputs.
+    pub fn parse(input: &str, today: NaiveDate, current_page: &str) -> Result<AdvancedQuery> {
+        let edn = Edn::from_str(input.trim()).map_err(|e| eyre!("Could not parse query: {e:?}"))?;
+        if !matches!(edn, Edn::Map(_)) {
+            return Err(unsupported("query", &edn));
+        }
+
+        let title = edn
+            .get(":title")
+            .map(title_text)
+            .filter(|title| !title.is_empty());
+
+        let ignored_options = [":view", ":result-transform"]
+            .into_iter()
+            .filter(|option| edn.get(*option).is_some())
+            .map(String::from)
+            .collect();
+
+        let body = match edn.get(":query") {
+            Some(Edn::Str(query)) => QueryBody::Simple(SimpleQuery::parse(query, today)?),
+            Some(query) => {
+                let inputs = edn
+                    .get(":inputs")
+                    .and_then(|inputs| inputs.iter_some())
+                    .into_iter()
+                    .flatten()
+                    .map(|input| input_value(input, today, current_page))
+                    .collect::<Result<Vec<_>>>()?;
+                Self::parse_datalog(query, inputs)?
+            }
+            None => return Err(eyre!("Query has no :query")),
+        };
+
+        Ok(AdvancedQuery {
+            title,
+            ignored_options,
+            body,
+        })
+    }
+
+    fn parse_datalog(query: &Edn, inputs: Vec<Value>) -> Result<QueryBody> {
+        // Queries can be either a vector like `[:find ?b :where ...]` or a map of vectors.
+        let mut sections: Vec<(&str, Vec<&Edn>)> = Vec::new();
+        match query {
+            Edn::Vector(_) => {
+                for item in query.iter_some().unwrap() {
+                    match (item, sections.last_mut()) {
+                        (Edn::Key(key), _) => sections.push((key.as_str(), Vec::new())),
+                        (_, Some((_, items))) => items.push(item),
+                        (_, None) => return Err(unsupported("query", query)),
+                    }
+                }
+            }
+            Edn::Map(_) => {
+                for (key, value) in query.map_iter().unwrap() {
+                    let items = value
+                        .iter_some()
+                        .ok_or_else(|| unsupported("query", query))?
+                        .collect();
+                    sections.push((key.as_str(), items));
+                }
+            }
+            _ => return Err(unsupported("query", query)),
+        }
+
+        let mut find = Vec::new();
+        let mut in_vars = Vec::new();
+        let mut clauses = Vec::new();
+        for (key, items) in sections {
+            match key {
+                ":find" => find = items.into_iter().map(find_element).collect::<Result<_>>()?,
+                ":in" => {
+                    in_vars = items
+                        .into_iter()
+                        .filter(|item| !matches!(item, Edn::Symbol(s) if s == "$"))
+                        .map(variable)
+                        .collect::<Result<_>>()?
+                }
+                ":where" => clauses = items.into_iter().map(clause).collect::<Result<_>>()?,
+                _ => return Err(eyre!("Unsupported query section `{key}`")),
+            }
+        }
+
+        if find.is_empty() {
+            return Err(eyre!("Query has no :find"));
+        }
+
+        if in_vars.len() != inputs.len() {
+            return Err(eyre!(
+                "Query has {} :in variables but {} :inputs",
+                in_vars.len(),
+                inputs.len()
+            ));
+        }
+
+        Ok(QueryBody::Datalog {
+            find,
+            inputs: in_vars.into_iter().zip(inputs).collect(),
+            clauses,
+        })
+    }
+
+    /// Run the query against the graph. `lookup_page` resolves the titles of referenced pages.
+    pub fn run(&self, graph: &Graph, lookup_page: &PageLookup) -> Result<QueryResults> {
+        let (find, inputs, clauses) = match &self.body {
+            QueryBody::Simple(query) => {
+                return Ok(QueryResults::Entities(query.run(graph, lookup_page)))
+            }
+            QueryBody::Datalog {
+                find,
+                inputs,
+                clauses,
+            } => (find, inputs, clauses),
+        };
+
+        let mut evaluator = Evaluator::new(graph, lookup_page);
+        let bindings = evaluator.eval(clauses, vec![inputs.iter().cloned().collect()])?;
+
+        let rows = bindings
+            .iter()
+            .map(|binding| {
+                find.iter()
+                    .map(|element| {
+                        binding
+                            .get(element.var())
+                            .cloned()
+                            .ok_or_else(|| eyre!("{} is not bound by the query", element.var()))
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<BTreeSet<_>>>()?;
+
+        if let [FindElement::Pull(_)] = find.as_slice() {
+            let ids = rows.into_iter().filter_map(|row| match row[0] {
+                Value::Entity(id) => Some(id),
+                _ => None,
+            });
+            return Ok(QueryResults::Entities(sort_by_page(graph, ids)));
+        }
+
+        Ok(QueryResults::Table {
+            columns: find
+                .iter()
+                .map(|element| element.var().trim_start_matches('?').to_string())
+                .collect(),
+            rows: rows.into_iter().collect(),
+        })
+    }
+}
+
+type Binding = HashMap<String, Value>;
+
+/// All the values of an attribute in the graph, indexed by value.
+#[derive(Default)]
+struct Relation {
+    rows: Vec<(usize, Value)>,
+    by_value: HashMap<Value, Vec<usize>>,
+}
+
+struct Evaluator<'g> {
+    graph: &'g Graph,
+    lookup_page: &'g PageLookup<'g>,
+    relations: HashMap<Attr, Rc<Relation>>,
+}
+
+/// Get the value of a term if it is a constant or a bound variable.
+fn resolve(term: &Term, binding: &Binding) -> Option<Value> {
+    match term {
+        Term::Const(value) => Some(value.clone()),
+        Term::Var(var) => binding.get(var).cloned(),
+        Term::Any => None,
+    }
+}
+
+/// Get the value of a term which must already be bound.
+fn bound_value(term: &Term, binding: &Binding) -> Result<Value> {
+    match term {
+        Term::Const(value) => Ok(value.clone()),
+        Term::Var(var) => binding
+            .get(var)
+            .cloned()
+            .ok_or_else(|| eyre!("{var} is used before it is bound")),
+        Term::Any => Err(eyre!("_ can not be used as a function argument")),
+    }
+}
+
+/// Match a term against a value, returning the updated bindings if they are compatible.
+fn unify(term: &Term, value: Value, binding: &Binding) -> Option<Binding> {
+    match term {
+        Term::Any => Some(binding.clone()),
+        Term::Const(c) => (*c == value).then(|| binding.clone()),
+        Term::Var(var) => match binding.get(var) {
+            Some(existing) => (*existing == value).then(|| binding.clone()),
+            None => {
+                let mut binding = binding.clone();
+                binding.insert(var.clone(), value);
+                Some(binding)
+            }
+        },
+    }
+}
+
+/// Limit a binding to the given variables, for `not-join` and `or-join`.
+fn restrict(binding: &Binding, vars: Option<&[String]>) -> Binding {
+    match vars {
+        Some(vars) => binding
+            .iter()
+            .filter(|(var, _)| vars.contains(var))
+            .map(|(var, value)| (var.clone(), value.clone()))
+            .collect(),
+        None => binding.clone(),
+    }
+}
+
+/// Look up a property value, as a string or as a set of strings if there are several.
+fn property_value(block: &Block, key: &str) -> Option<Value> {
+    let (_, values) = block
+        .attrs
+        .iter()
+        .find(|(name, _)| name.to_lowercase() == key)?;
+
+    let mut values = values
+        .iter()
+        .flat_map(|v| v.split(','))
+        .map(|v| {
+            v.trim()
+                .trim_start_matches('#')
+                .trim_start_matches("[[")
+                .trim_end_matches("]]")
+                .trim()
+        })
+        .filter(|v| !v.is_empty())
+        .map(|v| Value::Str(v.to_string()))
+        .collect::<BTreeSet<_>>();
+
+    if values.len() == 1 {
+        values.pop_first()
+    } else {
+        Some(Value::Set(values))
+    }
+}
+
+impl<'g> Evaluator<'g> {
+    fn new(graph: &'g Graph, lookup_page: &'g PageLookup<'g>) -> Evaluator<'g> {
+        Evaluator {
+            graph,
+            lookup_page,
+            relations: HashMap::default(),
+        }
+    }
+
+    fn page_entities(&self, references: Vec<PageReference>) -> Vec<Value> {
+        references
+            .into_iter()
+            .filter_map(|r| match r {
+                PageReference::Page(id) => Some(id),
+                PageReference::Title(_) => None,
+            })
+            .unique()
+            .map(Value::Entity)
+            .collect()
+    }
+
+    fn attr_values(&self, block: &Block, attr: Attr) -> Vec<Value> {
+        let is_page = block.page_title.is_some();
+        match attr {
+            Attr::Name => block
+                .page_title
+                .iter()
+                .map(|title| Value::Str(title.to_lowercase()))
+                .collect(),
+            Attr::OriginalName => block
+                .page_title
+                .iter()
+                .map(|title| Value::Str(title.clone()))
+                .collect(),
+            Attr::Content if !is_page => {
+                vec![Value::Str(block.contents.borrow_string().to_string())]
+            }
+            Attr::Marker => task_marker(block)
+                .map(|marker| Value::Str(marker.to_string()))
+                .into_iter()
+                .collect(),
+            Attr::Page if !is_page => vec![Value::Entity(block.containing_page)],
+            Attr::Parent => block.parent.map(Value::Entity).into_iter().collect(),
+            Attr::Refs => self.page_entities(block_page_references(block, self.lookup_page)),
+            Attr::PathRefs => {
+                self.page_entities(block_path_references(self.graph, block, self.lookup_page))
+            }
+            Attr::Tags => self.page_entities(
+                block
+                    .tags
+                    .iter()
+                    .map(|tag| PageReference::resolve(tag, self.lookup_page))
+                    .collect(),
+            ),
+            Attr::Properties if !block.attrs.is_empty() => vec![Value::Properties(block.id)],
+            Attr::Uuid if !block.uid.is_empty() => vec![Value::Str(block.uid.clone())],
+            Attr::Journal if is_page => vec![Value::Bool(block.is_journal)],
+            Attr::JournalDay if is_page => journal_date(self.graph, block)
+                .map(|date| Value::Int(journal_day(date)))
+                .into_iter()
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn relation(&mut self, attr: Attr) -> Rc<Relation> {
+        if let Some(relation) = self.relations.get(&attr) {
+            return relation.clone();
+        }
+
+        let mut relation = Relation::default();
+        for block in self.graph.blocks.values() {
+            for value in self.attr_values(block, attr) {
+                relation
+                    .by_value
+                    .entry(value.clone())
+                    .or_default()
+                    .push(block.id);
+                relation.rows.push((block.id, value));
+            }
+        }
+
+        let relation = Rc::new(relation);
+        self.relations.insert(attr, relation.clone());
+        relation
+    }
+
+    fn eval(&mut self, clauses: &[Clause], bindings: Vec<Binding>) -> Result<Vec<Binding>> {
+        clauses.iter().try_fold(bindings, |bindings, clause| {
+            self.eval_clause(clause, bindings)
+        })
+    }
+
+    fn eval_clause(&mut self, clause: &Clause, bindings: Vec<Binding>) -> Result<Vec<Binding>> {
+        let mut output = Vec::new();
+        match clause {
+            Clause::Pattern {
+                entity,
+                attr,
+                value,
+            } => {
+                for binding in bindings {
+                    match resolve(entity, &binding) {
+                        Some(Value::Entity(id)) => {
+                            let Some(block) = self.graph.blocks.get(&id) else {
+                                continue;
+                            };
+
+                            for v in self.attr_values(block, *attr) {
+                                output.extend(unify(value, v, &binding));
+                            }
+                        }
+                        Some(_) => {}
+                        None => {
+                            let relation = self.relation(*attr);
+                            if let Some(v) = resolve(value, &binding) {
+                                for id in relation.by_value.get(&v).into_iter().flatten() {
+                                    output.extend(unify(entity, Value::Entity(*id), &binding));
+                                }
+                            } else {
+                                for (id, v) in &relation.rows {
+                                    if let Some(b) = unify(value, v.clone(), &binding) {
+                                        output.extend(unify(entity, Value::Entity(*id), &b));
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            Clause::Predicate { predicate, args } => {
+                for binding in bindings {
+                    let a = bound_value(&args[0], &binding)?;
+                    let b = bound_value(&args[1], &binding)?;
+                    if predicate.test(&a, &b) {
+                        output.push(binding);
+                    }
+                }
+            }
+            Clause::Get {
+                map,
+                key,
+                default,
+                output: output_term,
+            } => {
+                for binding in bindings {
+                    let Value::Properties(id) = bound_value(map, &binding)? else {
+                        continue;
+                    };
+
+                    let value = self
+                        .graph
+                        .blocks
+                        .get(&id)
+                        .and_then(|block| property_value(block, key))
+                        .or_else(|| default.clone());
+                    if let Some(value) = value {
+                        output.extend(unify(output_term, value, &binding));
+                    }
+                }
+            }
+            Clause::Rule {
+                entity,
+                pages,
+                filter,
+            } => {
+                let matches = |block: &Block| {
+                    block.page_title.is_some() == *pages
+                        && filter.matches(
+                            self.graph,
+                            block,
+                            &block_path_references(self.graph, block, self.lookup_page),
+                            self.lookup_page,
+                        )
+                };
+
+                for binding in bindings {
+                    match resolve(entity, &binding) {
+                        Some(Value::Entity(id)) => {
+                            if self.graph.blocks.get(&id).map(matches).unwrap_or(false) {
+                                output.push(binding);
+                            }
+                        }
+                        Some(_) => {}
+                        None => {
+                            for block in self.graph.blocks.values().filter(|b| matches(b)) {
+                                output.extend(unify(entity, Value::Entity(block.id), &binding));
+                            }
+                        }
+                    }
+                }
+            }
+            Clause::Not { vars, clauses } => {
+                for binding in bindings {
+                    let start = restrict(&binding, vars.as_deref());
+                    if self.eval(clauses, vec![start])?.is_empty() {
+                        output.push(binding);
+                    }
+                }
+            }
+            Clause::Or { vars, branches } => {
+                for binding in bindings {
+                    let start = restrict(&binding, vars.as_deref());
+                    for branch in branches {
+                        for result in self.eval(branch, vec![start.clone()])? {
+                            let Some(vars) = vars else {
+                                output.push(result);
+                                continue;
+                            };
+
+                            // Only the join variables are carried over from the branch.
+                            let merged = vars.iter().try_fold(binding.clone(), |merged, var| {
+                                match result.get(var) {
+                                    Some(value) => {
+                                        unify(&Term::Var(var.clone()), value.clone(), &merged)
+                                    }
+                                    None => Some(merged),
+                                }
+                            });
+                            output.extend(merged);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(id: usize, page: usize, parent: usize, contents: &str) -> Block {
+        Block {
+            uid: format!("uid-{id}"),
+            parent: Some(parent),
+            ..Block::test(id, page, contents)
+        }
+    }
+
+    fn page(id: usize, title: &str, is_journal: bool) -> Block {
+        let mut page = block(id, id, id, "");
+        page.parent = None;
+        page.page_title = Some(title.to_string());
+        page.is_journal = is_journal;
+        page
+    }
+
+    fn test_graph() -> Graph {
+        let mut graph = Graph::new(false);
+        let mut books = page(0, "Books", false);
+        books.tags = smallvec::smallvec![String::from("reading")];
+        graph.add_block(books);
+        graph.add_block(page(1, "Report", false));
+
+        let mut book = block(2, 0, 0, "type:: book\nrating:: 4\nThe Rust Book");
+        book.attrs.insert(
+            String::from("type"),
+            smallvec::smallvec![String::from("book")],
+        );
+        book.attrs.insert(
+            String::from("rating"),
+            smallvec::smallvec![String::from("4")],
+        );
+        graph.add_block(book);
+        graph.add_block(block(3, 0, 2, "TODO read chapter 2 of the [[Report]]"));
+        graph.add_block(block(4, 0, 0, "DONE finish [[Report]]"));
+
+        graph.add_block(page(10, "2023-03-02", true));
+        graph.add_block(block(11, 10, 10, "LATER call about it"));
+        graph.add_block(page(20, "2023-02-01", true));
+        graph.add_block(block(21, 20, 20, "TODO old task"));
+        graph
+    }
+
+    fn run(query: &str) -> Result<QueryResults> {
+        let today = NaiveDate::from_ymd_opt(2023, 3, 10).unwrap();
+        let graph = test_graph();
+        let lookup_page = super::super::test_page_lookup(&graph);
+        AdvancedQuery::parse(query, today, "Books")?.run(&graph, &lookup_page)
+    }
+
+    fn entities(query: &str) -> Vec<usize> {
+        match run(query).unwrap() {
+            QueryResults::Entities(ids) => ids,
+            results => panic!("Expected entities, got {results:?}"),
+        }
+    }
+
+    #[test]
+    fn patterns_and_predicates() {
+        assert_eq!(
+            entities(
+                r##"{:title "Tasks"
+                     :query [:find (pull ?b [*])
+                             :where [?b :block/marker ?m]
+                                    [(contains? #{"TODO" "LATER"} ?m)]]}"##
+            ),
+            vec![21, 11, 3]
+        );
+
+        assert_eq!(
+            entities(
+                r##"{:query [:find (pull ?b [*])
+                             :where [?p :block/name "report"]
+                                    [?b :block/refs ?p]
+                                    (not [?b :block/marker "DONE"])]}"##
+            ),
+            vec![3]
+        );
+
+        assert_eq!(
+            entities(
+                r##"{:query [:find (pull ?b [*])
+                             :where [?b :block/path-refs ?p]
+                                    [?p :block/name "books"]
+                                    [?b :block/content ?c]
+                                    [(clojure.string/includes? ?c "chapter")]]}"##
+            ),
+            vec![3]
+        );
+    }
+
+    #[test]
+    fn properties_and_rules() {
+        assert_eq!(
+            entities(
+                r##"{:query [:find (pull ?b [*])
+                             :where [?b :block/properties ?props]
+                                    [(get ?props :type) ?t]
+                                    [(= ?t "book")]]}"##
+            ),
+            vec![2]
+        );
+
+        assert_eq!(
+            entities(
+                r##"{:query [:find (pull ?b [*])
+                             :where (or (task ?b #{"LATER"})
+                                        (and (page-ref ?b "report") (task ?b #{"DONE"})))]}"##
+            ),
+            vec![11, 4]
+        );
+
+        assert_eq!(
+            entities(r##"{:query [:find (pull ?p [*]) :where (page-tags ?p #{"reading"})]}"##),
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn inputs_and_tables() {
+        assert_eq!(
+            entities(
+                r##"{:query [:find (pull ?b [*])
+                             :in $ ?start ?today
+                             :where [?b :block/page ?p]
+                                    [?p :block/journal-day ?d]
+                                    [(>= ?d ?start)]
+                                    [(<= ?d ?today)]]
+                     :inputs [:-14d :today]}"##
+            ),
+            vec![11]
+        );
+
+        assert_eq!(
+            run(r##"{:query [:find ?name ?m
+                             :in $ ?current
+                             :where [?b :block/marker ?m]
+                                    [?b :block/page ?p]
+                                    [?p :block/name ?name]
+                                    [(= ?name ?current)]]
+                     :inputs [:current-page]}"##)
+            .unwrap(),
+            QueryResults::Table {
+                columns: vec![String::from("name"), String::from("m")],
+                rows: vec![
+                    vec![
+                        Value::Str(String::from("books")),
+                        Value::Str(String::from("DONE"))
+                    ],
+                    vec![
+                        Value::Str(String::from("books")),
+                        Value::Str(String::from("TODO"))
+                    ],
+                ],
+            }
+        );
+
+        assert_eq!(
+            entities(r##"{:query "(and [[Report]] (task TODO))"}"##),
+            vec![3]
+        );
+    }
+
+    #[test]
+    fn unsupported_clauses() {
+        let error = run(r##"{:query [:find ?b :where [?b :block/marker ?m] [(odd? ?m)]]}"##)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "Unsupported clause `[(odd? ?m)]`");
+
+        let error = run(r##"{:query [:find ?b :where [?b :block/created-at ?t]]}"##)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "Unsupported attribute `:block/created-at`");
+
+        let error = run(r##"{:query [:find (count ?b) :where [?b :block/marker]]}"##)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "Unsupported find `(count ?b)`");
+
+        let today = NaiveDate::from_ymd_opt(2023, 3, 10).unwrap();
+        let query = AdvancedQuery::parse(
+            r##"{:query [:find ?b :where [?b :block/marker]] :view (fn [r] [:div r])}"##,
+            today,
+            "Books",
+        )
+        .unwrap();
+        assert_eq!(query.ignored_options, vec![String::from(":view")]);
+    }
+}
@@ -0,0 +1,225 @@
+pub mod advanced;
+pub mod simple;
+
+use chrono::{Duration, Months, NaiveDate};
+use eyre::{eyre, Result};
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::{
+    graph::{Block, Graph},
+    page::normalize_title,
+    references::{collect_references, Reference},
+};
+
+use self::{
+    advanced::{AdvancedQuery, QueryResults},
+    simple::SimpleQuery,
+};
+
+/// Finds the page with a title, following aliases and normalizing the title the same way that
+/// links are resolved, and returns the ID of its root block.
+pub type PageLookup<'a> = dyn Fn(&str) -> Option<usize> + 'a;
+
+/// A page that a block refers to. A reference to a title without a page is compared by the
+/// normalized title instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PageReference {
+    Page(usize),
+    Title(String),
+}
+
+impl PageReference {
+    pub fn resolve(title: &str, lookup_page: &PageLookup) -> PageReference {
+        lookup_page(title)
+            .map(PageReference::Page)
+            .unwrap_or_else(|| PageReference::Title(normalize_title(title)))
+    }
+}
+
+/// The pages that a block refers to, through links, hashtags, and tags.
+pub fn block_page_references(block: &Block, lookup_page: &PageLookup) -> Vec<PageReference> {
+    let mut references = Vec::new();
+    collect_references(block.contents.borrow_parsed(), &mut references);
+
+    references
+        .into_iter()
+        .filter_map(|r| match r {
+            Reference::Page(title) => Some(title),
+            Reference::Block(_) => None,
+        })
+        .chain(block.tags.iter().map(|tag| tag.as_str()))
+        .map(|title| PageReference::resolve(title, lookup_page))
+        .collect()
+}
+
+/// The pages that a block or any of its parents refer to, plus the page that contains it, like
+/// Logseq's `:block/path-refs`.
+pub fn block_path_references(
+    graph: &Graph,
+    block: &Block,
+    lookup_page: &PageLookup,
+) -> Vec<PageReference> {
+    let mut references = Vec::new();
+    let mut current = Some(block);
+    while let Some(b) = current {
+        references.extend(block_page_references(b, lookup_page));
+        current = b.parent.and_then(|parent| graph.blocks.get(&parent));
+    }
+
+    let page = PageReference::Page(block.containing_page);
+    if !references.contains(&page) {
+        references.push(page);
+    }
+    references
+}
+
+/// A query in a page, from a `{{query}}` directive or an advanced query block.
+#[derive(Clone, Copy, Debug)]
+pub enum PageQuery<'a> {
+    Simple(&'a str),
+    Advanced(&'a str),
+}
+
+/// Parse and run a query from the page with the given title, returning the query's title and its
+/// results. Queries that can't be run are skipped with a warning, and so are unsupported options.
+pub fn evaluate_query(
+    graph: &Graph,
+    query: PageQuery,
+    page_title: &str,
+    lookup_page: &PageLookup,
+) -> Option<(Option<String>, QueryResults)> {
+    let today = chrono::Local::now().date_naive();
+    match query {
+        PageQuery::Simple(input) => match SimpleQuery::parse(input, today) {
+            Ok(query) => Some((None, QueryResults::Entities(query.run(graph, lookup_page)))),
+            Err(e) => {
+                eprintln!("{page_title}: Skipping query `{input}`: {e}");
+                None
+            }
+        },
+        PageQuery::Advanced(input) => {
+            let results = AdvancedQuery::parse(input, today, page_title).and_then(|query| {
+                for option in &query.ignored_options {
+                    eprintln!("{page_title}: Ignoring unsupported query option {option}");
+                }
+                query
+                    .run(graph, lookup_page)
+                    .map(|results| (query.title, results))
+            });
+
+            match results {
+                Ok(r) => Some(r),
+                Err(e) => {
+                    eprintln!("{page_title}: Skipping advanced query: {e}");
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Strip link and tag syntax from an attribute or query value, and lowercase it for comparison.
+pub fn normalize_value(value: &str) -> String {
+    value
+        .trim()
+        .trim_start_matches('#')
+        .trim_start_matches("[[")
+        .trim_end_matches("]]")
+        .trim()
+        .to_lowercase()
+}
+
+/// The task marker at the start of the block, such as `TODO` or `DONE`.
+pub fn task_marker(block: &Block) -> Option<&str> {
+    let marker = block.contents.borrow_string().split_whitespace().next()?;
+    matches!(
+        marker,
+        "TODO" | "DOING" | "DONE" | "NOW" | "LATER" | "WAITING" | "CANCELED" | "CANCELLED"
+    )
+    .then_some(marker)
+}
+
+/// The date of the journal page that contains the block, if it is in a journal.
+pub fn journal_date(graph: &Graph, block: &Block) -> Option<NaiveDate> {
+    let page = graph.blocks.get(&block.containing_page)?;
+    if !page.is_journal {
+        return None;
+    }
+
+    crate::logseq::journal_date(page.page_title.as_deref()?)
+}
+
+/// Sort block IDs by the title of their page, and then by their position in the page.
+pub fn sort_by_page(graph: &Graph, ids: impl IntoIterator<Item = usize>) -> Vec<usize> {
+    let page_title = |id: &usize| {
+        graph
+            .blocks
+            .get(id)
+            .and_then(|block| graph.blocks.get(&block.containing_page))
+            .and_then(|page| page.page_title.as_deref())
+            .unwrap_or_default()
+            .to_lowercase()
+    };
+
+    ids.into_iter()
+        .sorted_by_cached_key(|id| (page_title(id), *id))
+        .collect()
+}
+
+/// Parse a date used in a query. This can be a relative date like `today` or `-7d`, or the title
+/// of a journal page.
+pub fn parse_date(value: &str, today: NaiveDate) -> Result<NaiveDate> {
+    static RELATIVE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^([+-]?)(\d+)([dwmy])$").unwrap());
+
+    let value = value.trim().to_lowercase();
+    match value.as_str() {
+        "today" | "now" => return Ok(today),
+        "yesterday" => return Ok(today - Duration::days(1)),
+        "tomorrow" => return Ok(today + Duration::days(1)),
+        _ => {}
+    }
+
+    if let Some(captures) = RELATIVE.captures(&value) {
+        let negative = &captures[1] == "-";
+        let amount = captures[2].parse::<u32>()?;
+        let date = match (&captures[3], negative) {
+            ("d", false) => today.checked_add_signed(Duration::days(amount as i64)),
+            ("d", true) => today.checked_sub_signed(Duration::days(amount as i64)),
+            ("w", false) => today.checked_add_signed(Duration::weeks(amount as i64)),
+            ("w", true) => today.checked_sub_signed(Duration::weeks(amount as i64)),
+            ("m", false) => today.checked_add_months(Months::new(amount)),
+            ("m", true) => today.checked_sub_months(Months::new(amount)),
+            (_, false) => amount
+                .checked_mul(12)
+                .and_then(|months| today.checked_add_months(Months::new(months))),
+            (_, true) => amount
+                .checked_mul(12)
+                .and_then(|months| today.checked_sub_months(Months::new(months))),
+        };
+
+        return date.ok_or_else(|| eyre!("Date {value} is out of range"));
+    }
+
+    crate::logseq::journal_date(&value).ok_or_else(|| eyre!("Could not understand date {value}"))
+}
+
+/// Look up pages by their titles and aliases, for tests that don't build the exporter's maps of
+/// page titles.
+#[cfg(test)]
+fn test_page_lookup(graph: &Graph) -> impl Fn(&str) -> Option<usize> + '_ {
+    move |title| {
+        let title = normalize_title(title);
+        graph
+            .pages()
+            .find(|page| {
+                page.page_title.as_deref().map(normalize_title).as_ref() == Some(&title)
+                    || page
+                        .aliases()
+                        .iter()
+                        .any(|alias| normalize_title(alias) == title)
+            })
+            .map(|page| page.id)
+    }
+}
@@ -0,0 +1,405 @@
+//! Evaluation of Logseq simple queries, like `{{query (and [[page]] (task TODO))}}`.
+
+use chrono::NaiveDate;
+use eyre::{eyre, Result};
+use itertools::Itertools;
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_until, take_while1},
+    character::complete::{char, multispace0},
+    combinator::{all_consuming, map},
+    multi::{many0, many1},
+    sequence::{delimited, preceded, terminated},
+    IResult,
+};
+
+use super::{
+    block_path_references, journal_date, normalize_value, parse_date, sort_by_page, task_marker,
+    PageLookup, PageReference,
+};
+use crate::graph::{Block, Graph};
+
+#[derive(Debug, PartialEq, Eq)]
+enum Term<'a> {
+    List(Vec<Term<'a>>),
+    PageRef(&'a str),
+    Str(&'a str),
+    Atom(&'a str),
+}
+
+fn page_ref(input: &str) -> IResult<&str, &str> {
+    delimited(tag("[["), take_until("]]"), tag("]]"))(input)
+}
+
+fn atom(input: &str) -> IResult<&str, &str> {
+    take_while1(|c: char| !c.is_whitespace() && c != '(' && c != ')')(input)
+}
+
+fn term(input: &str) -> IResult<&str, Term<'_>> {
+    preceded(
+        multispace0,
+        alt((
+            map(
+                delimited(char('('), many0(term), preceded(multispace0, char(')'))),
+                Term::List,
+            ),
+            map(page_ref, Term::PageRef),
+            map(preceded(char('#'), alt((page_ref, atom))), Term::PageRef),
+            map(delimited(char('"'), take_until("\""), char('"')), Term::Str),
+            map(atom, Term::Atom),
+        )),
+    )(input)
+}
+
+fn term_value(term: &Term) -> Result<String> {
+    match term {
+        Term::PageRef(s) | Term::Str(s) | Term::Atom(s) => Ok(normalize_value(s)),
+        Term::List(_) => Err(eyre!("Expected a value but found a list")),
+    }
+}
+
+/// Parse a date argument to `between`.
+fn date_term(term: &Term, today: NaiveDate) -> Result<NaiveDate> {
+    match term {
+        Term::PageRef(s) | Term::Str(s) | Term::Atom(s) => parse_date(s, today),
+        Term::List(_) => Err(eyre!("Expected a date but found a list")),
+    }
+}
+
+/// Convert a Roam-style query like `{and: [[a]] {not: [[b]]}}` into the Logseq syntax.
+fn convert_roam_query(query: &str) -> String {
+    let mut output = String::with_capacity(query.len());
+    let mut chars = query.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                output.push('(');
+                while let Some(c) = chars.next_if(|c| *c != ':' && *c != '}') {
+                    output.push(c);
+                }
+                chars.next_if_eq(&':');
+            }
+            '}' => output.push(')'),
+            _ => output.push(c),
+        }
+    }
+
+    output
+}
+
+/// Extract the query from a brace directive, for both the `query ...` and Roam's `query: ...`
+/// syntax.
+pub fn query_from_directive(directive: &str) -> Option<String> {
+    if let Some(query) = directive.strip_prefix("query:") {
+        Some(convert_roam_query(query.trim()))
+    } else {
+        directive
+            .strip_prefix("query ")
+            .map(|query| query.trim().to_string())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SimpleQuery {
+    And(Vec<SimpleQuery>),
+    Or(Vec<SimpleQuery>),
+    Not(Box<SimpleQuery>),
+    /// Blocks that link to or are tagged with a page
+    PageRef(String),
+    /// Blocks with a property, optionally with a specific value
+    Property {
+        name: String,
+        value: Option<String>,
+    },
+    /// Blocks with any of these task markers
+    Task(Vec<String>),
+    /// Blocks on journal pages between these dates, inclusive
+    Between(NaiveDate, NaiveDate),
+    /// Pages with any of these tags
+    PageTags(Vec<String>),
+    /// Blocks that contain this text
+    FullText(String),
+}
+
+impl SimpleQuery {
+    /// Parse a query. `today` is used to resolve relative dates.
+    pub fn parse(input: &str, today: NaiveDate) -> Result<SimpleQuery> {
+        let (_, mut terms) = all_consuming(terminated(many1(term), multispace0))(input.trim())
+            .map_err(|e| eyre!("Could not parse query: {e}"))?;
+
+        if terms.len() == 1 {
+            Self::from_term(terms.pop().unwrap(), today)
+        } else {
+            terms
+                .into_iter()
+                .map(|t| Self::from_term(t, today))
+                .collect::<Result<Vec<_>>>()
+                .map(SimpleQuery::And)
+        }
+    }
+
+    fn from_term(term: Term, today: NaiveDate) -> Result<SimpleQuery> {
+        let mut args = match term {
+            Term::PageRef(title) => return Ok(SimpleQuery::PageRef(normalize_value(title))),
+            Term::Str(text) | Term::Atom(text) => {
+                return Ok(SimpleQuery::FullText(text.to_lowercase()))
+            }
+            Term::List(args) => args.into_iter(),
+        };
+
+        let name = match args.next() {
+            Some(Term::Atom(name)) => name.to_lowercase(),
+            Some(other) => return Err(eyre!("Expected a filter name, found {other:?}")),
+            None => return Err(eyre!("Empty filter")),
+        };
+
+        let query = match name.as_str() {
+            "and" | "or" | "not" => {
+                let mut queries = args
+                    .map(|t| Self::from_term(t, today))
+                    .collect::<Result<Vec<_>>>()?;
+
+                match name.as_str() {
+                    "and" => SimpleQuery::And(queries),
+                    "or" => SimpleQuery::Or(queries),
+                    _ if queries.len() == 1 => SimpleQuery::Not(Box::new(queries.pop().unwrap())),
+                    _ => SimpleQuery::Not(Box::new(SimpleQuery::Or(queries))),
+                }
+            }
+            "property" => {
+                let name = args
+                    .next()
+                    .ok_or_else(|| eyre!("property requires a name"))
+                    .and_then(|t| term_value(&t))?;
+                let value = args.next().map(|t| term_value(&t)).transpose()?;
+                SimpleQuery::Property {
+                    name: name.trim_start_matches(':').to_string(),
+                    value,
+                }
+            }
+            "task" | "todo" => SimpleQuery::Task(
+                args.map(|t| term_value(&t).map(|v| v.to_uppercase()))
+                    .collect::<Result<_>>()?,
+            ),
+            "between" => {
+                let (Some(start), Some(end)) = (args.next(), args.next()) else {
+                    return Err(eyre!("between requires a start and end date"));
+                };
+
+                SimpleQuery::Between(date_term(&start, today)?, date_term(&end, today)?)
+            }
+            "page-tags" => SimpleQuery::PageTags(
+                args.map(|t| match t {
+                    // Allow both `(page-tags a b)` and `(page-tags [a b])`.
+                    Term::Atom(s) => Ok(s
+                        .split(['[', ']', ','])
+                        .map(normalize_value)
+                        .filter(|v| !v.is_empty())
+                        .collect::<Vec<_>>()),
+                    t => term_value(&t).map(|v| vec![v]),
+                })
+                .flatten_ok()
+                .collect::<Result<_>>()?,
+            ),
+            _ => return Err(eyre!("Unsupported query filter `{name}`")),
+        };
+
+        Ok(query)
+    }
+
+    /// True if the query only filters on page-level information, and so should return pages
+    /// instead of blocks.
+    fn is_page_level(&self) -> bool {
+        match self {
+            SimpleQuery::PageTags(_) => true,
+            SimpleQuery::And(queries) | SimpleQuery::Or(queries) => {
+                queries.iter().all(|q| q.is_page_level())
+            }
+            SimpleQuery::Not(query) => query.is_page_level(),
+            _ => false,
+        }
+    }
+
+    /// Check if the block matches. `references` are the block's path references, from
+    /// [block_path_references].
+    pub(super) fn matches(
+        &self,
+        graph: &Graph,
+        block: &Block,
+        references: &[PageReference],
+        lookup_page: &PageLookup,
+    ) -> bool {
+        match self {
+            SimpleQuery::And(queries) => queries
+                .iter()
+                .all(|q| q.matches(graph, block, references, lookup_page)),
+            SimpleQuery::Or(queries) => queries
+                .iter()
+                .any(|q| q.matches(graph, block, references, lookup_page)),
+            SimpleQuery::Not(query) => !query.matches(graph, block, references, lookup_page),
+            SimpleQuery::PageRef(title) => {
+                references.contains(&PageReference::resolve(title, lookup_page))
+            }
+            SimpleQuery::Property { name, value } => block
+                .attrs
+                .iter()
+                .find(|(attr, _)| attr.to_lowercase() == *name)
+                .map(|(_, values)| match value {
+                    Some(value) => values
+                        .iter()
+                        .flat_map(|v| v.split(','))
+                        .any(|v| normalize_value(v) == *value),
+                    None => true,
+                })
+                .unwrap_or(false),
+            SimpleQuery::Task(markers) => task_marker(block)
+                .map(|marker| markers.iter().any(|m| m == marker))
+                .unwrap_or(false),
+            SimpleQuery::Between(start, end) => journal_date(graph, block)
+                .map(|date| date >= *start && date <= *end)
+                .unwrap_or(false),
+            SimpleQuery::PageTags(tags) => graph
+                .blocks
+                .get(&block.containing_page)
+                .map(|page| {
+                    page.tags
+                        .iter()
+                        .any(|tag| tags.contains(&normalize_value(tag)))
+                })
+                .unwrap_or(false),
+            SimpleQuery::FullText(text) => block
+                .contents
+                .borrow_string()
+                .to_lowercase()
+                .contains(text.as_str()),
+        }
+    }
+
+    /// Return the IDs of the matching blocks, or of the matching pages' root blocks for queries
+    /// that only look at pages. The results are sorted by page title and then by position in the
+    /// page. `lookup_page` resolves the titles of referenced pages.
+    pub fn run(&self, graph: &Graph, lookup_page: &PageLookup) -> Vec<usize> {
+        let pages_only = self.is_page_level();
+        let matches = graph
+            .blocks
+            .values()
+            .filter(|block| block.page_title.is_some() == pages_only)
+            .filter(|block| {
+                let references = block_path_references(graph, block, lookup_page);
+                self.matches(graph, block, &references, lookup_page)
+            })
+            .map(|block| block.id);
+
+        sort_by_page(graph, matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn block(id: usize, page: usize, contents: &str) -> Block {
+        Block::test(id, page, contents)
+    }
+
+    fn page(id: usize, title: &str, tags: &[&str], is_journal: bool) -> Block {
+        let mut page = block(id, id, "");
+        page.parent = None;
+        page.page_title = Some(title.to_string());
+        page.tags = tags.iter().map(|t| t.to_string()).collect();
+        page.is_journal = is_journal;
+        page
+    }
+
+    fn test_graph() -> Graph {
+        let mut graph = Graph::new(false);
+        graph.add_block(page(0, "Projects", &["work"], false));
+        graph.add_block(block(1, 0, "TODO write the [[Report]]"));
+        graph.add_block(block(2, 0, "DONE review #report"));
+        let mut with_attr = block(3, 0, "status:: active\nSomething else");
+        with_attr.attrs.insert(
+            String::from("status"),
+            smallvec::smallvec![String::from("active")],
+        );
+        graph.add_block(with_attr);
+        graph.add_block(page(10, "2023-03-02", &[], true));
+        graph.add_block(block(11, 10, "LATER call about the [[Report]]"));
+        graph.add_block(page(20, "Hobbies", &["fun"], false));
+        graph.add_block(block(21, 20, "Learning [[Rustlang]]"));
+        graph.add_block(Block {
+            parent: Some(21),
+            ..block(22, 20, "Chapter one")
+        });
+        let mut rust = page(30, "Rust", &[], false);
+        rust.attrs.insert(
+            String::from("alias"),
+            smallvec::smallvec![String::from("Rustlang")],
+        );
+        graph.add_block(rust);
+        graph.add_block(block(31, 30, "Ownership"));
+        graph
+    }
+
+    #[test]
+    fn parse_query() {
+        let today = date(2023, 3, 10);
+        assert_eq!(
+            SimpleQuery::parse(
+                "(and [[Report]] (not (task DONE)) (between -7d today))",
+                today
+            )
+            .unwrap(),
+            SimpleQuery::And(vec![
+                SimpleQuery::PageRef(String::from("report")),
+                SimpleQuery::Not(Box::new(SimpleQuery::Task(vec![String::from("DONE")]))),
+                SimpleQuery::Between(date(2023, 3, 3), today),
+            ])
+        );
+
+        assert_eq!(
+            SimpleQuery::parse(
+                &query_from_directive(
+                    "query: {or: [[a]] {between: [[March 1st, 2023]] [[today]]}}"
+                )
+                .unwrap(),
+                today
+            )
+            .unwrap(),
+            SimpleQuery::Or(vec![
+                SimpleQuery::PageRef(String::from("a")),
+                SimpleQuery::Between(date(2023, 3, 1), today),
+            ])
+        );
+
+        assert!(SimpleQuery::parse("(sort-by created-at)", today).is_err());
+        assert!(SimpleQuery::parse("(between -400000000y today)", today).is_err());
+    }
+
+    #[test]
+    fn run_query() {
+        let graph = test_graph();
+        let today = date(2023, 3, 10);
+        let lookup_page = super::super::test_page_lookup(&graph);
+        let run = |q: &str| {
+            SimpleQuery::parse(q, today)
+                .unwrap()
+                .run(&graph, &lookup_page)
+        };
+
+        assert_eq!(run("[[report]]"), vec![11, 1, 2]);
+        assert_eq!(run("(and [[Report]] (task TODO LATER))"), vec![11, 1]);
+        assert_eq!(run("(and [[Report]] (not (task LATER)))"), vec![1, 2]);
+        assert_eq!(run("(property status active)"), vec![3]);
+        assert_eq!(run("(between [[2023-03-01]] [[2023-03-05]])"), vec![11]);
+        assert_eq!(run("(page-tags work fun)"), vec![20, 0]);
+        assert_eq!(run("(and (page-tags work) \"review\")"), vec![2]);
+
+        // References through an alias or a parent block, and the blocks on the page itself.
+        assert_eq!(run("[[Rust]]"), vec![21, 22, 31]);
+        assert_eq!(run("[[rustlang]]"), vec![21, 22, 31]);
+    }
+}
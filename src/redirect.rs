@@ -0,0 +1,110 @@
+//! Pages that only send visitors on to another page.
+
+use serde::Deserialize;
+
+use crate::html;
+
+/// A page that immediately redirects to `slug`, for servers that can't be configured to redirect
+/// on their own.
+pub fn redirect_page(slug: &str, title: &str) -> String {
+    let slug = html::escape(slug);
+    let title = html::escape(title);
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>{title}</title>
+  <link rel="canonical" href="{slug}">
+  <meta http-equiv="refresh" content="0; url={slug}">
+</head>
+<body>
+  <p>This page has moved to <a href="{slug}">{title}</a>.</p>
+</body>
+</html>
+"#
+    )
+}
+
+/// How to publish the redirects from the URLs that pages were previously exported at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlugRedirects {
+    /// A redirect page at each old URL
+    Html,
+    /// A `_redirects` file, as used by Netlify and Cloudflare Pages
+    Netlify,
+    /// An `nginx-redirects.map` file with the entries for an nginx `map` block
+    Nginx,
+}
+
+/// A redirect from a URL that a page used to have.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlugRedirect {
+    pub old_slug: String,
+    pub old_output_path: String,
+    pub title: String,
+    pub new_slug: String,
+}
+
+/// The contents of a `_redirects` file with a permanent redirect for each old slug. Spaces
+/// separate the fields, so they're encoded in the slugs.
+pub fn redirects_file(redirects: &[SlugRedirect]) -> String {
+    redirects
+        .iter()
+        .map(|r| {
+            format!(
+                "{} {} 301\n",
+                r.old_slug.replace(' ', "%20"),
+                r.new_slug.replace(' ', "%20")
+            )
+        })
+        .collect()
+}
+
+/// The entries for an nginx `map` from each old slug to its new one, to be included like this:
+///
+/// ```nginx
+/// map $uri $redirect_uri {
+///     include /path/to/nginx-redirects.map;
+/// }
+/// ```
+pub fn nginx_map(redirects: &[SlugRedirect]) -> String {
+    let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+    redirects
+        .iter()
+        .map(|r| format!("{} {};\n", quote(&r.old_slug), quote(&r.new_slug)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirect_files() {
+        let redirects = [
+            SlugRedirect {
+                old_slug: String::from("/old"),
+                old_output_path: String::from("out/old.html"),
+                title: String::from("New"),
+                new_slug: String::from("/new"),
+            },
+            SlugRedirect {
+                old_slug: String::from("/books/a \"quoted\" name"),
+                old_output_path: String::from("out/books/a \"quoted\" name.html"),
+                title: String::from("A Book"),
+                new_slug: String::from("/books/a_book"),
+            },
+        ];
+
+        assert_eq!(
+            redirects_file(&redirects),
+            "/old /new 301\n/books/a%20\"quoted\"%20name /books/a_book 301\n"
+        );
+        assert_eq!(
+            nginx_map(&redirects),
+            "\"/old\" \"/new\";\n\"/books/a \\\"quoted\\\" name\" \"/books/a_book\";\n"
+        );
+    }
+}
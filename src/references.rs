@@ -0,0 +1,124 @@
+use ahash::{HashMap, HashSet};
+use serde::Serialize;
+
+use crate::{graph::Graph, parse_string::Expression};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reference<'a> {
+    /// A link or tag that points to a page title
+    Page(&'a str),
+    /// A reference to a block uid
+    Block(&'a str),
+}
+
+/// Gather all the page and block references in a list of expressions, including those nested
+/// inside styled text and attributes.
+pub fn collect_references<'a>(expressions: &[Expression<'a>], output: &mut Vec<Reference<'a>>) {
+    for expr in expressions {
+        match expr {
+            Expression::Link(title) => output.push(Reference::Page(title)),
+            Expression::Hashtag(title, _) => output.push(Reference::Page(title)),
+            Expression::MarkdownInternalLink { page, .. } => output.push(Reference::Page(page)),
+            Expression::BlockRef(uid) => output.push(Reference::Block(uid)),
+            _ => {}
+        }
+
+        let contained = expr.contained_expressions();
+        if !contained.is_empty() {
+            collect_references(contained, output);
+        }
+    }
+}
+
+/// An entry in the "Linked References" section of a page.
+#[derive(Serialize, Debug)]
+pub struct Backlink {
+    /// The title of the page containing the reference
+    pub title: String,
+    /// The slug of the page containing the reference
+    pub slug: String,
+    /// The uid of the referencing block, if it has one.
+    pub uid: String,
+    /// The rendered contents of the referencing block
+    pub snippet: String,
+}
+
+/// A reverse index from each page to the blocks on other pages that reference it.
+pub struct BacklinkIndex {
+    referencing_blocks: HashMap<usize, Vec<usize>>,
+}
+
+impl BacklinkIndex {
+    /// Build the index from every block in the graph. `lookup_page` converts a page title into
+    /// the ID of the page's root block.
+    pub fn new(graph: &Graph, lookup_page: impl Fn(&str) -> Option<usize>) -> BacklinkIndex {
+        let mut referencing_blocks: HashMap<usize, Vec<usize>> = HashMap::default();
+        let mut seen = HashSet::default();
+        let mut references = Vec::new();
+
+        let mut block_ids = graph.blocks.keys().copied().collect::<Vec<_>>();
+        block_ids.sort_unstable();
+
+        for block_id in block_ids {
+            let block = graph.blocks.get(&block_id).unwrap();
+
+            references.clear();
+            collect_references(block.contents.borrow_parsed(), &mut references);
+            if references.is_empty() {
+                continue;
+            }
+
+            seen.clear();
+            for reference in &references {
+                let target = match reference {
+                    Reference::Page(title) => lookup_page(title),
+                    Reference::Block(uid) => graph.block_from_uid(uid).map(|b| b.containing_page),
+                };
+
+                match target {
+                    Some(target) if target != block.containing_page && seen.insert(target) => {
+                        referencing_blocks.entry(target).or_default().push(block.id);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        BacklinkIndex { referencing_blocks }
+    }
+
+    /// Return the IDs of the blocks that reference the page.
+    pub fn referencing_blocks(&self, page_id: usize) -> &[usize] {
+        self.referencing_blocks
+            .get(&page_id)
+            .map(|v| v.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_string::{parse, ContentStyle};
+
+    #[test]
+    fn nested_references() {
+        let parsed = parse(
+            ContentStyle::Logseq,
+            "See [[Page A]] and **#tag [label]([[Page B]])** from ((abc-123))",
+        )
+        .unwrap();
+
+        let mut refs = Vec::new();
+        collect_references(&parsed, &mut refs);
+        assert_eq!(
+            refs,
+            vec![
+                Reference::Page("Page A"),
+                Reference::Page("tag"),
+                Reference::Page("Page B"),
+                Reference::Block("abc-123"),
+            ]
+        );
+    }
+}
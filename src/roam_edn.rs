@@ -1,4 +1,4 @@
-use std::{collections::BTreeMap, convert::TryFrom, mem, path::PathBuf, str::FromStr};
+use std::{collections::BTreeMap, convert::TryFrom, mem, path::Path, str::FromStr};
 
 use ahash::{HashMap, HashMapExt};
 use edn_rs::{Edn, EdnError};
@@ -372,8 +372,8 @@ impl RoamGraph {
     }
 }
 
-pub fn graph_from_roam_edn(path: &str) -> Result<(ContentStyle, bool, Vec<ParsedPage>)> {
-    let roam_graph = RoamGraph::from_edn(path)?;
+pub fn graph_from_roam_edn(path: &Path, data: &str) -> Result<(bool, Vec<ParsedPage>)> {
+    let roam_graph = RoamGraph::from_edn(data)?;
 
     let mut blocks = Vec::with_capacity(roam_graph.blocks.len());
 
@@ -446,9 +446,10 @@ pub fn graph_from_roam_edn(path: &str) -> Result<(ContentStyle, bool, Vec<Parsed
         let p = pages
             .entry(block.containing_page)
             .or_insert_with(|| ParsedPage {
-                path: PathBuf::from(path),
+                path: path.to_path_buf(),
                 root_block: block.containing_page,
                 blocks: HashMap::default(),
+                whiteboard: None,
             });
 
         p.blocks.insert(block.id, block);
@@ -456,5 +457,5 @@ pub fn graph_from_roam_edn(path: &str) -> Result<(ContentStyle, bool, Vec<Parsed
 
     let page_list = pages.into_iter().map(|(_, v)| v).collect::<Vec<_>>();
 
-    Ok((ContentStyle::Roam, true, page_list))
+    Ok((true, page_list))
 }
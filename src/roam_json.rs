@@ -0,0 +1,291 @@
+use std::{convert::TryFrom, path::Path};
+
+use ahash::{HashMap, HashMapExt};
+use eyre::Result;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use smallvec::SmallVec;
+
+use crate::{
+    content::BlockContent,
+    graph::{AttrList, Block, BlockInclude, ListType, ParsedPage, ViewType},
+    parse_string::ContentStyle,
+    roam_edn::RoamViewType,
+};
+
+/// Roam gives daily notes a uid made from the date, like "10-16-2023".
+static DAILY_NOTE_UID: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\d{2}-\d{2}-\d{4}$").unwrap());
+
+#[derive(Deserialize)]
+struct UidRef {
+    uid: String,
+}
+
+#[derive(Deserialize)]
+struct JsonBlock {
+    title: Option<String>,
+    #[serde(default)]
+    string: String,
+    #[serde(default)]
+    uid: String,
+    heading: Option<usize>,
+    #[serde(rename = "children/view-type")]
+    view_type: Option<String>,
+    #[serde(rename = "create-time")]
+    create_time: Option<u64>,
+    #[serde(rename = "edit-time")]
+    edit_time: Option<u64>,
+    #[serde(default)]
+    refs: Vec<UidRef>,
+    /// Attributes referenced within a page, in the same triples as the EDN export's
+    /// `:entity/attrs`, except that each entry is a JSON object.
+    #[serde(rename = ":entity/attrs", default)]
+    entity_attrs: Vec<Vec<Value>>,
+    #[serde(default)]
+    children: Vec<JsonBlock>,
+}
+
+/// Get the value of an attribute part, which is either a plain string or a
+/// `[":block/uid", "the-uid"]` reference.
+fn attr_value(part: &Value) -> Option<&str> {
+    match part.get("value")? {
+        Value::String(s) => Some(s.trim()),
+        Value::Array(a) => match a.as_slice() {
+            [Value::String(k), Value::String(uid)] if k == ":block/uid" => Some(uid.trim()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+struct JsonReader<'a> {
+    path: &'a Path,
+    next_id: usize,
+    blocks: Vec<Block>,
+    /// The refs of each block, resolved into tags once all the pages are read.
+    refs: Vec<(usize, Vec<String>)>,
+    titles_by_uid: HashMap<String, String>,
+}
+
+impl<'a> JsonReader<'a> {
+    /// Add a block and its children, returning the new block's id and creation time.
+    fn read_block(
+        &mut self,
+        block: JsonBlock,
+        page: Option<usize>,
+        parent: Option<usize>,
+        order: usize,
+    ) -> Result<(usize, u64)> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let containing_page = page.unwrap_or(id);
+
+        if let Some(title) = block.title.as_ref() {
+            self.titles_by_uid.insert(block.uid.clone(), title.clone());
+        }
+
+        let view_type = match block.view_type.as_deref() {
+            Some(v) => {
+                match RoamViewType::try_from(format!(":{}", v.trim_start_matches(':')).as_str())? {
+                    RoamViewType::Bullet => ViewType::Bullet,
+                    RoamViewType::Numbered => ViewType::Numbered,
+                    RoamViewType::Document => ViewType::Document,
+                }
+            }
+            None => ViewType::Bullet,
+        };
+
+        let mut attrs: HashMap<String, AttrList> = HashMap::new();
+        for mut triple in block.entity_attrs {
+            let (Some(value), Some(uid)) = (triple.pop(), triple.pop()) else {
+                continue;
+            };
+
+            if let (Some(uid), Some(value)) = (attr_value(&uid), attr_value(&value)) {
+                attrs
+                    .entry(uid.to_string())
+                    .or_default()
+                    .push(value.to_string());
+            }
+        }
+
+        let mut children = SmallVec::new();
+        let mut child_create_time = None;
+        for (i, child) in block.children.into_iter().enumerate() {
+            let (child_id, create_time) =
+                self.read_block(child, Some(containing_page), Some(id), i)?;
+            children.push(child_id);
+            child_create_time = Some(child_create_time.unwrap_or(u64::MAX).min(create_time));
+        }
+
+        // Pages often have no creation time in the export, so use the earliest one of their
+        // children, the same as the EDN reader.
+        let create_time = block
+            .create_time
+            .filter(|t| *t > 0)
+            .or(child_create_time)
+            .unwrap_or_default();
+
+        self.refs
+            .push((id, block.refs.into_iter().map(|r| r.uid).collect()));
+
+        let is_journal = page.is_none() && DAILY_NOTE_UID.is_match(&block.uid);
+
+        self.blocks.push(Block {
+            id,
+            uid: block.uid,
+            include_type: BlockInclude::default(),
+            containing_page,
+            page_title: block.title,
+            original_title: None,
+            tags: SmallVec::new(),
+            attrs,
+            create_time,
+            edit_time: block.edit_time.unwrap_or(create_time),
+            is_journal,
+            extra_classes: Vec::new(),
+            content_element: None,
+            wrapper_element: None,
+
+            order,
+            parent,
+            children,
+            contents: BlockContent::new_parsed(ContentStyle::Roam, block.string)?,
+            heading: block.heading.unwrap_or(0),
+            view_type,
+            this_block_list_type: ListType::Default,
+        });
+
+        Ok((id, create_time))
+    }
+}
+
+/// Read a Roam JSON export, which is an array of pages with their blocks nested inside.
+pub fn graph_from_roam_json(path: &Path, data: &str) -> Result<(bool, Vec<ParsedPage>)> {
+    let json_pages: Vec<JsonBlock> = serde_json::from_str(data)?;
+
+    let mut reader = JsonReader {
+        path,
+        next_id: 1,
+        blocks: Vec::new(),
+        refs: Vec::new(),
+        titles_by_uid: HashMap::new(),
+    };
+
+    for page in json_pages {
+        reader.read_block(page, None, None, 0)?;
+    }
+
+    let mut tags_by_id = reader
+        .refs
+        .into_iter()
+        .map(|(id, refs)| {
+            let tags = refs
+                .iter()
+                .filter_map(|uid| reader.titles_by_uid.get(uid).cloned())
+                .collect::<SmallVec<_>>();
+            (id, tags)
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut pages: HashMap<usize, ParsedPage> = HashMap::new();
+    for mut block in reader.blocks {
+        block.tags = tags_by_id.remove(&block.id).unwrap_or_default();
+
+        let p = pages
+            .entry(block.containing_page)
+            .or_insert_with(|| ParsedPage {
+                path: reader.path.to_path_buf(),
+                root_block: block.containing_page,
+                blocks: HashMap::default(),
+                whiteboard: None,
+            });
+
+        p.blocks.insert(block.id, block);
+    }
+
+    Ok((true, pages.into_values().collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r##"[
+  {
+    "title": "Project",
+    "uid": "projuid01",
+    "edit-time": 1690000005000,
+    "children": [
+      {
+        "string": "Overview",
+        "uid": "blk000001",
+        "heading": 2,
+        "children/view-type": "numbered",
+        "create-time": 1690000001000,
+        "edit-time": 1690000002000,
+        "children": [
+          { "string": "First", "uid": "blk000002", "create-time": 1690000003000 },
+          {
+            "string": "Second, see ((blk000001)) and [[October 16th, 2023]]",
+            "uid": "blk000003",
+            "create-time": 1690000004000,
+            "refs": [{ "uid": "10-16-2023" }]
+          }
+        ]
+      }
+    ]
+  },
+  {
+    "title": "October 16th, 2023",
+    "uid": "10-16-2023",
+    "create-time": 1697400000000,
+    "children": [{ "string": "Journal entry", "uid": "blk000004", "create-time": 1697400000000 }]
+  }
+]"##;
+
+    #[test]
+    fn read_json() {
+        let (explicit_ordering, pages) =
+            graph_from_roam_json(Path::new("roam.json"), SOURCE).unwrap();
+        assert!(explicit_ordering);
+        assert_eq!(pages.len(), 2);
+
+        let project = pages
+            .iter()
+            .find(|p| p.blocks[&p.root_block].page_title.as_deref() == Some("Project"))
+            .unwrap();
+        assert_eq!(project.blocks.len(), 4);
+        assert_eq!(project.path, Path::new("roam.json"));
+
+        let root = &project.blocks[&project.root_block];
+        assert!(!root.is_journal);
+        assert_eq!(root.uid, "projuid01");
+        // The page has no creation time, so it comes from its earliest child.
+        assert_eq!(root.create_time, 1690000001000);
+        assert_eq!(root.edit_time, 1690000005000);
+        assert_eq!(root.children.len(), 1);
+
+        let overview = &project.blocks[&root.children[0]];
+        assert_eq!(overview.heading, 2);
+        assert_eq!(overview.view_type, ViewType::Numbered);
+        assert_eq!(overview.create_time, 1690000001000);
+        assert_eq!(overview.edit_time, 1690000002000);
+        assert_eq!(overview.parent, Some(root.id));
+
+        let second = &project.blocks[&overview.children[1]];
+        assert_eq!(second.uid, "blk000003");
+        assert_eq!(second.order, 1);
+        assert_eq!(second.tags.as_slice(), &["October 16th, 2023".to_string()]);
+
+        let journal = pages
+            .iter()
+            .find(|p| p.root_block != project.root_block)
+            .unwrap();
+        let journal_root = &journal.blocks[&journal.root_block];
+        assert!(journal_root.is_journal);
+        assert_eq!(journal_root.create_time, 1697400000000);
+    }
+}
@@ -1,7 +1,10 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
 
 use ahash::{HashMap, HashSet};
-use eyre::{eyre, Result};
+use eyre::{eyre, Result, WrapErr};
 use regex::RegexSet;
 use rhai::{
     def_package,
@@ -16,6 +19,7 @@ use crate::{
     content::BlockContent,
     graph::{AttrList, Block, BlockInclude, ParsedPage, ViewType},
     make_pages::title_to_slug,
+    namespace::{namespace_parts, nested_url_name},
 };
 
 type SmartString = smartstring::SmartString<smartstring::LazyCompact>;
@@ -75,6 +79,9 @@ pub struct PageConfig {
     pub attrs: HashMap<String, AttrList>,
     pub tags: AttrList,
     pub is_journal: bool,
+    pub is_whiteboard: bool,
+    /// The levels of the page's namespace, ending with the page itself.
+    pub namespace_parts: Vec<String>,
     pub template: TemplateSelection,
     pub picture_template: TemplateSelection,
     pub picture_upload_profile: Option<String>,
@@ -84,6 +91,13 @@ pub struct PageConfig {
     pub top_header_level: usize,
 
     pub root_block: usize,
+
+    /// The titles of pages that mention this page in plain text without linking to it.
+    pub unlinked_mentions: Vec<String>,
+
+    /// List the page in the sitemap. Defaults to true.
+    pub in_sitemap: bool,
+    pub sitemap_priority: Option<f64>,
 }
 
 #[derive(Debug, Clone)]
@@ -321,6 +335,31 @@ pub mod rhai_page {
         page.is_journal
     }
 
+    #[rhai_fn(get = "is_whiteboard", pure)]
+    pub fn is_whiteboard(page: &mut Page) -> bool {
+        page.is_whiteboard
+    }
+
+    /// The levels of the page's namespace, so `Books/Notes/A Book` is
+    /// `["Books", "Notes", "A Book"]`. A page outside any namespace has just its own title.
+    #[rhai_fn(get = "namespace_parts", pure)]
+    pub fn get_namespace_parts(page: &mut Page) -> Vec<Dynamic> {
+        page.namespace_parts
+            .iter()
+            .map(|s| Dynamic::from(s.to_string()))
+            .collect()
+    }
+
+    /// The titles of the pages that mention this page without linking to it. This is empty
+    /// unless the `unlinked_mentions` option is set.
+    #[rhai_fn(get = "unlinked_mentions", pure)]
+    pub fn get_unlinked_mentions(page: &mut Page) -> Vec<Dynamic> {
+        page.unlinked_mentions
+            .iter()
+            .map(|s| Dynamic::from(s.to_string()))
+            .collect()
+    }
+
     #[rhai_fn(get = "root_block", pure)]
     pub fn root_block(page: &mut Page) -> i64 {
         page.root_block as i64
@@ -400,6 +439,30 @@ pub mod rhai_page {
         page.include = value;
     }
 
+    /// Whether the page is listed in `sitemap.xml`, when writing one. Defaults to true.
+    #[rhai_fn(get = "in_sitemap", pure)]
+    pub fn get_in_sitemap(page: &mut Page) -> bool {
+        page.in_sitemap
+    }
+
+    #[rhai_fn(set = "in_sitemap")]
+    pub fn set_in_sitemap(page: &mut Page, value: bool) {
+        page.in_sitemap = value;
+    }
+
+    /// The page's priority in `sitemap.xml`, from 0.0 to 1.0, or `()` if it isn't set.
+    #[rhai_fn(get = "sitemap_priority", pure)]
+    pub fn get_sitemap_priority(page: &mut Page) -> Dynamic {
+        page.sitemap_priority
+            .map(Dynamic::from_float)
+            .unwrap_or(Dynamic::UNIT)
+    }
+
+    #[rhai_fn(set = "sitemap_priority")]
+    pub fn set_sitemap_priority(page: &mut Page, value: f64) {
+        page.sitemap_priority = Some(value);
+    }
+
     #[rhai_fn(get = "top_header_level", pure)]
     pub fn get_top_header_level(page: &mut Page) -> usize {
         page.top_header_level
@@ -666,11 +729,31 @@ def_package! {
     }
 }
 
+/// A page script, compiled once and then run against every page.
+pub struct PageScript {
+    package: ParsePackage,
+    ast: AST,
+}
+
+impl PageScript {
+    pub fn compile(path: &Path) -> Result<PageScript> {
+        let package = ParsePackage::new();
+        let mut parse_engine = Engine::new_raw();
+        package.register_into_engine(&mut parse_engine);
+
+        let ast = parse_engine
+            .compile_file(path.to_path_buf())
+            .wrap_err("Parsing script")?;
+
+        Ok(PageScript { package, ast })
+    }
+}
+
 pub fn run_script_on_page(
-    package: &ParsePackage,
-    ast: &AST,
+    script: &PageScript,
     global_config: &Config,
     page: ParsedPage,
+    unlinked_mentions: Vec<String>,
 ) -> Result<(PageConfig, ParsedPage)> {
     let mut engine = Engine::new_raw();
 
@@ -679,7 +762,7 @@ pub fn run_script_on_page(
         println!("script:{pos:?}: {x}");
     });
 
-    package.register_into_engine(&mut engine);
+    script.package.register_into_engine(&mut engine);
 
     let page_block = page.blocks.get(&page.root_block).expect("Block must exist");
     let title = page_block
@@ -687,7 +770,12 @@ pub fn run_script_on_page(
         .clone()
         .expect("Page title must exist");
 
-    let slug = title_to_slug(&title);
+    let namespace_parts = namespace_parts(&title);
+    let slug = if global_config.namespace_dirs {
+        nested_url_name(&namespace_parts)
+    } else {
+        title_to_slug(&title)
+    };
 
     let page_config = PageConfig {
         include: false,
@@ -700,11 +788,16 @@ pub fn run_script_on_page(
         picture_template: TemplateSelection::Default,
         picture_upload_profile: None,
         is_journal: page_block.is_journal,
+        is_whiteboard: page.whiteboard.is_some(),
+        namespace_parts,
         attrs: page_block.attrs.clone(),
         tags: page_block.tags.clone(),
         allow_embedding: AllowEmbed::Default,
         top_header_level: global_config.top_header_level,
         root_block: page.root_block,
+        unlinked_mentions,
+        in_sitemap: true,
+        sitemap_priority: None,
     };
 
     let page = Arc::new(Mutex::new(page));
@@ -731,7 +824,7 @@ pub fn run_script_on_page(
     }
 
     engine
-        .run_ast_with_scope(&mut scope, ast)
+        .run_ast_with_scope(&mut scope, &script.ast)
         .map_err(|e| eyre!("{e:?}"))?;
 
     drop(scope);
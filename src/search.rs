@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+
+use eyre::Result;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::plain_text::PlainTextRenderer;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchIndexFormat {
+    /// A list of the entries
+    #[default]
+    Documents,
+    /// The entries, plus an index from each word to the entries that contain it.
+    Inverted,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SearchIndexConfig {
+    /// Create an entry for each block instead of each page. Defaults to false.
+    #[serde(default)]
+    pub per_block: bool,
+    /// The layout of the index file. Defaults to "documents".
+    #[serde(default)]
+    pub format: SearchIndexFormat,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SearchEntry {
+    pub title: String,
+    pub slug: String,
+    pub tags: Vec<String>,
+    /// The anchors of the blocks that the entry covers
+    pub anchors: Vec<String>,
+    pub text: String,
+}
+
+#[derive(Serialize)]
+struct InvertedIndex<'a> {
+    documents: &'a [SearchEntry],
+    /// Each word to the indexes of the documents that contain it
+    index: BTreeMap<String, Vec<usize>>,
+}
+
+/// Create the search entries for a page, either one for the whole page or one for each block.
+pub fn page_entries(
+    renderer: &PlainTextRenderer,
+    page_id: usize,
+    title: &str,
+    slug: &str,
+    tags: &[&str],
+    per_block: bool,
+) -> Vec<SearchEntry> {
+    let texts = renderer.page_blocks(page_id).into_iter();
+
+    let entry = |anchors: Vec<String>, text: String| SearchEntry {
+        title: title.to_string(),
+        slug: slug.to_string(),
+        tags: tags.iter().map(|t| t.to_string()).collect(),
+        anchors,
+        text,
+    };
+
+    if per_block {
+        texts
+            .map(|(block, text)| {
+                let anchors = if block.uid.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![block.uid.clone()]
+                };
+                entry(anchors, text)
+            })
+            .collect()
+    } else {
+        let mut anchors = Vec::new();
+        let mut text = String::new();
+        for (block, block_text) in texts {
+            if !block.uid.is_empty() {
+                anchors.push(block.uid.clone());
+            }
+
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text.push_str(&block_text);
+        }
+
+        vec![entry(anchors, text)]
+    }
+}
+
+/// Split text into lowercased words for the inverted index.
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() > 1)
+        .map(|word| word.to_lowercase())
+}
+
+fn inverted_index(entries: &[SearchEntry]) -> BTreeMap<String, Vec<usize>> {
+    let mut index: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let entry_words = words(&entry.title)
+            .chain(entry.tags.iter().flat_map(|tag| words(tag)))
+            .chain(words(&entry.text))
+            .unique();
+
+        for word in entry_words {
+            index.entry(word).or_default().push(i);
+        }
+    }
+
+    index
+}
+
+/// The contents of the search index file.
+pub fn search_index_json(config: &SearchIndexConfig, entries: &[SearchEntry]) -> Result<String> {
+    let json = match config.format {
+        SearchIndexFormat::Documents => serde_json::to_string(entries)?,
+        SearchIndexFormat::Inverted => serde_json::to_string(&InvertedIndex {
+            documents: entries,
+            index: inverted_index(entries),
+        })?,
+    };
+
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use ahash::{HashMap, HashSet};
+
+    use super::*;
+    use crate::graph::{Block, Graph};
+
+    fn block(id: usize, uid: &str, contents: &str, children: &[usize]) -> Block {
+        Block {
+            uid: uid.to_string(),
+            children: children.iter().copied().collect(),
+            ..Block::test(id, 0, contents)
+        }
+    }
+
+    fn test_graph() -> Graph {
+        let mut graph = Graph::new(false);
+        let mut page = block(0, "", "", &[1, 2, 3]);
+        page.page_title = Some(String::from("Page"));
+        graph.add_block(page);
+        graph.add_block(block(
+            1,
+            "abc",
+            "Some **bold** text with a [[Link]] and `code`",
+            &[],
+        ));
+        graph.add_block(block(2, "", "A #tag and {{table}}", &[]));
+        graph.add_block(block(3, "def", "private:: yes", &[]));
+        graph
+    }
+
+    fn test_entries(
+        graph: &Graph,
+        omitted: &[&str],
+        tags: &[&str],
+        per_block: bool,
+    ) -> Vec<SearchEntry> {
+        let pages_by_title = HashMap::default();
+        let pages_by_filename_title = HashMap::default();
+        let omitted_attributes = HashSet::from_iter(omitted.iter().copied());
+        let renderer = PlainTextRenderer {
+            graph,
+            pages_by_title: &pages_by_title,
+            pages_by_filename_title: &pages_by_filename_title,
+            omitted_attributes: &omitted_attributes,
+        };
+
+        page_entries(&renderer, 0, "Page", "/page", tags, per_block)
+    }
+
+    #[test]
+    fn page_and_block_entries() {
+        let graph = test_graph();
+
+        let entries = test_entries(&graph, &["private"], &["tag"], false);
+        assert_eq!(
+            entries,
+            vec![SearchEntry {
+                title: String::from("Page"),
+                slug: String::from("/page"),
+                tags: vec![String::from("tag")],
+                anchors: vec![String::from("abc")],
+                text: String::from("Some bold text with a Link and code\nA tag and"),
+            }]
+        );
+
+        let entries = test_entries(&graph, &["private"], &[], true);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].anchors, vec![String::from("abc")]);
+        assert_eq!(entries[1].anchors, Vec::<String>::new());
+        assert_eq!(entries[1].text, "A tag and");
+    }
+
+    #[test]
+    fn inverted() {
+        let graph = test_graph();
+        let entries = test_entries(&graph, &[], &[], true);
+        let index = inverted_index(&entries);
+        assert_eq!(index.get("page"), Some(&vec![0, 1, 2]));
+        assert_eq!(index.get("private"), Some(&vec![2]));
+        assert_eq!(index.get("link"), Some(&vec![0]));
+        assert_eq!(index.get("tag"), Some(&vec![1]));
+        assert_eq!(index.get("a"), None);
+    }
+}
@@ -0,0 +1,317 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use eyre::{Result, WrapErr};
+
+use crate::page::ManifestItem;
+
+/// The path that browsers connect to in order to receive reload events.
+const LIVE_RELOAD_PATH: &str = "/__live-reload";
+
+/// Injected into HTML pages so that they reload whenever the site is rebuilt.
+const LIVE_RELOAD_SCRIPT: &str =
+    r##"<script>new EventSource("/__live-reload").onmessage = () => location.reload();</script>"##;
+
+/// How often to send a keepalive to idle live reload connections, so that closed connections are
+/// noticed.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    pub host: String,
+    pub port: u16,
+}
+
+/// Tells the connected browsers to reload.
+#[derive(Clone, Default)]
+pub struct ReloadSignal(Arc<(Mutex<u64>, Condvar)>);
+
+impl ReloadSignal {
+    pub fn notify(&self) {
+        let (generation, condvar) = &*self.0;
+        *generation.lock().unwrap() += 1;
+        condvar.notify_all();
+    }
+
+    fn generation(&self) -> u64 {
+        *self.0 .0.lock().unwrap()
+    }
+
+    /// Wait until the generation differs from `seen`, or until the timeout elapses. Returns the
+    /// current generation.
+    fn wait(&self, seen: u64, timeout: Duration) -> u64 {
+        let (generation, condvar) = &*self.0;
+        let guard = generation.lock().unwrap();
+        let (guard, _) = condvar
+            .wait_timeout_while(guard, timeout, |g| *g == seen)
+            .unwrap();
+        *guard
+    }
+}
+
+struct Server {
+    output: PathBuf,
+    reload: ReloadSignal,
+}
+
+/// Start serving the output directory in a background thread.
+pub fn start(
+    options: &ServeOptions,
+    output: &Path,
+    reload: ReloadSignal,
+) -> Result<JoinHandle<()>> {
+    let address = format!("{}:{}", options.host, options.port);
+    let listener =
+        TcpListener::bind(&address).with_context(|| format!("Listening on {address}"))?;
+    println!("Serving {} at http://{address}/", output.display());
+
+    let server = Arc::new(Server {
+        output: output.to_path_buf(),
+        reload,
+    });
+
+    let handle = std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+
+            let server = server.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = server.handle(stream) {
+                    eprintln!("Serve error: {e:?}");
+                }
+            });
+        }
+    });
+
+    Ok(handle)
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+    {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        _ => "application/octet-stream",
+    }
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut output = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let decoded = (bytes[i] == b'%')
+            .then(|| s.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match decoded {
+            Some(b) => {
+                output.push(b);
+                i += 3;
+            }
+            None => {
+                output.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+    include_body: bool,
+) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    if include_body {
+        stream.write_all(body)?;
+    }
+    stream.flush()?;
+    Ok(())
+}
+
+impl Server {
+    fn handle(&self, mut stream: TcpStream) -> Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+
+        // Skip the headers, since nothing here needs them.
+        let mut header = String::new();
+        loop {
+            header.clear();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default();
+        let target = parts.next().unwrap_or_default();
+        let path = percent_decode(target.split(['?', '#']).next().unwrap_or_default());
+
+        if method != "GET" && method != "HEAD" {
+            return write_response(
+                &mut stream,
+                "405 Method Not Allowed",
+                "text/plain",
+                b"Method not allowed",
+                true,
+            );
+        }
+
+        if path == LIVE_RELOAD_PATH {
+            return self.live_reload(stream);
+        }
+
+        let include_body = method == "GET";
+        match self.resolve(&path) {
+            Some(file) => {
+                let mut body = std::fs::read(&file).with_context(|| format!("{file:?}"))?;
+                let content_type = content_type(&file);
+                if content_type.starts_with("text/html") {
+                    body = inject_live_reload(body);
+                }
+                write_response(&mut stream, "200 OK", content_type, &body, include_body)
+            }
+            None => write_response(
+                &mut stream,
+                "404 Not Found",
+                "text/plain",
+                b"Not found",
+                include_body,
+            ),
+        }
+    }
+
+    /// Find the file for a request path, first by looking up the path as a slug in the manifest and
+    /// then by looking in the output directory.
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let manifest = std::fs::read_to_string(self.output.join("manifest.json"))
+            .ok()
+            .and_then(|data| serde_json::from_str::<BTreeMap<String, ManifestItem>>(&data).ok())
+            .unwrap_or_default();
+
+        let trimmed = path.trim_end_matches('/');
+        let from_manifest = manifest
+            .into_iter()
+            .find(|(_, item)| item.slug.trim_end_matches('/') == trimmed)
+            .map(|(file, _)| PathBuf::from(file));
+        if let Some(file) = from_manifest.filter(|f| f.is_file()) {
+            return Some(file);
+        }
+
+        // Don't allow the request to escape the output directory.
+        let relative = Path::new(path.trim_start_matches('/'));
+        if relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return None;
+        }
+
+        let file = self.output.join(relative);
+        if file.is_dir() {
+            Some(file.join("index.html")).filter(|f| f.is_file())
+        } else {
+            Some(file).filter(|f| f.is_file())
+        }
+    }
+
+    /// Hold the connection open as a stream of server-sent events, sending a message whenever the
+    /// site is rebuilt.
+    fn live_reload(&self, mut stream: TcpStream) -> Result<()> {
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n"
+        )?;
+        stream.flush()?;
+
+        let mut seen = self.reload.generation();
+        loop {
+            let generation = self.reload.wait(seen, KEEPALIVE_INTERVAL);
+            let message = if generation == seen {
+                ": keepalive\n\n"
+            } else {
+                "data: reload\n\n"
+            };
+            seen = generation;
+
+            if stream
+                .write_all(message.as_bytes())
+                .and_then(|_| stream.flush())
+                .is_err()
+            {
+                // The browser went away.
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Add the live reload script to an HTML page, just before the closing body tag if there is one.
+fn inject_live_reload(body: Vec<u8>) -> Vec<u8> {
+    let mut html = match String::from_utf8(body) {
+        Ok(html) => html,
+        Err(e) => return e.into_bytes(),
+    };
+
+    match html.rfind("</body>") {
+        Some(pos) => html.insert_str(pos, LIVE_RELOAD_SCRIPT),
+        None => html.push_str(LIVE_RELOAD_SCRIPT),
+    }
+
+    html.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_path() {
+        assert_eq!(percent_decode("/a%20b/c%2Fd"), "/a b/c/d");
+        assert_eq!(percent_decode("/100%"), "/100%");
+        assert_eq!(percent_decode("/caf%C3%A9"), "/café");
+    }
+
+    #[test]
+    fn inject_script() {
+        let html = inject_live_reload(b"<html><body>Hi</body></html>".to_vec());
+        assert_eq!(
+            String::from_utf8(html).unwrap(),
+            format!("<html><body>Hi{LIVE_RELOAD_SCRIPT}</body></html>")
+        );
+    }
+}
@@ -0,0 +1,206 @@
+//! `sitemap.xml`, listing the exported pages for search engines.
+
+use std::fmt::Write;
+
+use chrono::{SecondsFormat, TimeZone, Utc};
+use itertools::Itertools;
+
+use crate::html;
+
+/// The most URLs that the sitemap protocol allows in one file. Larger sites get a sitemap index
+/// pointing to several sitemaps.
+pub const MAX_SITEMAP_URLS: usize = 50_000;
+
+/// A page to list in the sitemap.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SitemapEntry {
+    pub slug: String,
+    pub edited_time: u64,
+    /// The priority from the script, if it set one
+    pub priority: Option<f64>,
+}
+
+/// A sitemap file to write, with its path relative to the output directory.
+#[derive(Debug)]
+pub struct SitemapFile {
+    pub path: String,
+    pub contents: String,
+}
+
+fn lastmod(time: u64) -> Option<String> {
+    (time > 0)
+        .then(|| Utc.timestamp_millis_opt(time as i64).single())
+        .flatten()
+        .map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+fn urlset(origin: &str, entries: &[&SitemapEntry]) -> String {
+    let mut output = String::new();
+    writeln!(output, r#"<?xml version="1.0" encoding="utf-8"?>"#).unwrap();
+    writeln!(
+        output,
+        r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#
+    )
+    .unwrap();
+
+    for entry in entries {
+        let url = format!("{origin}/{}", entry.slug.trim_start_matches('/'));
+        writeln!(output, "  <url>").unwrap();
+        writeln!(output, "    <loc>{}</loc>", html::escape(&url)).unwrap();
+        if let Some(lastmod) = lastmod(entry.edited_time) {
+            writeln!(output, "    <lastmod>{lastmod}</lastmod>").unwrap();
+        }
+        if let Some(priority) = entry.priority {
+            writeln!(
+                output,
+                "    <priority>{:.1}</priority>",
+                priority.clamp(0.0, 1.0)
+            )
+            .unwrap();
+        }
+        writeln!(output, "  </url>").unwrap();
+    }
+
+    writeln!(output, "</urlset>").unwrap();
+    output
+}
+
+/// Create `sitemap.xml` for the pages. When there are more than `max_urls` pages, they're split
+/// across `sitemap-1.xml`, `sitemap-2.xml`, and so on, and `sitemap.xml` is an index of those.
+/// `origin` is the scheme and host that the site is served from, and `base_url` is the path of the
+/// output directory within it.
+pub fn make_sitemaps(
+    origin: &str,
+    base_url: &str,
+    entries: &[SitemapEntry],
+    max_urls: usize,
+) -> Vec<SitemapFile> {
+    let origin = origin.trim_end_matches('/');
+    let entries = entries
+        .iter()
+        .sorted_by(|a, b| a.slug.cmp(&b.slug))
+        .collect::<Vec<_>>();
+
+    if entries.len() <= max_urls {
+        return vec![SitemapFile {
+            path: String::from("sitemap.xml"),
+            contents: urlset(origin, &entries),
+        }];
+    }
+
+    let site_url = format!("{origin}/{}", base_url.trim_start_matches('/'));
+    let mut index = String::new();
+    writeln!(index, r#"<?xml version="1.0" encoding="utf-8"?>"#).unwrap();
+    writeln!(
+        index,
+        r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#
+    )
+    .unwrap();
+
+    let mut files = Vec::new();
+    for (i, chunk) in entries.chunks(max_urls).enumerate() {
+        let path = format!("sitemap-{}.xml", i + 1);
+        writeln!(index, "  <sitemap>").unwrap();
+        writeln!(
+            index,
+            "    <loc>{}</loc>",
+            html::escape(&format!("{site_url}{path}"))
+        )
+        .unwrap();
+        let edited_time = chunk.iter().map(|e| e.edited_time).max().unwrap_or(0);
+        if let Some(lastmod) = lastmod(edited_time) {
+            writeln!(index, "    <lastmod>{lastmod}</lastmod>").unwrap();
+        }
+        writeln!(index, "  </sitemap>").unwrap();
+
+        files.push(SitemapFile {
+            path,
+            contents: urlset(origin, chunk),
+        });
+    }
+
+    writeln!(index, "</sitemapindex>").unwrap();
+    files.insert(
+        0,
+        SitemapFile {
+            path: String::from("sitemap.xml"),
+            contents: index,
+        },
+    );
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+
+    fn entry(slug: &str, edited_time: u64, priority: Option<f64>) -> SitemapEntry {
+        SitemapEntry {
+            slug: slug.to_string(),
+            edited_time,
+            priority,
+        }
+    }
+
+    #[test]
+    fn single_sitemap() {
+        let entries = [
+            entry("/notes/b", 0, Some(1.5)),
+            entry("/notes/a", 1_700_000_000_000, None),
+        ];
+        let files = make_sitemaps("https://example.com/", "/notes/", &entries, 10);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, "sitemap.xml");
+        assert_eq!(
+            files[0].contents,
+            indoc! {r#"
+                <?xml version="1.0" encoding="utf-8"?>
+                <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                  <url>
+                    <loc>https://example.com/notes/a</loc>
+                    <lastmod>2023-11-14T22:13:20Z</lastmod>
+                  </url>
+                  <url>
+                    <loc>https://example.com/notes/b</loc>
+                    <priority>1.0</priority>
+                  </url>
+                </urlset>
+            "#}
+        );
+    }
+
+    #[test]
+    fn sitemap_index() {
+        let entries = [
+            entry("/a", 1_700_000_000_000, None),
+            entry("/b", 1_700_000_100_000, None),
+            entry("/c", 1_700_000_000_000, None),
+        ];
+        let files = make_sitemaps("https://example.com", "/", &entries, 2);
+        let paths = files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, vec!["sitemap.xml", "sitemap-1.xml", "sitemap-2.xml"]);
+
+        assert_eq!(
+            files[0].contents,
+            indoc! {r#"
+                <?xml version="1.0" encoding="utf-8"?>
+                <sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                  <sitemap>
+                    <loc>https://example.com/sitemap-1.xml</loc>
+                    <lastmod>2023-11-14T22:15:00Z</lastmod>
+                  </sitemap>
+                  <sitemap>
+                    <loc>https://example.com/sitemap-2.xml</loc>
+                    <lastmod>2023-11-14T22:13:20Z</lastmod>
+                  </sitemap>
+                </sitemapindex>
+            "#}
+        );
+        assert_eq!(files[2].contents.matches("<url>").count(), 1);
+        assert!(files[2]
+            .contents
+            .contains("<loc>https://example.com/c</loc>"));
+    }
+}
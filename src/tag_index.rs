@@ -0,0 +1,317 @@
+//! Generated pages listing the exported pages with each tag, and an overview of all the tags.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::OutputFormat,
+    html,
+    make_pages::{title_to_slug, ListingPage},
+    markdown,
+    namespace::IndexEntry,
+};
+
+fn default_dir() -> String {
+    String::from("tags")
+}
+
+fn default_overview_title() -> String {
+    String::from("Tags")
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TagPagesConfig {
+    /// The template for the tag pages and the overview. Defaults to the default template.
+    pub template: Option<PathBuf>,
+    /// The directory, relative to the output directory and the base URL, that the tag pages are
+    /// written to. The overview is written next to it, as `tags.html` for the default "tags".
+    #[serde(default = "default_dir")]
+    pub dir: String,
+    /// The title of the overview page. Defaults to "Tags".
+    #[serde(default = "default_overview_title")]
+    pub overview_title: String,
+}
+
+/// An exported page, as far as the tag pages care.
+pub struct TagMember<'a> {
+    pub title: &'a str,
+    pub slug: &'a str,
+    pub tags: &'a [String],
+    pub create_time: u64,
+    pub edit_time: u64,
+}
+
+/// A tag and the number of exported pages that have it, for the overview and tag clouds.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct TagCount {
+    pub tag: String,
+    /// The tag's page
+    pub slug: String,
+    pub count: usize,
+}
+
+/// A generated page listing the pages with a tag.
+#[derive(Debug)]
+pub struct TagPage {
+    pub tag: String,
+    /// The path of the page relative to the output directory, without the extension
+    pub url_name: String,
+    pub slug: String,
+    pub create_time: u64,
+    pub edit_time: u64,
+    pub pages: Vec<IndexEntry>,
+}
+
+/// The generated overview of all the tags.
+#[derive(Debug)]
+pub struct TagsOverview {
+    pub url_name: String,
+    pub slug: String,
+    pub create_time: u64,
+    pub edit_time: u64,
+    pub tags: Vec<TagCount>,
+}
+
+fn render_list<'a>(
+    class: &str,
+    format: OutputFormat,
+    items: impl Iterator<Item = (&'a str, &'a str, Option<usize>)>,
+) -> String {
+    match format {
+        OutputFormat::Html => {
+            let items = items
+                .map(|(title, slug, count)| {
+                    let count = count
+                        .map(|c| format!(" <span class=\"tag-count\">({c})</span>"))
+                        .unwrap_or_default();
+                    format!(
+                        "  <li><a href=\"{slug}\">{title}</a>{count}</li>\n",
+                        slug = html::escape(slug),
+                        title = html::escape(title)
+                    )
+                })
+                .join("");
+            format!("<ul class=\"{class}\">\n{items}</ul>\n")
+        }
+        OutputFormat::Markdown => items
+            .map(|(title, slug, count)| {
+                let count = count.map(|c| format!(" ({c})")).unwrap_or_default();
+                format!(
+                    "- [{title}]({slug}){count}\n",
+                    title = markdown::escape(title),
+                    slug = markdown::escape_url(slug)
+                )
+            })
+            .join(""),
+    }
+}
+
+impl TagPage {
+    /// Render the list of pages with the tag.
+    pub fn render(&self, format: OutputFormat) -> String {
+        render_list(
+            "tag-index",
+            format,
+            self.pages
+                .iter()
+                .map(|entry| (entry.title.as_str(), entry.slug.as_str(), None)),
+        )
+    }
+}
+
+impl TagsOverview {
+    /// Render the list of tags, with the number of pages for each.
+    pub fn render(&self, format: OutputFormat) -> String {
+        render_list(
+            "tags-overview",
+            format,
+            self.tags
+                .iter()
+                .map(|tag| (tag.tag.as_str(), tag.slug.as_str(), Some(tag.count))),
+        )
+    }
+}
+
+pub struct TagPages {
+    pub pages: Vec<TagPage>,
+    pub overview: TagsOverview,
+}
+
+impl TagPages {
+    /// Gather the tags of the exported pages. Tags that differ only in case or punctuation share
+    /// a page, and tags without any letters or digits to make a URL from are skipped. `make_slug`
+    /// turns a path relative to the output directory into a URL.
+    pub fn new(dir: &str, members: &[TagMember], make_slug: impl Fn(&str) -> String) -> TagPages {
+        let dir = dir.trim_matches('/');
+
+        // Keyed by the tag's URL name, so the pages come out in a stable order.
+        let mut pages: BTreeMap<String, TagPage> = BTreeMap::new();
+        for member in members {
+            for tag in member.tags.iter().unique_by(|tag| title_to_slug(tag)) {
+                let tag_slug = title_to_slug(tag);
+                if tag_slug.is_empty() {
+                    continue;
+                }
+
+                let page = pages.entry(tag_slug).or_insert_with_key(|tag_slug| {
+                    let url_name = format!("{dir}/{tag_slug}");
+                    TagPage {
+                        tag: tag.to_string(),
+                        slug: make_slug(&url_name),
+                        url_name,
+                        create_time: u64::MAX,
+                        edit_time: 0,
+                        pages: Vec::new(),
+                    }
+                });
+
+                // Use the same spelling of the tag no matter what order the pages come in.
+                if tag.as_str() < page.tag.as_str() {
+                    page.tag = tag.to_string();
+                }
+                page.create_time = page.create_time.min(member.create_time);
+                page.edit_time = page.edit_time.max(member.edit_time);
+                page.pages.push(IndexEntry {
+                    title: member.title.to_string(),
+                    slug: member.slug.to_string(),
+                });
+            }
+        }
+
+        let mut pages = pages.into_values().collect::<Vec<_>>();
+        for page in pages.iter_mut() {
+            page.pages.sort_by(|a, b| {
+                (a.title.to_lowercase(), &a.slug).cmp(&(b.title.to_lowercase(), &b.slug))
+            });
+        }
+
+        let overview = TagsOverview {
+            slug: make_slug(dir),
+            url_name: dir.to_string(),
+            create_time: pages.iter().map(|page| page.create_time).min().unwrap_or(0),
+            edit_time: pages.iter().map(|page| page.edit_time).max().unwrap_or(0),
+            tags: pages
+                .iter()
+                .map(|page| TagCount {
+                    tag: page.tag.clone(),
+                    slug: page.slug.clone(),
+                    count: page.pages.len(),
+                })
+                .sorted_by_key(|tag| tag.tag.to_lowercase())
+                .collect(),
+        };
+
+        TagPages { pages, overview }
+    }
+
+    /// Render the tag pages, followed by the overview.
+    pub fn render(&self, overview_title: &str, format: OutputFormat) -> Vec<ListingPage> {
+        let overview = ListingPage {
+            title: overview_title.to_string(),
+            url_name: self.overview.url_name.clone(),
+            slug: self.overview.slug.clone(),
+            body: self.overview.render(format),
+            create_time: self.overview.create_time,
+            edit_time: self.overview.edit_time,
+            tag_counts: self.overview.tags.clone(),
+        };
+
+        self.pages
+            .iter()
+            .map(|page| ListingPage {
+                title: page.tag.clone(),
+                url_name: page.url_name.clone(),
+                slug: page.slug.clone(),
+                body: page.render(format),
+                create_time: page.create_time,
+                edit_time: page.edit_time,
+                tag_counts: Vec::new(),
+            })
+            .chain(std::iter::once(overview))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    #[test]
+    fn tag_pages() {
+        let rust_tags = tags(&["Rust", "programming"]);
+        let async_tags = tags(&["rust", "Rust"]);
+        let symbol_tags = tags(&["#"]);
+        let members = [
+            TagMember {
+                title: "Rust Notes",
+                slug: "/rust_notes",
+                tags: &rust_tags,
+                create_time: 5,
+                edit_time: 10,
+            },
+            TagMember {
+                title: "Async",
+                slug: "/async",
+                tags: &async_tags,
+                create_time: 3,
+                edit_time: 20,
+            },
+            TagMember {
+                title: "Symbols",
+                slug: "/symbols",
+                tags: &symbol_tags,
+                create_time: 1,
+                edit_time: 1,
+            },
+        ];
+
+        let tags = TagPages::new("/tags/", &members, |name| format!("/{name}"));
+
+        let names = tags
+            .pages
+            .iter()
+            .map(|page| (page.tag.as_str(), page.url_name.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![("programming", "tags/programming"), ("Rust", "tags/rust")]
+        );
+
+        let rust = &tags.pages[1];
+        assert_eq!(rust.slug, "/tags/rust");
+        assert_eq!(rust.create_time, 3);
+        assert_eq!(rust.edit_time, 20);
+        assert_eq!(
+            rust.render(OutputFormat::Markdown),
+            "- [Async](/async)\n- [Rust Notes](/rust_notes)\n"
+        );
+
+        assert_eq!(tags.overview.slug, "/tags");
+        assert_eq!(tags.overview.edit_time, 20);
+        assert_eq!(
+            tags.overview.tags,
+            vec![
+                TagCount {
+                    tag: String::from("programming"),
+                    slug: String::from("/tags/programming"),
+                    count: 1,
+                },
+                TagCount {
+                    tag: String::from("Rust"),
+                    slug: String::from("/tags/rust"),
+                    count: 2,
+                },
+            ]
+        );
+        assert_eq!(
+            tags.overview.render(OutputFormat::Html),
+            "<ul class=\"tags-overview\">\n  <li><a href=\"/tags/programming\">programming</a> <span class=\"tag-count\">(1)</span></li>\n  <li><a href=\"/tags/rust\">Rust</a> <span class=\"tag-count\">(2)</span></li>\n</ul>\n"
+        );
+    }
+}
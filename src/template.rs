@@ -1,4 +1,9 @@
-use std::{borrow::Cow, fs::File, io::Read, path::Path};
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
 
 use ahash::HashMap;
 use chrono::TimeZone;
@@ -6,6 +11,8 @@ use eyre::{Result, WrapErr};
 use handlebars::*;
 use itertools::Itertools;
 
+use crate::{config::Config, image};
+
 handlebars_helper!(join: |list: array, sep: str| {
   list.iter().filter_map(|s| {
     match s {
@@ -20,7 +27,7 @@ handlebars_helper!(iso_time: |fmt:str, t: i64| { chrono::Utc.timestamp(t / 1000,
 handlebars_helper!(format_time: |fmt:str, t: i64| { chrono::Utc.timestamp(t / 1000, 0).format(fmt).to_string() });
 handlebars_helper!(replace: |content:str, pattern: str, replacement:str | content.replace(pattern, replacement) );
 
-pub fn create(path: Option<&Path>) -> Result<Handlebars> {
+pub fn create(path: Option<&Path>) -> Result<Handlebars<'static>> {
     let mut hbars = handlebars::Handlebars::new();
     if let Some(path) = path {
         let mut template_file = match File::open(path).with_context(|| format!("{path:?}")) {
@@ -53,6 +60,31 @@ pub fn create(path: Option<&Path>) -> Result<Handlebars> {
     Ok(hbars)
 }
 
+/// Create the template registry with the templates from the configuration.
+pub fn create_registry(config: &Config) -> Result<DedupingTemplateRegistry<'static>> {
+    let hbars = create(config.template.as_deref())?;
+    let mut templates = DedupingTemplateRegistry::new(hbars);
+    if let Some(path) = config.template.as_deref() {
+        templates.add_file_with_key("default".to_string(), path)?;
+    }
+
+    if let Some(path) = config
+        .pic_store
+        .as_ref()
+        .and_then(|ps| ps.template.as_deref())
+    {
+        templates.add_file_with_key("default_picture".to_string(), path)?;
+    } else {
+        // Use the default picture template if none was provided.
+        templates.add_template(
+            "default_picture".to_string(),
+            image::DEFAULT_PICTURE_TEMPLATE.to_string(),
+        )?;
+    }
+
+    Ok(templates)
+}
+
 pub struct DedupingTemplateRegistry<'a> {
     handlebars: Handlebars<'a>,
     templates: HashMap<String, String>,
@@ -68,8 +100,13 @@ impl<'a> DedupingTemplateRegistry<'a> {
         }
     }
 
-    pub fn into_inner(self) -> Handlebars<'a> {
-        self.handlebars
+    pub fn handlebars(&self) -> &Handlebars<'a> {
+        &self.handlebars
+    }
+
+    /// The paths of all the template files that have been loaded.
+    pub fn files(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        self.template_files.keys().map(PathBuf::from)
     }
 
     pub fn add_file_with_key(&mut self, key: String, path: &Path) -> Result<String> {
@@ -81,7 +118,9 @@ impl<'a> DedupingTemplateRegistry<'a> {
 
         let template =
             std::fs::read_to_string(path).with_context(|| format!("{}", path.display()))?;
-        self.add_template(key, template)
+        let key = self.add_template(key, template)?;
+        self.template_files.insert(p.into_owned(), key.clone());
+        Ok(key)
     }
 
     pub fn add_file(&mut self, path: &Path) -> Result<String> {
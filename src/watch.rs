@@ -0,0 +1,420 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use ahash::{HashMap, HashSet};
+use eyre::{eyre, Result, WrapErr};
+
+use crate::{
+    config::{Config, PkmProduct},
+    graph::ParsedPage,
+    logseq::{db::MetadataDb, is_page_file, LogseqGraph},
+    make_pages::{make_pages_from_script, ExportInput},
+    mentions::{find_unlinked_mentions, MentionMatcher},
+    page::{is_query_block, normalize_title},
+    parse_string::Expression,
+    references::{collect_references, Reference},
+    script::PageScript,
+    syntax_highlight::Highlighter,
+    template::{create_registry, DedupingTemplateRegistry},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+struct PageFile {
+    modified: Option<SystemTime>,
+    /// The same blake3 hash of the contents that the metadata database keys pages by, so that a
+    /// save without any changes doesn't count as a change.
+    hash: Option<blake3::Hash>,
+    is_journal: bool,
+}
+
+/// List the page files in the graph along with their modification times and hashes. Only the files
+/// whose modification time differs from `previous` are read again to update the hash.
+fn scan_page_files(
+    graph: &LogseqGraph,
+    previous: &HashMap<PathBuf, PageFile>,
+) -> Result<HashMap<PathBuf, PageFile>> {
+    let mut files = HashMap::default();
+    for (dir, is_journal) in graph.page_directories() {
+        for entry in std::fs::read_dir(&dir).with_context(|| format!("{dir:?}"))? {
+            let path = entry?.path();
+            if is_page_file(&path) {
+                let modified = modified_time(&path);
+                let hash = match previous.get(&path) {
+                    Some(old) if old.modified == modified => old.hash,
+                    _ => std::fs::read(&path)
+                        .ok()
+                        .map(|contents| blake3::hash(&contents)),
+                };
+                files.insert(
+                    path,
+                    PageFile {
+                        modified,
+                        hash,
+                        is_journal,
+                    },
+                );
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Add the normalized names that can be used to link to the page, and the uids of its blocks.
+fn page_names(page: &ParsedPage, output: &mut HashSet<String>) {
+    if let Some(root) = page.blocks.get(&page.root_block) {
+        output.extend(
+            root.page_title
+                .iter()
+                .chain(root.original_title.iter())
+                .cloned()
+                .chain(root.aliases())
+                .map(|name| normalize_title(&name)),
+        );
+    }
+
+    output.extend(
+        page.blocks
+            .values()
+            .filter(|block| !block.uid.is_empty())
+            .map(|block| block.uid.clone()),
+    );
+}
+
+fn expression_references(expressions: &[Expression], output: &mut HashSet<String>) {
+    let mut references = Vec::new();
+    collect_references(expressions, &mut references);
+    output.extend(references.into_iter().map(|r| match r {
+        Reference::Page(title) => normalize_title(title),
+        Reference::Block(uid) => uid.to_string(),
+    }));
+
+    for expr in expressions {
+        match expr {
+            Expression::PageEmbed(title) => {
+                output.insert(normalize_title(title));
+            }
+            Expression::BlockEmbed(uid) => {
+                output.insert(uid.to_string());
+            }
+            _ => {
+                let contained = expr.contained_expressions();
+                if !contained.is_empty() {
+                    expression_references(contained, output);
+                }
+            }
+        }
+    }
+}
+
+/// Add the normalized page titles and the block uids that the page refers to.
+fn page_references(page: &ParsedPage, output: &mut HashSet<String>) {
+    for block in page.blocks.values() {
+        output.extend(block.tags.iter().map(|tag| normalize_title(tag)));
+        expression_references(block.contents.borrow_parsed(), output);
+    }
+}
+
+struct Watcher<'a> {
+    config: &'a Config,
+    highlighter: &'a Highlighter,
+    metadata_db: Option<MetadataDb>,
+    script: PageScript,
+    templates: DedupingTemplateRegistry<'static>,
+
+    graph: LogseqGraph,
+    pages: Vec<ParsedPage>,
+    page_files: HashMap<PathBuf, PageFile>,
+    /// The script and template files, and their modification times.
+    sources: HashMap<PathBuf, Option<SystemTime>>,
+    /// The pages that mention each page without linking to it, if unlinked mentions are enabled.
+    mentions: HashMap<PathBuf, HashSet<PathBuf>>,
+}
+
+impl<'a> Watcher<'a> {
+    fn timestamp_db(&self) -> Option<MetadataDb> {
+        if self.config.track_logseq_timestamps {
+            self.metadata_db.clone()
+        } else {
+            None
+        }
+    }
+
+    fn scan_sources(&self) -> HashMap<PathBuf, Option<SystemTime>> {
+        std::iter::once(self.config.script.clone())
+            .chain(self.templates.files())
+            .map(|path| {
+                let modified = modified_time(&path);
+                (path, modified)
+            })
+            .collect()
+    }
+
+    /// Map each page to the pages that mention it without linking to it.
+    fn find_mentions(&self) -> Result<HashMap<PathBuf, HashSet<PathBuf>>> {
+        if self.config.unlinked_mentions.is_none() {
+            return Ok(HashMap::default());
+        }
+
+        let Some(matcher) = MentionMatcher::new(
+            self.pages
+                .iter()
+                .filter_map(|page| page.blocks.get(&page.root_block)),
+        )?
+        else {
+            return Ok(HashMap::default());
+        };
+
+        let block_paths = self
+            .pages
+            .iter()
+            .flat_map(|page| page.blocks.keys().map(|id| (*id, &page.path)))
+            .collect::<HashMap<_, _>>();
+
+        let mentions = find_unlinked_mentions(&self.pages, &matcher)
+            .into_iter()
+            .filter_map(|(target, blocks)| {
+                let target = (*block_paths.get(&target)?).clone();
+                let sources = blocks
+                    .iter()
+                    .filter_map(|block| block_paths.get(block))
+                    .map(|path| (*path).clone())
+                    .collect::<HashSet<_>>();
+                Some((target, sources))
+            })
+            .collect();
+
+        Ok(mentions)
+    }
+
+    fn export(&mut self, render_only: Option<HashSet<PathBuf>>) -> Result<()> {
+        let input = ExportInput {
+            pages: self.pages.clone(),
+            explicit_ordering: false,
+            render_only,
+        };
+
+        let (wrote, skipped) = make_pages_from_script(
+            input,
+            &self.script,
+            &mut self.templates,
+            self.highlighter,
+            self.config,
+            self.metadata_db.clone(),
+        )?;
+
+        // The script may have loaded new templates, so look for them again.
+        self.sources = self.scan_sources();
+
+        println!("Wrote {wrote} pages, skipped {skipped} up-to-date");
+        Ok(())
+    }
+
+    /// Find the pages whose output may be different after `changed` pages were updated and `old`
+    /// pages were replaced or removed.
+    fn affected_pages(
+        &self,
+        old: &[ParsedPage],
+        changed: &[PathBuf],
+        new_mentions: &HashMap<PathBuf, HashSet<PathBuf>>,
+    ) -> HashSet<PathBuf> {
+        let mut affected = changed.iter().cloned().collect::<HashSet<_>>();
+
+        let mut changed_names = HashSet::default();
+        let mut changed_references = HashSet::default();
+        for page in old.iter().chain(
+            self.pages
+                .iter()
+                .filter(|page| affected.contains(&page.path)),
+        ) {
+            page_names(page, &mut changed_names);
+            page_references(page, &mut changed_references);
+        }
+
+        let mut names = HashSet::default();
+        let mut references = HashSet::default();
+        for page in &self.pages {
+            names.clear();
+            references.clear();
+            page_names(page, &mut names);
+            page_references(page, &mut references);
+
+            // Pages that link to the changed pages may render their titles or contents, and pages
+            // linked from the changed pages have their backlinks updated.
+            if !references.is_disjoint(&changed_names) || !names.is_disjoint(&changed_references) {
+                affected.insert(page.path.clone());
+            }
+        }
+
+        // Query results can come from any page, so pages with queries are always updated.
+        affected.extend(
+            self.pages
+                .iter()
+                .filter(|page| page.blocks.values().any(is_query_block))
+                .map(|page| page.path.clone()),
+        );
+
+        for target in self.mentions.keys().chain(new_mentions.keys()) {
+            let old_sources = self.mentions.get(target);
+            let new_sources = new_mentions.get(target);
+            if old_sources != new_sources {
+                affected.insert(target.clone());
+            }
+
+            if changed.contains(target) {
+                affected.extend(old_sources.into_iter().flatten().cloned());
+                affected.extend(new_sources.into_iter().flatten().cloned());
+            }
+        }
+
+        affected
+    }
+
+    /// Check for changes and re-export if there are any. Returns true if anything was exported.
+    fn poll(&mut self) -> Result<bool> {
+        let sources_changed = self
+            .sources
+            .iter()
+            .any(|(path, modified)| modified_time(path) != *modified);
+
+        let page_files = scan_page_files(&self.graph, &self.page_files)?;
+        let changed = page_files
+            .iter()
+            .filter(|(path, file)| {
+                self.page_files
+                    .get(*path)
+                    .map(|old| old.hash.is_none() || old.hash != file.hash)
+                    .unwrap_or(true)
+            })
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        let removed = self
+            .page_files
+            .keys()
+            .filter(|path| !page_files.contains_key(*path))
+            .cloned()
+            .collect::<HashSet<_>>();
+
+        if !sources_changed && changed.is_empty() && removed.is_empty() {
+            // Files that were saved without changing still need their new times recorded, so
+            // that they aren't hashed again on every poll.
+            self.page_files = page_files;
+            return Ok(false);
+        }
+
+        // Read the changed pages before changing any state, so that if reading fails, the same
+        // changes are found again on the next poll.
+        let timestamp_db = self.timestamp_db();
+        let mut new_pages = Vec::new();
+        for is_journal in [false, true] {
+            let files = changed
+                .iter()
+                .filter(|path| {
+                    page_files
+                        .get(*path)
+                        .map(|file| file.is_journal == is_journal)
+                        .unwrap_or(false)
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if !files.is_empty() {
+                new_pages.extend(self.graph.read_files(&files, &timestamp_db, is_journal)?);
+            }
+        }
+
+        if sources_changed {
+            // Record the new times right away so that a script or template with an error isn't
+            // reloaded again until it is fixed. The pages aren't recorded yet, so they are read
+            // again on the next poll and exported with the working script.
+            self.sources = self.scan_sources();
+            let script = PageScript::compile(&self.config.script)?;
+            let templates = create_registry(self.config)?;
+            self.script = script;
+            self.templates = templates;
+        }
+
+        self.page_files = page_files;
+        let (old_pages, pages) = std::mem::take(&mut self.pages)
+            .into_iter()
+            .partition::<Vec<_>, _>(|page| {
+                removed.contains(&page.path) || changed.contains(&page.path)
+            });
+        self.pages = pages;
+        self.pages.extend(new_pages);
+
+        let mentions = self.find_mentions()?;
+        let render_only = if sources_changed {
+            None
+        } else {
+            Some(self.affected_pages(&old_pages, &changed, &mentions))
+        };
+        self.mentions = mentions;
+
+        println!(
+            "Detected changes in {} pages{}",
+            changed.len() + removed.len(),
+            if sources_changed {
+                " and the script or templates"
+            } else {
+                ""
+            }
+        );
+        self.export(render_only)?;
+        Ok(true)
+    }
+}
+
+/// Export the graph, and then keep watching the graph, script, and templates, re-exporting the
+/// affected pages whenever something changes. `on_rebuild` is called after each re-export.
+pub fn watch(
+    config: &Config,
+    templates: DedupingTemplateRegistry<'static>,
+    script: PageScript,
+    highlighter: &Highlighter,
+    metadata_db: Option<MetadataDb>,
+    mut on_rebuild: impl FnMut(),
+) -> Result<()> {
+    if !matches!(config.product, PkmProduct::Logseq) {
+        return Err(eyre!("Watch mode only supports Logseq graphs"));
+    }
+
+    let graph = LogseqGraph::open(config.path.clone())?;
+    let page_files = scan_page_files(&graph, &HashMap::default())?;
+
+    let mut watcher = Watcher {
+        config,
+        highlighter,
+        metadata_db,
+        script,
+        templates,
+        graph,
+        pages: Vec::new(),
+        page_files,
+        sources: HashMap::default(),
+        mentions: HashMap::default(),
+    };
+
+    let timestamp_db = watcher.timestamp_db();
+    watcher.pages = watcher.graph.read_all(&timestamp_db)?;
+    watcher.mentions = watcher.find_mentions()?;
+    watcher.export(None)?;
+
+    println!("Watching for changes...");
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+
+        match watcher.poll() {
+            Ok(true) => on_rebuild(),
+            Ok(false) => {}
+            Err(e) => eprintln!("Error: {e:?}"),
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use std::f64::consts::PI;
+
+use eyre::Result;
+use itertools::Itertools;
+
+use crate::html;
+
+/// Space to leave around the shapes when fitting the drawing to them.
+const PADDING: f64 = 20.0;
+
+const SVG_STYLE: &str = r##"<style>
+.wb-shape { fill: #ffffff; stroke: currentColor; stroke-width: 2; }
+.wb-line, .wb-path { fill: none; stroke: currentColor; stroke-width: 2; stroke-linecap: round; stroke-linejoin: round; }
+.wb-text, .wb-label { fill: currentColor; stroke: none; font-family: sans-serif; }
+.wb-portal-border { fill: none; stroke: #a0a0a0; stroke-width: 1; }
+.wb-portal { width: 100%; height: 100%; overflow: auto; padding: 8px; box-sizing: border-box; }
+</style>"##;
+
+const SVG_DEFS: &str = r##"<defs><marker id="wb-arrow" viewBox="0 0 10 10" refX="9" refY="5" markerWidth="6" markerHeight="6" orient="auto-start-reverse"><path d="M 0 0 L 10 5 L 0 10 z" fill="currentColor" /></marker></defs>"##;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortalKind {
+    Page,
+    Block,
+}
+
+/// A reference to a page or block, shown inside the whiteboard.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Portal {
+    pub kind: PortalKind,
+    /// The title of the page, or the UUID of the block.
+    pub target: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShapeKind {
+    Box {
+        radius: f64,
+    },
+    Ellipse,
+    /// A regular polygon with this many sides.
+    Polygon {
+        sides: usize,
+    },
+    Text {
+        text: String,
+        font_size: f64,
+    },
+    /// A line or arrow between two points, relative to the shape's position.
+    Line {
+        start: (f64, f64),
+        end: (f64, f64),
+        start_arrow: bool,
+        end_arrow: bool,
+    },
+    /// A freehand drawing, relative to the shape's position.
+    Path {
+        points: Vec<(f64, f64)>,
+        highlighter: bool,
+    },
+    Portal(Portal),
+    Image {
+        src: String,
+    },
+    /// Embedded web content such as videos, shown as a link.
+    Link {
+        url: String,
+    },
+    Html {
+        html: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shape {
+    pub kind: ShapeKind,
+    pub point: (f64, f64),
+    pub size: (f64, f64),
+    /// The rotation in radians
+    pub rotation: f64,
+    pub opacity: f64,
+    pub fill: Option<String>,
+    pub stroke: Option<String>,
+    pub stroke_width: Option<f64>,
+    pub label: String,
+}
+
+/// The shapes on a whiteboard, in drawing order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Whiteboard {
+    pub shapes: Vec<Shape>,
+}
+
+/// Only allow plain color values, since they are written into style attributes.
+fn color(value: Option<&str>) -> Option<&str> {
+    value.filter(|v| {
+        !v.is_empty()
+            && !v.starts_with("var(")
+            && v.chars().all(|c| {
+                c.is_ascii_alphanumeric() || matches!(c, '#' | '(' | ')' | ',' | '.' | '%' | ' ')
+            })
+    })
+}
+
+impl Shape {
+    /// The area the shape covers, as (min x, min y, max x, max y).
+    fn bounds(&self) -> (f64, f64, f64, f64) {
+        let (x, y) = self.point;
+        let relative_points = match &self.kind {
+            ShapeKind::Line { start, end, .. } => vec![*start, *end],
+            ShapeKind::Path { points, .. } if !points.is_empty() => points.clone(),
+            _ => vec![(0.0, 0.0), self.size],
+        };
+
+        relative_points.iter().fold(
+            (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+            |(min_x, min_y, max_x, max_y), (px, py)| {
+                (
+                    min_x.min(x + px),
+                    min_y.min(y + py),
+                    max_x.max(x + px),
+                    max_y.max(y + py),
+                )
+            },
+        )
+    }
+
+    fn style(&self) -> String {
+        let mut style = Vec::new();
+        if let Some(fill) = color(self.fill.as_deref()) {
+            style.push(format!("fill: {fill}"));
+        }
+        if let Some(stroke) = color(self.stroke.as_deref()) {
+            style.push(format!("stroke: {stroke}"));
+        }
+        if let Some(width) = self.stroke_width {
+            style.push(format!("stroke-width: {width}"));
+        }
+
+        if style.is_empty() {
+            String::new()
+        } else {
+            format!(r##" style="{}""##, style.join("; "))
+        }
+    }
+
+    fn render_label(&self, x: f64, y: f64) -> String {
+        if self.label.is_empty() {
+            return String::new();
+        }
+
+        format!(
+            r##"<text class="wb-label" x="{x}" y="{y}" text-anchor="middle" dominant-baseline="middle">{}</text>"##,
+            html::escape(&self.label)
+        )
+    }
+
+    fn render(&self, render_portal: &impl Fn(&Portal) -> Result<String>) -> Result<String> {
+        let (w, h) = self.size;
+        let style = self.style();
+        let body = match &self.kind {
+            ShapeKind::Box { radius } => format!(
+                r##"<rect class="wb-shape" width="{w}" height="{h}" rx="{radius}"{style} />{}"##,
+                self.render_label(w / 2.0, h / 2.0)
+            ),
+            ShapeKind::Ellipse => format!(
+                r##"<ellipse class="wb-shape" cx="{cx}" cy="{cy}" rx="{cx}" ry="{cy}"{style} />{}"##,
+                self.render_label(w / 2.0, h / 2.0),
+                cx = w / 2.0,
+                cy = h / 2.0,
+            ),
+            ShapeKind::Polygon { sides } => {
+                let sides = (*sides).max(3);
+                let points = (0..sides)
+                    .map(|i| {
+                        let angle = -PI / 2.0 + 2.0 * PI * i as f64 / sides as f64;
+                        format!(
+                            "{},{}",
+                            w / 2.0 + w / 2.0 * angle.cos(),
+                            h / 2.0 + h / 2.0 * angle.sin()
+                        )
+                    })
+                    .join(" ");
+                format!(
+                    r##"<polygon class="wb-shape" points="{points}"{style} />{}"##,
+                    self.render_label(w / 2.0, h / 2.0)
+                )
+            }
+            ShapeKind::Text { text, font_size } => {
+                let lines = text
+                    .lines()
+                    .map(|line| {
+                        format!(
+                            r##"<tspan x="0" dy="{dy}">{}</tspan>"##,
+                            html::escape(line),
+                            dy = font_size * 1.2
+                        )
+                    })
+                    .join("");
+                format!(r##"<text class="wb-text" font-size="{font_size}"{style}>{lines}</text>"##)
+            }
+            ShapeKind::Line {
+                start,
+                end,
+                start_arrow,
+                end_arrow,
+            } => {
+                let marker_start = if *start_arrow {
+                    r##" marker-start="url(#wb-arrow)""##
+                } else {
+                    ""
+                };
+                let marker_end = if *end_arrow {
+                    r##" marker-end="url(#wb-arrow)""##
+                } else {
+                    ""
+                };
+
+                format!(
+                    r##"<line class="wb-line" x1="{}" y1="{}" x2="{}" y2="{}"{marker_start}{marker_end}{style} />{}"##,
+                    start.0,
+                    start.1,
+                    end.0,
+                    end.1,
+                    self.render_label((start.0 + end.0) / 2.0, (start.1 + end.1) / 2.0)
+                )
+            }
+            ShapeKind::Path {
+                points,
+                highlighter,
+            } => {
+                let points = points.iter().map(|(x, y)| format!("{x},{y}")).join(" ");
+                let highlight = if *highlighter {
+                    r##" stroke-width="12" stroke-opacity="0.5""##
+                } else {
+                    ""
+                };
+                format!(r##"<polyline class="wb-path" points="{points}"{highlight}{style} />"##)
+            }
+            ShapeKind::Portal(portal) => format!(
+                r##"<rect class="wb-portal-border" width="{w}" height="{h}" rx="4" /><foreignObject width="{w}" height="{h}"><div xmlns="http://www.w3.org/1999/xhtml" class="wb-portal">{}</div></foreignObject>"##,
+                render_portal(portal)?
+            ),
+            ShapeKind::Image { src } => format!(
+                r##"<image href="{}" width="{w}" height="{h}" />"##,
+                html::escape(src)
+            ),
+            ShapeKind::Link { url } => format!(
+                r##"<a href="{url}"><rect class="wb-shape" width="{w}" height="{h}"{style} /><text class="wb-label" x="{x}" y="{y}" text-anchor="middle" dominant-baseline="middle">{url}</text></a>"##,
+                url = html::escape(url),
+                x = w / 2.0,
+                y = h / 2.0,
+            ),
+            ShapeKind::Html { html } => format!(
+                r##"<foreignObject width="{w}" height="{h}"><div xmlns="http://www.w3.org/1999/xhtml">{html}</div></foreignObject>"##
+            ),
+        };
+
+        let (x, y) = self.point;
+        let rotate = if self.rotation == 0.0 {
+            String::new()
+        } else {
+            format!(
+                " rotate({} {} {})",
+                self.rotation.to_degrees(),
+                w / 2.0,
+                h / 2.0
+            )
+        };
+        let opacity = if self.opacity < 1.0 {
+            format!(r##" opacity="{}""##, self.opacity)
+        } else {
+            String::new()
+        };
+
+        Ok(format!(
+            r##"<g transform="translate({x} {y}){rotate}"{opacity}>{body}</g>"##
+        ))
+    }
+}
+
+impl Whiteboard {
+    /// Draw the whiteboard as an inline SVG, fitted to its shapes. `render_portal` renders
+    /// the HTML shown inside page and block portals.
+    pub fn render_svg(&self, render_portal: impl Fn(&Portal) -> Result<String>) -> Result<String> {
+        let (min_x, min_y, max_x, max_y) = self
+            .shapes
+            .iter()
+            .map(|shape| shape.bounds())
+            .reduce(|a, b| (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3)))
+            .unwrap_or((0.0, 0.0, 0.0, 0.0));
+
+        let x = min_x - PADDING;
+        let y = min_y - PADDING;
+        let width = max_x - min_x + PADDING * 2.0;
+        let height = max_y - min_y + PADDING * 2.0;
+
+        let shapes = self
+            .shapes
+            .iter()
+            .map(|shape| shape.render(&render_portal))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(format!(
+            "<svg class=\"whiteboard\" xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{x} {y} {width} {height}\" width=\"{width}\" style=\"max-width: 100%; height: auto\">\n{SVG_STYLE}\n{SVG_DEFS}\n{}\n</svg>\n",
+            shapes.join("\n")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shape(kind: ShapeKind, point: (f64, f64), size: (f64, f64)) -> Shape {
+        Shape {
+            kind,
+            point,
+            size,
+            rotation: 0.0,
+            opacity: 1.0,
+            fill: None,
+            stroke: None,
+            stroke_width: None,
+            label: String::new(),
+        }
+    }
+
+    #[test]
+    fn render() {
+        let whiteboard = Whiteboard {
+            shapes: vec![
+                Shape {
+                    label: String::from("A <box>"),
+                    fill: Some(String::from("red")),
+                    ..shape(ShapeKind::Box { radius: 0.0 }, (0.0, 0.0), (100.0, 50.0))
+                },
+                shape(
+                    ShapeKind::Line {
+                        start: (0.0, 0.0),
+                        end: (100.0, 0.0),
+                        start_arrow: false,
+                        end_arrow: true,
+                    },
+                    (100.0, 25.0),
+                    (0.0, 0.0),
+                ),
+                shape(
+                    ShapeKind::Portal(Portal {
+                        kind: PortalKind::Page,
+                        target: String::from("Other"),
+                    }),
+                    (200.0, 0.0),
+                    (100.0, 100.0),
+                ),
+            ],
+        };
+
+        let svg = whiteboard
+            .render_svg(|portal| Ok(format!("<a href=\"/other\">{}</a>", portal.target)))
+            .unwrap();
+
+        assert!(svg.contains(r##"viewBox="-20 -20 340 140""##));
+        assert!(svg.contains(
+            r##"<rect class="wb-shape" width="100" height="50" rx="0" style="fill: red" />"##
+        ));
+        assert!(svg.contains("A &lt;box&gt;"));
+        assert!(svg.contains(
+            r##"<g transform="translate(100 25)"><line class="wb-line" x1="0" y1="0" x2="100" y2="0" marker-end="url(#wb-arrow)" />"##
+        ));
+        assert!(svg.contains(r##"class="wb-portal"><a href="/other">Other</a></div>"##));
+    }
+
+    #[test]
+    fn unsafe_colors_are_ignored() {
+        assert_eq!(color(Some("#ff0000")), Some("#ff0000"));
+        assert_eq!(color(Some("var(--ls-color)")), None);
+        assert_eq!(color(Some("red\" onclick=\"x")), None);
+    }
+}